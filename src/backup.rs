@@ -0,0 +1,258 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+use crate::{
+    config::{Limits, SnapshotMeta},
+    crypto::SEAL_OVERHEAD,
+    error::{Result, StoreError},
+    store::{now_millis, write_manifest, BackupPin},
+    vfs::RealFs,
+};
+
+// Archive layout (all integers little endian):
+// header: [magic: 8][created_ms: u64][snapshot_number: u64][snapshot_seq: u64][last_seq: u64]
+//         [max_key_len: u64][max_val_len: u64][key_check: 40][file_count: u32][header_crc: u32]
+// file:   [kind: u8][len: u64][bytes: len][crc: u32]
+// key_check is the encrypted source dir's MANIFEST key check (all zeros if it isn't encrypted);
+// the files are copied as they are, so an encrypted dir's backup is encrypted too.
+const ARCHIVE_MAGIC: &[u8; 8] = b"KVSBAK03";
+const KIND_SNAPSHOT: u8 = 1;
+const KIND_LOG: u8 = 2;
+const KEY_CHECK_LEN: usize = SEAL_OVERHEAD;
+const HEADER_LEN: usize = 8 + 8 + 8 + 8 + 8 + 8 + 8 + KEY_CHECK_LEN + 4 + 4;
+
+#[derive(Debug, Clone)]
+pub struct BackupInfo {
+    pub created_ms: u64,
+    pub snapshot_number: u64,
+    pub snapshot_seq: u64,
+    // last sequence number contained in the backup (snapshot + log tail)
+    pub last_seq: u64,
+    pub bytes: u64,
+    // size limits of the data dir the backup was taken from
+    pub limits: Limits,
+    // the data dir was encrypted; restoring needs its key to open
+    pub encrypted: bool,
+}
+
+// Writes the pinned snapshot and log tail into a single archive at `archive_path`.
+// Safe to run off the store thread; the pin keeps the source files alive.
+pub fn write_backup(pin: BackupPin, archive_path: &Path) -> Result<BackupInfo> {
+    let tmp_path = archive_path.with_extension("tmp");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&tmp_path)?;
+    let mut writer = BufWriter::new(file);
+
+    let info = BackupInfo {
+        created_ms: now_millis(),
+        snapshot_number: pin.snapshot_number,
+        snapshot_seq: pin.snapshot_seq,
+        last_seq: pin.last_seq,
+        bytes: 0,
+        limits: pin.limits,
+        encrypted: pin.key_check.is_some(),
+    };
+
+    let file_count = if pin.snapshot.is_some() { 2 } else { 1 };
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(ARCHIVE_MAGIC);
+    header.extend_from_slice(&info.created_ms.to_le_bytes());
+    header.extend_from_slice(&info.snapshot_number.to_le_bytes());
+    header.extend_from_slice(&info.snapshot_seq.to_le_bytes());
+    header.extend_from_slice(&info.last_seq.to_le_bytes());
+    header.extend_from_slice(&(info.limits.max_key_len as u64).to_le_bytes());
+    header.extend_from_slice(&(info.limits.max_val_len as u64).to_le_bytes());
+    match &pin.key_check {
+        Some(key_check) if key_check.len() == KEY_CHECK_LEN => header.extend_from_slice(key_check),
+        Some(_) => return Err(StoreError::InvalidInput { msg: "unexpected key check length in MANIFEST".into() }),
+        None => header.extend_from_slice(&[0u8; KEY_CHECK_LEN]),
+    }
+    header.extend_from_slice(&(file_count as u32).to_le_bytes());
+    let header_crc = crc32fast::hash(&header);
+    header.extend_from_slice(&header_crc.to_le_bytes());
+    writer.write_all(&header)?;
+
+    let mut bytes = header.len() as u64;
+    if let Some(snapshot) = &pin.snapshot {
+        let len = pin.vfs.file_len(snapshot)?;
+        bytes += write_entry(&mut writer, KIND_SNAPSHOT, pin.vfs.open_read(snapshot)?, len)?;
+    }
+    bytes += write_entry(&mut writer, KIND_LOG, pin.vfs.open_read(&pin.log)?, pin.log_len)?;
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+
+    fs::rename(&tmp_path, archive_path)?;
+
+    Ok(BackupInfo { bytes, ..info })
+}
+
+fn write_entry<W: Write, R: Read>(w: &mut W, kind: u8, file: R, len: u64) -> Result<u64> {
+    w.write_all(&[kind])?;
+    w.write_all(&len.to_le_bytes())?;
+
+    let mut hasher = crc32fast::Hasher::new();
+    let mut src = BufReader::new(file).take(len);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        w.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    if copied != len {
+        return Err(StoreError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("pinned file shorter than expected: {copied} of {len} bytes"),
+        )));
+    }
+
+    w.write_all(&hasher.finalize().to_le_bytes())?;
+    Ok(1 + 8 + len + 4)
+}
+
+// Validates the archive at `archive_path` and unpacks it into the empty directory `dest_dir`.
+// On a checksum or format error nothing is left behind in `dest_dir`, nor `dest_dir` itself
+// if the restore created it.
+pub fn restore_from_archive(archive_path: &Path, dest_dir: &Path, log_name: &str) -> Result<BackupInfo> {
+    if dest_dir.exists() && fs::read_dir(dest_dir)?.next().is_some() {
+        return Err(StoreError::InvalidInput {
+            msg: format!("restore destination {} is not empty", dest_dir.display()),
+        });
+    }
+
+    let mut r = BufReader::new(File::open(archive_path)?);
+    let mut header = [0u8; HEADER_LEN];
+    r.read_exact(&mut header[..8]).map_err(|_| corrupt_archive("truncated header"))?;
+    if header[..8] != *ARCHIVE_MAGIC {
+        return Err(corrupt_archive("not a kvs backup archive"));
+    }
+    r.read_exact(&mut header[8..]).map_err(|_| corrupt_archive("truncated header"))?;
+
+    let stored_crc = u32::from_le_bytes(header[HEADER_LEN - 4..].try_into().unwrap());
+    if crc32fast::hash(&header[..HEADER_LEN - 4]) != stored_crc {
+        return Err(corrupt_archive("header checksum mismatch"));
+    }
+
+    let field = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+    let limits = Limits { max_key_len: field(40) as usize, max_val_len: field(48) as usize };
+    let key_check = Some(header[56..56 + KEY_CHECK_LEN].to_vec()).filter(|check| check.iter().any(|&b| b != 0));
+    let mut info = BackupInfo {
+        created_ms: field(8),
+        snapshot_number: field(16),
+        snapshot_seq: field(24),
+        last_seq: field(32),
+        bytes: HEADER_LEN as u64,
+        limits,
+        encrypted: key_check.is_some(),
+    };
+    let file_count = u32::from_le_bytes(header[HEADER_LEN - 8..HEADER_LEN - 4].try_into().unwrap());
+
+    let created_dest = !dest_dir.exists();
+    fs::create_dir_all(dest_dir)?;
+    let dest_dir = dest_dir.canonicalize()?;
+    let snapshot_path = dest_dir.join(format!("snapshot-{:04}.snap", info.snapshot_number));
+    let log_path = dest_dir.join(log_name);
+
+    let res = (|| {
+        let mut seen_log = false;
+        let mut seen_snapshot = false;
+        for _ in 0..file_count {
+            let mut kind = [0u8; 1];
+            r.read_exact(&mut kind).map_err(|_| corrupt_archive("truncated entry"))?;
+            let dest = match kind[0] {
+                KIND_SNAPSHOT => {
+                    seen_snapshot = true;
+                    &snapshot_path
+                }
+                KIND_LOG => {
+                    seen_log = true;
+                    &log_path
+                }
+                other => return Err(corrupt_archive(&format!("unknown entry kind {other}"))),
+            };
+            info.bytes += unpack_entry(&mut r, dest)?;
+        }
+
+        if !seen_log {
+            return Err(corrupt_archive("archive has no log entry"));
+        }
+        if r.read(&mut [0u8; 1])? != 0 {
+            return Err(corrupt_archive("trailing data after last entry"));
+        }
+
+        // a store that was never snapshotted is fully described by its log,
+        // unless it needs a MANIFEST for non-default limits or its key check
+        if !seen_snapshot && info.limits == Limits::default() && key_check.is_none() {
+            return Ok(());
+        }
+        write_manifest(&RealFs, &dest_dir.join("MANIFEST"), &SnapshotMeta {
+            snapshot_number: if seen_snapshot { info.snapshot_number } else { 0 },
+            snapshot_path: snapshot_path.clone(),
+            log_path: log_path.clone(),
+            last_seq: if seen_snapshot { info.snapshot_seq } else { 0 },
+            limits: info.limits,
+            key_check: key_check.clone(),
+        })
+    })();
+
+    if let Err(e) = res {
+        let _ = fs::remove_file(&snapshot_path);
+        let _ = fs::remove_file(&log_path);
+        let _ = fs::remove_file(dest_dir.join("MANIFEST"));
+        if created_dest {
+            let _ = fs::remove_dir(&dest_dir);
+        }
+        return Err(e);
+    }
+
+    Ok(info)
+}
+
+fn unpack_entry<R: Read>(r: &mut R, dest: &Path) -> Result<u64> {
+    let mut len = [0u8; 8];
+    r.read_exact(&mut len).map_err(|_| corrupt_archive("truncated entry"))?;
+    let len = u64::from_le_bytes(len);
+
+    let mut out = BufWriter::new(File::create(dest)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut src = r.take(len);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        out.write_all(&buf[..n])?;
+        copied += n as u64;
+    }
+    if copied != len {
+        return Err(corrupt_archive("truncated entry"));
+    }
+
+    let mut crc = [0u8; 4];
+    r.read_exact(&mut crc).map_err(|_| corrupt_archive("truncated entry"))?;
+    if hasher.finalize() != u32::from_le_bytes(crc) {
+        return Err(corrupt_archive(&format!("checksum mismatch for {}", dest.display())));
+    }
+
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    Ok(1 + 8 + len + 4)
+}
+
+fn corrupt_archive(msg: &str) -> StoreError {
+    StoreError::CorruptLog { msg: format!("invalid backup archive: {msg}") }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use crate::{
+    config::{Compression, Limits, SnapshotMeta},
+    crypto::EncryptionKey,
+    error::{Result, StoreError},
+    sst::SstWriter,
+    store::{base_dir_for, validate_kv, write_manifest},
+    vfs::{RealFs, Vfs},
+};
+
+// Builds a fresh data dir straight from a stream of pairs: they're sorted in memory and
+// written to `snapshot-0001.snap` in the sorted format on finish, without touching the log
+// or the store's index. Input order doesn't matter; if a key repeats, the later pair wins.
+pub struct BulkLoader {
+    base_dir: PathBuf,
+    log_path: PathBuf,
+    snapshot_path: PathBuf,
+    tmp_path: PathBuf,
+    file: File,
+    pairs: BTreeMap<Vec<u8>, Vec<u8>>,
+    entries: u64,
+    limits: Limits,
+    compression: Compression,
+    encryption: Option<EncryptionKey>,
+}
+
+impl BulkLoader {
+    // Fails unless the data dir for `log_path` has no MANIFEST and no log data yet
+    pub fn create(log_path: impl AsRef<Path>) -> Result<Self> {
+        Self::create_with_limits(log_path, Limits::default())
+    }
+
+    // Like `create`, for a data dir that takes keys/values up to `limits`
+    pub fn create_with_limits(log_path: impl AsRef<Path>, limits: Limits) -> Result<Self> {
+        let log_path = log_path.as_ref();
+        if let Some(parent) = log_path.parent()
+            && !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let base_dir = base_dir_for(log_path);
+        let log_path = base_dir.join(log_path.file_name().unwrap_or_else(|| "data.log".as_ref()));
+        if base_dir.join("MANIFEST").exists()
+            || fs::metadata(&log_path).is_ok_and(|m| m.len() > 0) {
+            return Err(StoreError::InvalidInput {
+                msg: format!("bulk load needs a fresh data dir, but {} already has data", base_dir.display()),
+            });
+        }
+
+        let snapshot_path = base_dir.join(format!("snapshot-{:04}.snap", 1));
+        let tmp_path = snapshot_path.with_extension("tmp");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+
+        Ok(BulkLoader {
+            base_dir,
+            log_path,
+            snapshot_path,
+            tmp_path,
+            file,
+            pairs: BTreeMap::new(),
+            entries: 0,
+            limits,
+            compression: Compression::None,
+            encryption: None,
+        })
+    }
+
+    // Compresses the values written to the snapshot
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // Creates an encrypted data dir, which only opens with `key`
+    pub fn with_encryption(mut self, key: EncryptionKey) -> Self {
+        self.encryption = Some(key);
+        self
+    }
+
+    pub fn add(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        validate_kv(key, Some(val), &self.limits)?;
+        self.pairs.insert(key.to_vec(), val.to_vec());
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Number of pairs added so far, duplicates included
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    // Seals the snapshot and points a new MANIFEST at it with an empty log
+    pub fn finish(mut self) -> Result<SnapshotMeta> {
+        let mut sst = SstWriter::new(BufWriter::new(self.file.try_clone()?), self.compression, self.encryption.clone())?;
+        for (key, val) in std::mem::take(&mut self.pairs) {
+            sst.add(&key, &val)?;
+        }
+        let (mut writer, _, _) = sst.finish()?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&self.tmp_path, &self.snapshot_path)?;
+        RealFs.sync_dir(&self.base_dir)?;
+
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.log_path)?;
+        RealFs.sync_dir(&self.base_dir)?;
+
+        let meta = SnapshotMeta {
+            snapshot_number: 1,
+            snapshot_path: self.snapshot_path.clone(),
+            log_path: self.log_path.clone(),
+            last_seq: 0,
+            limits: self.limits,
+            key_check: self.encryption.as_ref().map(EncryptionKey::key_check),
+        };
+        write_manifest(&RealFs, &self.base_dir.join("MANIFEST"), &meta)?;
+        Ok(meta)
+    }
+}
+
+// an abandoned load leaves no half-written snapshot behind
+impl Drop for BulkLoader {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::config::Compression;
+
+// Which codec a stored value went through. Log records carry it in the top two bits of
+// the op byte, snapshot entries in the top two bits of the key length; 0 means stored as is.
+pub(crate) const FLAG_MASK: u8 = 0xC0;
+pub(crate) const FLAG_LZ4: u8 = 0x40;
+pub(crate) const FLAG_ZSTD: u8 = 0x80;
+
+// Compressed values are [raw_len: u32][codec output]. Returns None when compression is
+// off or wouldn't make the value any smaller, so incompressible values cost nothing.
+pub(crate) fn compress(compression: Compression, val: &[u8]) -> Option<(u8, Vec<u8>)> {
+    // the raw length has to fit the u32 in front
+    if val.len() > u32::MAX as usize {
+        return None;
+    }
+    let (flag, body) = match compression {
+        Compression::None => return None,
+        Compression::Lz4 => (FLAG_LZ4, lz4_flex::block::compress(val)),
+        Compression::Zstd(level) => (FLAG_ZSTD, zstd::bulk::compress(val, level).ok()?),
+    };
+    if 4 + body.len() >= val.len() {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(4 + body.len());
+    out.extend_from_slice(&(val.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Some((flag, out))
+}
+
+// The raw length is checked against `max_len` before anything is allocated, so a damaged
+// length can't make us allocate gigabytes. Errors are messages for a CorruptLog.
+pub(crate) fn decompress(flag: u8, data: &[u8], max_len: usize) -> Result<Vec<u8>, String> {
+    let Some((len, body)) = data.split_first_chunk::<4>() else {
+        return Err("compressed value is too short".into());
+    };
+    let len = u32::from_le_bytes(*len) as usize;
+    if len > max_len {
+        return Err(format!("compressed value claims {len} bytes, limit is {max_len}"));
+    }
+
+    let val = match flag {
+        FLAG_LZ4 => {
+            let mut out = vec![0u8; len];
+            let n = lz4_flex::block::decompress_into(body, &mut out).map_err(|e| format!("lz4: {e}"))?;
+            out.truncate(n);
+            out
+        }
+        FLAG_ZSTD => zstd::bulk::decompress(body, len).map_err(|e| format!("zstd: {e}"))?,
+        other => return Err(format!("unknown compression flag {other:#x}")),
+    };
+    if val.len() != len {
+        return Err(format!("value decompressed to {} bytes, expected {len}", val.len()));
+    }
+    Ok(val)
+}
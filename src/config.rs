@@ -1,14 +1,136 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use crate::{crypto::EncryptionKey, vfs::{RealFs, Vfs}};
+
 #[derive(Debug, Clone)]
 pub enum Durability {
     Flush,
     FsyncAlways,
     FsyncEveryN(u64),
+    // flush every write, fsync in the background at most this long after it
+    FsyncInterval(Duration),
+    // fsync after N writes or after the interval, whichever comes first
+    FsyncEveryNOrInterval(u64, Duration),
+}
+
+impl Durability {
+    pub fn sync_interval(&self) -> Option<Duration> {
+        match self {
+            Durability::FsyncInterval(interval) | Durability::FsyncEveryNOrInterval(_, interval) => Some(*interval),
+            _ => None,
+        }
+    }
+}
+
+// What Store::open does when it finds a corrupt record (not a torn tail)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    // fail the open with CorruptLog
+    #[default]
+    Strict,
+    // skip ahead to the next valid record, then write a clean snapshot and keep the damaged files aside
+    Salvage,
+}
+
+// Per-write options; the defaults follow the store's Durability
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    // fsync before returning, whatever the durability mode
+    pub sync: bool,
+}
+
+impl WriteOptions {
+    pub fn sync() -> Self {
+        Self { sync: true }
+    }
+}
+
+// Largest key and value a data dir accepts.
+// The limits a dir has been written under are kept in its MANIFEST, so replay checks
+// records against those rather than whatever the current process was started with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_key_len: usize,
+    pub max_val_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_key_len: 1024,
+            max_val_len: 1024 * 1024, // 1 MiB
+        }
+    }
+}
+
+impl Limits {
+    // The larger of each limit; what a reader needs to accept both sets of records
+    pub fn max(self, other: Limits) -> Limits {
+        Limits {
+            max_key_len: self.max_key_len.max(other.max_key_len),
+            max_val_len: self.max_val_len.max(other.max_val_len),
+        }
+    }
+}
+
+// Codec for values written from now on. Every record says how it was written, so a data dir
+// can mix codecs and switching (or turning compression off) needs no migration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    // zstd with a level, 1 (fastest) to 22 (smallest)
+    Zstd(i32),
+}
+
+// Which entries go first when the store is over StoreOptions::max_memory. Reads are gets;
+// scans don't count, or one scan would make everything look recently used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    // least recently read or written
+    #[default]
+    Lru,
+    // fewest reads and writes since open, the least recent first among equals
+    Lfu,
+    Random,
+    // oldest write first, whatever has been read since. This is "TTL-first" for a cache with
+    // one uniform TTL: there are no per-key TTLs, but that entry is the one nearest expiry
+    OldestWrite,
+}
+
+// A namespace's own settings (see Store::namespace); None keeps the store's
+#[derive(Debug, Clone, Default)]
+pub struct NamespaceOptions {
+    pub compression: Option<Compression>,
+    pub max_memory: Option<u64>,
+    pub eviction: Option<EvictionPolicy>,
 }
 
 #[derive(Debug, Clone)]
 pub struct StoreOptions {
     pub durability: Durability,
     pub max_log_size: Option<u64>,
+    // grow the log this many bytes at a time, reserved up front, so appends don't change the
+    // file size (and fsyncs don't have metadata to write)
+    pub preallocate: Option<u64>,
+    // keep superseded snapshots and rotated logs in `archive/` for point-in-time restore
+    pub retain_history: bool,
+    pub recovery: RecoveryPolicy,
+    // None keeps the data dir's limits (the defaults for a new dir)
+    pub max_key_len: Option<usize>,
+    pub max_val_len: Option<usize>,
+    pub compression: Compression,
+    // encrypt logs and snapshots at rest; an encrypted data dir only opens with its key
+    pub encryption: Option<EncryptionKey>,
+    // where the data dir's files live; the real filesystem unless a test says otherwise
+    pub vfs: Arc<dyn Vfs>,
+    // key and value bytes the index may hold; past that, entries are deleted (tombstones and
+    // all, like any delete) until it fits again. For using the store as a cache.
+    pub max_memory: Option<u64>,
+    pub eviction: EvictionPolicy,
+    // settings for particular namespaces, by name ("default" for the default one); the
+    // store's compression, max_memory and eviction apply to each namespace separately
+    pub namespaces: HashMap<String, NamespaceOptions>,
 }
 
 impl Default for StoreOptions {
@@ -16,13 +138,73 @@ impl Default for StoreOptions {
         Self { 
             durability: Durability::Flush,
             max_log_size: None,
+            preallocate: None,
+            retain_history: false,
+            recovery: RecoveryPolicy::Strict,
+            max_key_len: None,
+            max_val_len: None,
+            compression: Compression::None,
+            encryption: None,
+            vfs: Arc::new(RealFs),
+            max_memory: None,
+            eviction: EvictionPolicy::Lru,
+            namespaces: HashMap::new(),
         }
     }
 }
 
+// Settings for the TCP server (see server::run_server_with)
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    // where BACKUP writes archives; clients name a file inside it. None refuses BACKUP.
+    pub backup_dir: Option<std::path::PathBuf>,
+}
+
 #[derive(Debug, Clone)]
 pub struct SnapshotMeta {
     pub snapshot_number: u64,
     pub snapshot_path: std::path::PathBuf,
     pub log_path: std::path::PathBuf,
-}
\ No newline at end of file
+    // last sequence number included in the snapshot
+    pub last_seq: u64,
+    // limits the data dir has been written under
+    pub limits: Limits,
+    // set in an encrypted data dir; only its key opens it
+    pub key_check: Option<Vec<u8>>,
+}
+// Counters since the store was opened
+#[derive(Debug, Clone, Default)]
+pub struct StoreStats {
+    // records appended to the log
+    pub writes: u64,
+    // times appended records were flushed out together (one per set/del, one per batch)
+    pub commits: u64,
+    // fsyncs of the log
+    pub syncs: u64,
+    // value bytes written to the log and snapshots, before and after compression
+    pub value_bytes: u64,
+    pub stored_value_bytes: u64,
+    // writes that failed and were rolled back out of the log
+    pub failed_writes: u64,
+    // automatic (--max-log-size) snapshots that failed after the write that triggered them
+    pub failed_snapshots: u64,
+    // evictions (--max-memory) that failed after the write that triggered them
+    pub failed_evictions: u64,
+    // refusing writes after a failed one (see Store::read_only_cause)
+    pub read_only: bool,
+    // key and value bytes in the index right now
+    pub memory_bytes: u64,
+    // entries deleted to stay under max_memory, and their key and value bytes
+    pub evictions: u64,
+    pub evicted_bytes: u64,
+}
+
+impl StoreStats {
+    // How many times smaller values got on disk; 1.0 with compression off
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_value_bytes == 0 {
+            return 1.0;
+        }
+        self.value_bytes as f64 / self.stored_value_bytes as f64
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fmt, path::Path};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use crate::error::{Result, StoreError};
+
+// Sealed data is [nonce: 24][ciphertext][tag: 16]
+const NONCE_LEN: usize = 24;
+pub(crate) const SEAL_OVERHEAD: usize = NONCE_LEN + 16;
+
+// Associated data says what a sealed blob is and where it sits, so a record or block can't
+// be passed off as another one or moved around the file without failing authentication
+pub(crate) const AAD_LOG: &[u8] = b"kvs log record";
+pub(crate) const AAD_SNAPSHOT: &[u8] = b"kvs snapshot";
+const AAD_KEY_CHECK: &[u8] = b"kvs key check";
+
+// Key for encryption at rest: XChaCha20-Poly1305 with a 256-bit key. Nonces are random,
+// which their 192 bits make safe to do for as many records as a store will ever write.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    cipher: XChaCha20Poly1305,
+}
+
+// never print key material
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl EncryptionKey {
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Self { cipher: XChaCha20Poly1305::new(bytes.into()) }
+    }
+
+    // 64 hex digits (e.g. from `openssl rand -hex 32`); surrounding whitespace is ignored
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        let invalid = || StoreError::Encryption { msg: "a key must be 64 hex digits (32 bytes)".into() };
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self::from_bytes(&bytes))
+    }
+
+    // A file holding the key as hex
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let hex = std::fs::read_to_string(path).map_err(|e| StoreError::Encryption {
+            msg: format!("can't read key file {}: {e}", path.display()),
+        })?;
+        Self::from_hex(&hex)
+    }
+
+    // An environment variable holding the key as hex
+    pub fn from_env(var: &str) -> Result<Self> {
+        let hex = std::env::var(var).map_err(|e| StoreError::Encryption {
+            msg: format!("can't read key from ${var}: {e}"),
+        })?;
+        Self::from_hex(&hex)
+    }
+
+    pub(crate) fn seal(&self, aad: &[u8], offset: u64, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = [aad, &offset.to_le_bytes()].concat();
+        let ciphertext = self.cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .expect("XChaCha20-Poly1305 takes any message length a store writes");
+
+        let mut sealed = Vec::with_capacity(SEAL_OVERHEAD + plaintext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    // None if `sealed` wasn't sealed by this key with the same `aad` and `offset`
+    pub(crate) fn open(&self, aad: &[u8], offset: u64, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = [aad, &offset.to_le_bytes()].concat();
+        self.cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad }).ok()
+    }
+
+    // Kept in the MANIFEST so opening with the wrong key fails up front, with a clear error,
+    // instead of as a corrupt record somewhere in the log
+    pub(crate) fn key_check(&self) -> Vec<u8> {
+        self.seal(AAD_KEY_CHECK, 0, &[])
+    }
+
+    pub(crate) fn matches(&self, key_check: &[u8]) -> bool {
+        self.open(AAD_KEY_CHECK, 0, key_check).is_some()
+    }
+}
@@ -15,6 +15,12 @@ pub enum StoreError {
 
     // Actor/channel Errors
     StoreClosed { msg: String },
+
+    // Missing or wrong encryption key, or an encrypted dir opened without one
+    Encryption { msg: String },
+
+    // A write failed (disk full, I/O error) and writes are refused until one gets through again
+    ReadOnly { msg: String },
 }
 
 impl fmt::Display for StoreError {
@@ -24,6 +30,8 @@ impl fmt::Display for StoreError {
             StoreError::CorruptLog { msg } => write!(f, "corrupt log: {msg}"),
             StoreError::InvalidInput { msg } => write!(f, "invalid input: {msg}"),
             StoreError::StoreClosed { msg} => write!(f, "store closed: {msg}"),
+            StoreError::Encryption { msg } => write!(f, "encryption: {msg}"),
+            StoreError::ReadOnly { msg } => write!(f, "read-only: {msg}"),
         }
     }
 }
@@ -48,6 +56,26 @@ impl StoreError {
     pub fn is_corrupt_log(&self) -> bool {
         matches!(self, StoreError::CorruptLog { .. })
     }
+
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, StoreError::ReadOnly { .. })
+    }
+
+    pub fn is_unexpected_eof(&self) -> bool {
+        matches!(self, StoreError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+    }
+
+    // io::Error isn't Clone; used when one failure has to be reported to several callers
+    pub(crate) fn duplicate(&self) -> StoreError {
+        match self {
+            StoreError::Io(e) => StoreError::Io(io::Error::new(e.kind(), e.to_string())),
+            StoreError::CorruptLog { msg } => StoreError::CorruptLog { msg: msg.clone() },
+            StoreError::InvalidInput { msg } => StoreError::InvalidInput { msg: msg.clone() },
+            StoreError::StoreClosed { msg } => StoreError::StoreClosed { msg: msg.clone() },
+            StoreError::Encryption { msg } => StoreError::Encryption { msg: msg.clone() },
+            StoreError::ReadOnly { msg } => StoreError::ReadOnly { msg: msg.clone() },
+        }
+    }
 }
 
 
@@ -0,0 +1,94 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasher, RandomState},
+    sync::Arc,
+};
+
+use crate::config::EvictionPolicy;
+
+// Where an entry stands in line for eviction: the policy's part, then the tick it was last
+// ranked at, which keeps ranks unique and breaks ties by recency
+type Rank = (u64, u64);
+
+// Keeps the index's keys in the order the policy would evict them, so the next victim is
+// always the first one. Only exists when the store has a memory budget.
+pub(crate) struct Evictor {
+    policy: EvictionPolicy,
+    clock: u64,
+    rng: u64,
+    ranks: HashMap<Arc<[u8]>, Usage>,
+    order: BTreeMap<Rank, Arc<[u8]>>,
+}
+
+struct Usage {
+    rank: Rank,
+    uses: u64,
+}
+
+impl Evictor {
+    // No access history survives a restart, so whatever `keys` holds starts out equal
+    pub(crate) fn new<'a>(policy: EvictionPolicy, keys: impl Iterator<Item = &'a Vec<u8>>) -> Self {
+        let mut evictor = Evictor {
+            policy,
+            clock: 0,
+            // any odd seed will do for xorshift
+            rng: RandomState::new().hash_one(0u64) | 1,
+            ranks: HashMap::new(),
+            order: BTreeMap::new(),
+        };
+        for key in keys {
+            evictor.written(key);
+        }
+        evictor
+    }
+
+    pub(crate) fn written(&mut self, key: &[u8]) {
+        let fresh = !self.ranks.contains_key(key);
+        // a random rank is drawn once, and an overwrite doesn't move it
+        if fresh || self.policy != EvictionPolicy::Random {
+            self.bump(key);
+        }
+    }
+
+    pub(crate) fn read(&mut self, key: &[u8]) {
+        if matches!(self.policy, EvictionPolicy::Lru | EvictionPolicy::Lfu) && self.ranks.contains_key(key) {
+            self.bump(key);
+        }
+    }
+
+    pub(crate) fn removed(&mut self, key: &[u8]) {
+        if let Some(usage) = self.ranks.remove(key) {
+            self.order.remove(&usage.rank);
+        }
+    }
+
+    // Every key, in the order the policy would evict them
+    pub(crate) fn victims(&self) -> impl Iterator<Item = &[u8]> {
+        self.order.values().map(|key| &**key)
+    }
+
+    fn bump(&mut self, key: &[u8]) {
+        self.clock += 1;
+        let (key, uses) = match self.ranks.remove_entry(key) {
+            Some((key, usage)) => {
+                self.order.remove(&usage.rank);
+                (key, usage.uses + 1)
+            }
+            None => (Arc::from(key), 1),
+        };
+        let rank = match self.policy {
+            EvictionPolicy::Lru | EvictionPolicy::OldestWrite => (0, self.clock),
+            EvictionPolicy::Lfu => (uses, self.clock),
+            EvictionPolicy::Random => (self.next_random(), self.clock),
+        };
+        self.order.insert(rank, Arc::clone(&key));
+        self.ranks.insert(key, Usage { rank, uses });
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
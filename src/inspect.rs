@@ -0,0 +1,228 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use crate::{
+    config::Limits,
+    crypto::EncryptionKey,
+    error::{Result, StoreError},
+    store::{base_dir_for, is_preallocated_log, read_manifest, read_record, read_snapshot_entry},
+    sst::{is_sorted_snapshot, SstReader},
+    vfs::RealFs,
+};
+
+const PREVIEW_LEN: usize = 32;
+
+// One decoded log record or snapshot entry
+#[derive(Debug, Clone)]
+pub struct InspectEntry {
+    pub offset: u64,
+    // raw op code and its name; None for snapshot entries
+    pub op: Option<(u8, &'static str)>,
+    pub seq: Option<u64>,
+    pub timestamp_ms: Option<u64>,
+    // None for the default namespace
+    pub namespace: Option<String>,
+    pub key: Vec<u8>,
+    // None for tombstones
+    pub value_len: Option<usize>,
+    pub value_preview: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InspectSummary {
+    pub entries: u64,
+    // (offset, length) of a torn record at the end of a log
+    pub torn: Option<(u64, u64)>,
+}
+
+impl InspectEntry {
+    pub fn to_text(&self) -> String {
+        let mut out = format!("offset={}", self.offset);
+        match self.op {
+            Some((code, name)) => { let _ = write!(out, " op={name}({code})"); }
+            None => out.push_str(" op=ENTRY"),
+        }
+        if let Some(seq) = self.seq {
+            let _ = write!(out, " seq={seq}");
+        }
+        if let Some(ts) = self.timestamp_ms {
+            let _ = write!(out, " ts={ts}");
+        }
+        if let Some(ns) = &self.namespace {
+            let _ = write!(out, " ns={ns}");
+        }
+        let _ = write!(out, " key=\"{}\"", self.key.escape_ascii());
+        if let (Some(len), Some(preview)) = (self.value_len, &self.value_preview) {
+            let _ = write!(out, " val_len={len} val=\"{}\"{}", preview.escape_ascii(), ellipsis(len));
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\"offset\":{}", self.offset);
+        match self.op {
+            Some((code, name)) => { let _ = write!(out, ",\"op\":\"{name}\",\"op_code\":{code}"); }
+            None => out.push_str(",\"op\":\"ENTRY\""),
+        }
+        if let Some(seq) = self.seq {
+            let _ = write!(out, ",\"seq\":{seq}");
+        }
+        if let Some(ts) = self.timestamp_ms {
+            let _ = write!(out, ",\"ts\":{ts}");
+        }
+        if let Some(ns) = &self.namespace {
+            let _ = write!(out, ",\"ns\":{}", json_string(ns));
+        }
+        let _ = write!(out, ",\"key\":{}", json_string(&self.key.escape_ascii().to_string()));
+        if let (Some(len), Some(preview)) = (self.value_len, &self.value_preview) {
+            let _ = write!(
+                out, ",\"val_len\":{len},\"val_preview\":{}",
+                json_string(&format!("{}{}", preview.escape_ascii(), ellipsis(len)))
+            );
+        }
+        out.push('}');
+        out
+    }
+}
+
+fn ellipsis(len: usize) -> &'static str {
+    if len > PREVIEW_LEN { "..." } else { "" }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// The log the store would open for `log_path`, following the MANIFEST
+pub fn current_log_path(log_path: &Path) -> Result<PathBuf> {
+    let manifest = read_manifest(&RealFs, &base_dir_for(log_path).join("MANIFEST"))?;
+    Ok(manifest.map(|meta| meta.log_path).unwrap_or_else(|| log_path.to_path_buf()))
+}
+
+// The size limits the data dir owning `log_path` has been written under
+pub fn data_dir_limits(log_path: &Path) -> Result<Limits> {
+    let manifest = read_manifest(&RealFs, &base_dir_for(log_path).join("MANIFEST"))?;
+    Ok(manifest.map(|meta| meta.limits).unwrap_or_default())
+}
+
+// Decodes `path` record by record from `from_offset`, calling `f` for each one.
+// Files ending in `.snap` are read as snapshots, anything else as a log. No index is built.
+// Files of an encrypted data dir need its `key`.
+pub fn inspect_file(
+    path: &Path,
+    from_offset: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    mut f: impl FnMut(&InspectEntry) -> Result<()>,
+) -> Result<InspectSummary> {
+    let is_snapshot = path.extension().is_some_and(|ext| ext == "snap");
+    if is_snapshot && is_sorted_snapshot(&RealFs, path)? {
+        return inspect_sorted_snapshot(path, from_offset, limits, key, f);
+    }
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut r = BufReader::new(file);
+    let preallocated = !is_snapshot && is_preallocated_log(&mut r)?;
+    r.seek(SeekFrom::Start(from_offset))?;
+
+    let mut summary = InspectSummary::default();
+    loop {
+        let offset = r.stream_position()?;
+        let decoded = if is_snapshot {
+            read_snapshot_entry(&mut r, offset, limits, key).map(|entry| entry.map(|(key, val)| InspectEntry {
+                offset,
+                op: None,
+                seq: None,
+                timestamp_ms: None,
+                namespace: None,
+                key,
+                value_len: Some(val.len()),
+                value_preview: Some(preview(&val)),
+            }))
+        } else {
+            read_record(&mut r, offset, limits, key, preallocated).map(|record| record.map(|record| InspectEntry {
+                offset,
+                op: Some((record.op, record.op_name())),
+                seq: record.seq,
+                timestamp_ms: record.timestamp_ms,
+                namespace: Some(record.namespace).filter(|ns| !ns.is_empty()),
+                value_len: record.value.as_ref().map(|v| v.len()),
+                value_preview: record.value.as_deref().map(preview),
+                key: record.key,
+            }))
+        };
+
+        match decoded {
+            Ok(Some(entry)) => {
+                summary.entries += 1;
+                f(&entry)?;
+            }
+            Ok(None) => {
+                // a preallocated log only ends early at its zeros, which aren't a torn record
+                if offset < len && !preallocated {
+                    summary.torn = Some((offset, len - offset));
+                }
+                break;
+            }
+            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                summary.torn = Some((offset, len - offset));
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(summary)
+}
+
+// Entries of a sorted snapshot carry the offset of the block they're in; `from_offset`
+// skips the blocks before it
+fn inspect_sorted_snapshot(
+    path: &Path,
+    from_offset: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    mut f: impl FnMut(&InspectEntry) -> Result<()>,
+) -> Result<InspectSummary> {
+    let mut reader = SstReader::open_with_key(path, limits, key)?;
+    let mut summary = InspectSummary::default();
+
+    for i in 0..reader.blocks() {
+        let (offset, len) = reader.block_span(i);
+        if offset + len <= from_offset {
+            continue;
+        }
+        for (key, val) in reader.read_block(i)? {
+            summary.entries += 1;
+            let (namespace, key) = reader.split_key(&key);
+            f(&InspectEntry {
+                offset,
+                op: None,
+                seq: None,
+                timestamp_ms: None,
+                namespace: Some(namespace.to_string()).filter(|ns| !ns.is_empty()),
+                key: key.to_vec(),
+                value_len: Some(val.len()),
+                value_preview: Some(preview(&val)),
+            })?;
+        }
+    }
+    Ok(summary)
+}
+
+fn preview(val: &[u8]) -> Vec<u8> {
+    val[..val.len().min(PREVIEW_LEN)].to_vec()
+}
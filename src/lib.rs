@@ -1,4 +1,17 @@
 pub mod error;
 pub mod store;
 pub mod config;
-pub mod server;
\ No newline at end of file
+pub mod server;
+pub mod restore;
+pub mod backup;
+pub mod verify;
+pub mod inspect;
+pub mod transfer;
+pub mod bulk;
+pub mod sst;
+pub mod crypto;
+pub mod vfs;
+pub mod mmap;
+mod sync;
+mod compress;
+mod evict;
@@ -1,12 +1,20 @@
 use clap::{Parser, Subcommand};
-use kvs::config::{Durability, StoreOptions};
+use kvs::config::{Compression, Durability, EvictionPolicy, Limits, RecoveryPolicy, ServerOptions, StoreOptions};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
-use kvs::store::Store;
-use kvs::error::Result;
+use kvs::store::{Namespace, Store, DEFAULT_NAMESPACE};
+use kvs::crypto::EncryptionKey;
+use kvs::error::{Result, StoreError};
 use kvs::server::{StoreActor, StoreHandle};
+use kvs::restore::{restore_to_point_with_key, RestoreTarget};
+use kvs::backup::{restore_from_archive, write_backup};
+use kvs::verify::verify_data_dir_with_key;
+use kvs::inspect::{current_log_path, data_dir_limits, inspect_file};
+use kvs::transfer::{export, import, read_rows, DumpFormat};
+use kvs::bulk::BulkLoader;
 
 #[derive(Parser, Debug)]
 #[command(name = "kvs", version, about = "Tiny persistent key-value store")]
@@ -21,6 +29,36 @@ struct Cli {
     #[arg(long, value_parser = parse_max_log_size, help = "Maximum log file size before auto-snapshot (e.g., '10MB', '1GB', '1048576')")]
     max_log_size: Option<u64>,
 
+    #[arg(long, value_parser = parse_max_log_size, help = "Preallocate the log this much at a time (e.g., '64MB'), so appends don't grow the file")]
+    preallocate: Option<u64>,
+
+    #[arg(long, help = "Keep superseded snapshots and rotated logs in archive/ for point-in-time restore")]
+    retain_history: bool,
+
+    #[arg(long, default_value = "strict", value_parser = parse_recovery, help = "What to do with corrupt records on open: 'strict' fails, 'salvage' skips them")]
+    recovery: RecoveryPolicy,
+
+    #[arg(long, value_parser = parse_max_log_size, help = "Largest key accepted (e.g., '4KB'); defaults to the data dir's limit, 1KB for a new one")]
+    max_key_len: Option<u64>,
+
+    #[arg(long, value_parser = parse_max_log_size, help = "Largest value accepted (e.g., '16MB'); defaults to the data dir's limit, 1MB for a new one")]
+    max_value_len: Option<u64>,
+
+    #[arg(long, default_value = "none", value_parser = parse_compression, help = "Compress values written from now on: 'none', 'lz4', 'zstd' or 'zstd:<level>'")]
+    compression: Compression,
+
+    #[arg(long, value_parser = parse_max_log_size, help = "Evict entries once keys and values take up more than this (e.g., '512MB')")]
+    max_memory: Option<u64>,
+
+    #[arg(long, default_value = "lru", value_parser = parse_eviction, help = "Which entries --max-memory evicts first: 'lru', 'lfu', 'random' or 'oldest-write'")]
+    eviction: EvictionPolicy,
+
+    #[arg(long, help = "Namespace set, get, del, scan, export and import work in, and server connections start in (default: 'default')")]
+    ns: Option<String>,
+
+    #[arg(long, help = "File holding the data dir's encryption key as 64 hex digits; defaults to $KVS_KEY, if set")]
+    key_file: Option<PathBuf>,
+
     #[command(subcommand)]
     cmd: Command,
 }
@@ -29,6 +67,18 @@ fn parse_durability(s: &str) -> std::result::Result<Durability, String> {
     match s {
         "flush" => Ok(Durability::Flush),
         "fsync-always" => Ok(Durability::FsyncAlways),
+        s if s.starts_with("fsync-every-n-or-interval:") => {
+            // fsync-every-n-or-interval:<number>:<duration>
+            let (n, interval) = s.strip_prefix("fsync-every-n-or-interval:")
+                .unwrap()
+                .split_once(':')
+                .ok_or("expected fsync-every-n-or-interval:<number>:<duration>")?;
+            let n = n.parse::<u64>().map_err(|e| format!("invalid number: {e}"))?;
+            if n == 0 {
+                return Err("fsync-every-n-or-interval count must be greater than 0".to_string());
+            }
+            Ok(Durability::FsyncEveryNOrInterval(n, parse_interval(interval)?))
+        }
         s if s.starts_with("fsync-every-n:") => {
             let n = s.strip_prefix("fsync-every-n:")
                 .unwrap()
@@ -39,7 +89,89 @@ fn parse_durability(s: &str) -> std::result::Result<Durability, String> {
             }
             Ok(Durability::FsyncEveryN(n))
         }
-        _ => Err(format!("invalid durability mode: {s}. Use 'flush', 'fsync-always', or 'fsync-every-n:<number>'"))
+        s if s.starts_with("fsync-interval:") => {
+            Ok(Durability::FsyncInterval(parse_interval(s.strip_prefix("fsync-interval:").unwrap())?))
+        }
+        _ => Err(format!(
+            "invalid durability mode: {s}. Use 'flush', 'fsync-always', 'fsync-every-n:<number>', \
+             'fsync-interval:<duration>' or 'fsync-every-n-or-interval:<number>:<duration>'"
+        ))
+    }
+}
+
+// "100ms", "2s" or "1m"
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    let (num, unit_ms) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 1)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1000)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60_000)
+    } else {
+        return Err(format!("invalid interval: {s}. Use a number with ms, s or m, e.g. 100ms"));
+    };
+    let n = num.parse::<u64>().map_err(|e| format!("invalid interval {s}: {e}"))?;
+    if n == 0 {
+        return Err("fsync interval must be greater than 0".to_string());
+    }
+    Ok(Duration::from_millis(n * unit_ms))
+}
+
+fn parse_format(s: &str) -> std::result::Result<DumpFormat, String> {
+    match s {
+        "jsonl" => Ok(DumpFormat::Jsonl),
+        "csv" => Ok(DumpFormat::Csv),
+        _ => Err(format!("invalid format: {s}. Use 'jsonl' or 'csv'")),
+    }
+}
+
+fn parse_compression(s: &str) -> std::result::Result<Compression, String> {
+    match s {
+        "none" => Ok(Compression::None),
+        "lz4" => Ok(Compression::Lz4),
+        "zstd" => Ok(Compression::Zstd(3)),
+        s if s.starts_with("zstd:") => {
+            let level = s.strip_prefix("zstd:")
+                .unwrap()
+                .parse::<i32>()
+                .map_err(|e| format!("invalid zstd level: {e}"))?;
+            if !(1..=22).contains(&level) {
+                return Err("zstd level must be between 1 and 22".to_string());
+            }
+            Ok(Compression::Zstd(level))
+        }
+        _ => Err(format!("invalid compression: {s}. Use 'none', 'lz4', 'zstd' or 'zstd:<level>'")),
+    }
+}
+
+fn parse_recovery(s: &str) -> std::result::Result<RecoveryPolicy, String> {
+    match s {
+        "strict" => Ok(RecoveryPolicy::Strict),
+        "salvage" => Ok(RecoveryPolicy::Salvage),
+        _ => Err(format!("invalid recovery policy: {s}. Use 'strict' or 'salvage'")),
+    }
+}
+
+fn parse_eviction(s: &str) -> std::result::Result<EvictionPolicy, String> {
+    match s {
+        "lru" => Ok(EvictionPolicy::Lru),
+        "lfu" => Ok(EvictionPolicy::Lfu),
+        "random" => Ok(EvictionPolicy::Random),
+        "oldest-write" => Ok(EvictionPolicy::OldestWrite),
+        _ => Err(format!("invalid eviction policy: {s}. Use 'lru', 'lfu', 'random' or 'oldest-write'")),
+    }
+}
+
+// unix seconds, or unix millis with an "ms" suffix
+fn parse_timestamp(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse::<u64>()
+            .map_err(|e| format!("invalid timestamp: {e}"))
+    } else {
+        s.parse::<u64>()
+            .map(|secs| secs * 1000)
+            .map_err(|e| format!("invalid timestamp: {e}. Use unix seconds like '1760000000' or millis like '1760000000123ms'"))
     }
 }
 
@@ -74,15 +206,76 @@ enum Command {
     Server {
         #[arg(long, default_value = "127.0.0.1:8080")]
         addr: String,
+        // Directory BACKUP writes archives into; without it, BACKUP is refused
+        #[arg(long)]
+        backup_dir: Option<PathBuf>,
     },
     Snapshot,
+    // Check MANIFEST, snapshot and log for problems without modifying anything
+    Verify,
+    // Write the keyspace to stdout as JSON Lines or CSV
+    Export {
+        #[arg(long, default_value = "jsonl", value_parser = parse_format)]
+        format: DumpFormat,
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+    // Load pairs written by `export` from a file (or stdin), in batched writes
+    Import {
+        file: Option<PathBuf>,
+        #[arg(long, default_value = "jsonl", value_parser = parse_format)]
+        format: DumpFormat,
+        // Writes per batch; each batch is flushed/fsynced once according to --durability
+        #[arg(long, default_value_t = 1000)]
+        batch_size: usize,
+    },
+    // Build a fresh data dir from an export by writing its snapshot directly
+    BulkLoad {
+        file: Option<PathBuf>,
+        #[arg(long, default_value = "jsonl", value_parser = parse_format)]
+        format: DumpFormat,
+    },
+    // Decode a log or snapshot file record by record, without building the index
+    InspectLog {
+        // Log or snapshot (*.snap) file; defaults to the current log
+        file: Option<PathBuf>,
+        #[arg(long, default_value_t = 0)]
+        from_offset: u64,
+        #[arg(long)]
+        json: bool,
+    },
+    // Salvage every readable record into a clean snapshot, keeping the damaged files aside
+    Repair,
+    // Write a consistent snapshot + log tail into a single archive file
+    Backup { path: PathBuf },
+    // Rebuild the store as of a past sequence number or time, or from a backup archive, into a new data dir
+    #[command(group = clap::ArgGroup::new("target").required(true).args(["to_seq", "to_time", "from_archive"]))]
+    Restore {
+        #[arg(long)]
+        to_seq: Option<u64>,
+        #[arg(long, value_parser = parse_timestamp)]
+        to_time: Option<u64>,
+        #[arg(long)]
+        from_archive: Option<PathBuf>,
+        // Directory to write the restored data dir into (must be empty)
+        #[arg(long)]
+        dest: PathBuf,
+    },
+    // Re-encrypt the data dir under a new key, or decrypt it
+    #[command(group = clap::ArgGroup::new("new_key").required(true).args(["new_key_file", "decrypt"]))]
+    Rekey {
+        #[arg(long)]
+        new_key_file: Option<PathBuf>,
+        #[arg(long)]
+        decrypt: bool,
+    },
 }
 
 fn main() {
     if let Err(e) = run() {
         eprintln!("error: {e}");
         if e.is_corrupt_log() {
-            eprintln!("hint: your data.log appears corrupted (likely a torn write or format mismatch). You can move/delete the log file and try again.");
+            eprintln!("hint: your data.log appears corrupted (likely a torn write or format mismatch). Run `kvs verify` to see every problem with its file and offset, or `kvs repair` to salvage the readable records.");
         }
         // for debugging:
         if std::env::var_os("KVS_DEBUG").is_some() {
@@ -94,15 +287,147 @@ fn main() {
 
 fn run() -> Result<()> {
     let cli = Cli::parse();
+    let encryption = match &cli.key_file {
+        Some(path) => Some(EncryptionKey::from_file(path)?),
+        None if std::env::var_os("KVS_KEY").is_some() => Some(EncryptionKey::from_env("KVS_KEY")?),
+        None => None,
+    };
     let opts = StoreOptions {
         durability: cli.durability,
         max_log_size: cli.max_log_size,
+        preallocate: cli.preallocate.filter(|&n| n > 0),
+        retain_history: cli.retain_history,
+        recovery: cli.recovery,
+        max_key_len: cli.max_key_len.map(|n| n as usize),
+        max_val_len: cli.max_value_len.map(|n| n as usize),
+        compression: cli.compression,
+        encryption,
+        max_memory: cli.max_memory,
+        eviction: cli.eviction,
+        ..Default::default()
+    };
+    // for commands that read or write the files without opening the store
+    let flag_limits = Limits {
+        max_key_len: opts.max_key_len.unwrap_or(Limits::default().max_key_len),
+        max_val_len: opts.max_val_len.unwrap_or(Limits::default().max_val_len),
     };
 
+    if cli.ns.is_some()
+        && !matches!(cli.cmd, Command::Set { .. } | Command::Get { .. } | Command::Del { .. } | Command::Scan { .. }
+            | Command::Export { .. } | Command::Import { .. } | Command::Server { .. })
+    {
+        return Err(StoreError::InvalidInput { msg: "--ns only applies to set, get, del, scan, export, import and server".into() });
+    }
+
+    // commands that work on the files directly, without opening the store
+    let cmd = match cli.cmd {
+        Command::BulkLoad { file, format } => {
+            let mut loader = BulkLoader::create_with_limits(&cli.log, flag_limits)?
+                .with_compression(opts.compression);
+            if let Some(key) = opts.encryption {
+                loader = loader.with_encryption(key);
+            }
+            match file {
+                Some(path) => {
+                    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+                    read_rows(format, reader, |key, val| loader.add(&key, &val))?
+                }
+                None => read_rows(format, std::io::stdin().lock(), |key, val| loader.add(&key, &val))?,
+            };
+            let entries = loader.entries();
+            loader.finish()?;
+            println!("loaded {entries} pairs");
+            return Ok(());
+        }
+        Command::InspectLog { file, from_offset, json } => {
+            let path = match file {
+                Some(file) => file,
+                None => current_log_path(&cli.log)?,
+            };
+            let limits = data_dir_limits(&cli.log)?.max(flag_limits);
+            let summary = inspect_file(&path, from_offset, &limits, opts.encryption.as_ref(), |entry| {
+                println!("{}", if json { entry.to_json() } else { entry.to_text() });
+                Ok(())
+            })?;
+            if let Some((offset, len)) = summary.torn {
+                if json {
+                    println!("{{\"offset\":{offset},\"torn\":true,\"len\":{len}}}");
+                } else {
+                    println!("offset={offset} torn record ({len} bytes)");
+                }
+            }
+            return Ok(());
+        }
+        Command::Verify => {
+            let report = verify_data_dir_with_key(&cli.log, opts.encryption.as_ref())?;
+            for problem in &report.problems {
+                println!("{problem}");
+            }
+            for note in &report.notes {
+                println!("note: {note}");
+            }
+            if !report.is_ok() {
+                println!("{} problems found", report.problems.len());
+                std::process::exit(1);
+            }
+            println!(
+                "ok: {} snapshot entries, {} log records, last seq {}",
+                report.snapshot_entries, report.log_records, report.last_seq
+            );
+            return Ok(());
+        }
+        Command::Restore { from_archive: Some(archive), dest, .. } => {
+            let log_name = cli.log.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("data.log");
+            let info = restore_from_archive(&archive, &dest, log_name)?;
+            println!("restored backup up to seq {} into {}", info.last_seq, dest.display());
+            if info.encrypted {
+                println!("the backup is encrypted; open the restored data dir with the original key");
+            }
+            return Ok(());
+        }
+        Command::Restore { to_seq, to_time, dest, .. } => {
+            let target = match (to_seq, to_time) {
+                (Some(seq), _) => RestoreTarget::Seq(seq),
+                (None, Some(millis)) => RestoreTarget::Time(millis),
+                (None, None) => unreachable!("clap requires one of --to-seq/--to-time/--from-archive"),
+            };
+            let report = restore_to_point_with_key(&cli.log, &dest, target, opts.encryption.as_ref())?;
+            println!("restored {} keys up to seq {} into {}", report.keys, report.last_seq, report.log_path.display());
+            return Ok(());
+        }
+        cmd => cmd,
+    };
+
+    if let Command::Repair = cmd {
+        let store = Store::open(&cli.log, StoreOptions { recovery: RecoveryPolicy::Salvage, ..opts })?;
+        let report = store.salvage_report().expect("opened with RecoveryPolicy::Salvage");
+        if report.is_clean() {
+            println!("nothing to repair: {} records replayed", report.recovered_records);
+            return Ok(());
+        }
+        for (path, offset, len) in &report.skipped_regions {
+            println!("skipped {len} bytes at offset {offset} in {}", path.display());
+        }
+        println!(
+            "recovered {} log records, skipped {} bytes ({} records known lost)",
+            report.recovered_records, report.skipped_bytes, report.lost_records
+        );
+        for path in &report.preserved {
+            println!("original kept as {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let ns = cli.ns.as_deref().unwrap_or(DEFAULT_NAMESPACE);
     let mut store = Store::open(&cli.log, opts)?;
 
-    match cli.cmd {
-        Command::Server { addr } => {
+    match cmd {
+        Command::Server { addr, backup_dir } => {
+            if let Some(dir) = &backup_dir {
+                std::fs::create_dir_all(dir)?;
+            }
 
             // create channel
             let (sender, receiver) = mpsc::channel();
@@ -116,24 +441,24 @@ fn run() -> Result<()> {
             });
 
             // create handle
-            let handle = StoreHandle::new(sender);
+            let handle = StoreHandle::new(sender).in_namespace(ns)?;
 
             // Run server
             let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(kvs::server::run_server(&addr, handle))?;
+            rt.block_on(kvs::server::run_server_with(&addr, handle, ServerOptions { backup_dir }))?;
 
         }
         Command::Set { key, value } => {
-            store.set(key.as_bytes(), value.as_bytes())?;
+            store.namespace(ns)?.set(key.as_bytes(), value.as_bytes())?;
             println!("OK");
 
         }
         Command::Del { key } => {
-            let existed = store.del(key.as_bytes())?;
+            let existed = store.namespace(ns)?.del(key.as_bytes())?;
             println!("{}", if existed { 1 } else { 0 });
         }
         Command::Get { key } => {
-            match store.get(key.as_bytes()) {
+            match store.namespace(ns)?.get(key.as_bytes()) {
                 Some(bytes) => {
                     match std::str::from_utf8(bytes) {
                         Ok(s) => println!("{s}"),
@@ -143,15 +468,50 @@ fn run() -> Result<()> {
                 None => println!("(nil)"),
             }
         }
-        Command::Scan{ prefix } => scan(&store, prefix.as_deref())?,
+        Command::Scan{ prefix } => scan(&store.namespace(ns)?, prefix.as_deref())?,
+        Command::Export { format, prefix } => {
+            let stdout = std::io::stdout().lock();
+            // rows carry no namespace, so mixing them in one export would lose it
+            let others: Vec<String> = store.namespaces().into_iter().filter(|name| name != DEFAULT_NAMESPACE).collect();
+            if cli.ns.is_none() && !others.is_empty() {
+                return Err(StoreError::InvalidInput {
+                    msg: format!("the store has other namespaces ({}); export them one at a time with --ns", others.join(", ")),
+                });
+            }
+            export(&store.namespace(ns)?, prefix.unwrap_or_default().as_bytes(), format, std::io::BufWriter::new(stdout))?;
+        }
+        Command::Import { file, format, batch_size } => {
+            let count = match file {
+                Some(path) => {
+                    let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+                    import(&mut store.namespace(ns)?, format, reader, batch_size)?
+                }
+                None => import(&mut store.namespace(ns)?, format, std::io::stdin().lock(), batch_size)?,
+            };
+            println!("imported {count} keys");
+        }
         Command::Snapshot => {
             store.create_snapshot()?;
         }
+        Command::Backup { path } => {
+            let pin = store.pin_for_backup()?;
+            let info = write_backup(pin, &path)?;
+            println!("backup of seq {} written to {} ({} bytes)", info.last_seq, path.display(), info.bytes);
+        }
+        Command::Rekey { new_key_file, decrypt: _ } => {
+            let new_key = new_key_file.as_deref().map(EncryptionKey::from_file).transpose()?;
+            let encrypted = new_key.is_some();
+            store.rekey(new_key)?;
+            println!("{}", if encrypted { "data dir encrypted under the new key" } else { "data dir decrypted" });
+        }
+        Command::Verify | Command::Repair | Command::InspectLog { .. } | Command::BulkLoad { .. } | Command::Restore { .. } => {
+            unreachable!("handled before opening the store")
+        }
     }
     Ok(())
 }
 
-fn scan(store: &Store, prefix: Option<&str>) -> Result<()> {
+fn scan(store: &Namespace, prefix: Option<&str>) -> Result<()> {
     let keys = store.scan_prefix_str(prefix);
     
     match prefix {
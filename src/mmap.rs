@@ -0,0 +1,64 @@
+use std::{fmt, fs::File, io, ops::Deref};
+
+// A whole file mapped read-only. Only for files nothing will change while the map is alive:
+// snapshots qualify, since they're written under a .tmp name and never touched after the
+// rename. A file truncated under a map gets the reader killed by SIGBUS.
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// the mapping is read-only and owned by this value, like a Box<[u8]>
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    // None for an empty file, which has nothing to map
+    #[cfg(unix)]
+    pub fn map(file: &File) -> io::Result<Option<Mmap>> {
+        use std::os::fd::AsRawFd;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("file too large to map"))?;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // it's read front to back (or a block range per thread), so let readahead go wide
+        unsafe { libc::madvise(ptr, len, libc::MADV_WILLNEED) };
+        Ok(Some(Mmap { ptr, len }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File) -> io::Result<Option<Mmap>> {
+        Ok(None)
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+impl fmt::Debug for Mmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mmap({} bytes)", self.len)
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Seek},
+    path::{Path, PathBuf},
+};
+use crate::{
+    config::{Compression, Limits, SnapshotMeta},
+    crypto::EncryptionKey,
+    error::{Result, StoreError},
+    store::{
+        archive_dir, base_dir_for, check_key, is_preallocated_log, load_snapshot, read_manifest, read_record,
+        write_manifest,
+        write_snapshot, Indexes, LogRecord, PendingChunks,
+    },
+    vfs::RealFs,
+};
+
+// Point to restore to: a sequence number, or a wall-clock time in unix millis
+#[derive(Debug, Clone, Copy)]
+pub enum RestoreTarget {
+    Seq(u64),
+    Time(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreReport {
+    pub last_seq: u64,
+    pub keys: usize,
+    // snapshot the restore started from, if any
+    pub base_snapshot: Option<u64>,
+    pub log_path: PathBuf,
+}
+
+struct RetainedSnapshot {
+    number: u64,
+    last_seq: u64,
+    path: PathBuf,
+}
+
+impl RetainedSnapshot {
+    // When the snapshot was written, in unix millis; everything it holds was written before that
+    fn written_ms(&self) -> Result<u64> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        Ok(modified.duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64))
+    }
+}
+
+// Rebuilds the state of the data dir owning `log_path` as of `target` into the empty directory `dest_dir`.
+// Uses the current snapshot/log plus whatever was kept in `archive/` by `retain_history`.
+pub fn restore_to_point(log_path: &Path, dest_dir: &Path, target: RestoreTarget) -> Result<RestoreReport> {
+    restore_to_point_with_key(log_path, dest_dir, target, None)
+}
+
+// Like `restore_to_point`, for an encrypted data dir; the restored dir keeps the same key
+pub fn restore_to_point_with_key(
+    log_path: &Path,
+    dest_dir: &Path,
+    target: RestoreTarget,
+    key: Option<&EncryptionKey>,
+) -> Result<RestoreReport> {
+    let base_dir = base_dir_for(log_path);
+    let manifest = read_manifest(&RealFs, &base_dir.join("MANIFEST"))?;
+    let limits = manifest.as_ref().map(|meta| meta.limits).unwrap_or_default();
+    let key_check = manifest.as_ref().and_then(|meta| meta.key_check.as_deref());
+    let key_check = check_key(&base_dir, key_check, key, true)?;
+
+    let mut snapshots = Vec::new();
+    // (number of the snapshot the segment leads up to, path)
+    let mut segments: Vec<(u64, PathBuf)> = Vec::new();
+
+    let archive = archive_dir(&base_dir);
+    if archive.exists() {
+        for entry in fs::read_dir(&archive)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+
+            if let Some((number, last_seq)) = name.strip_prefix("snapshot-")
+                .and_then(|s| s.strip_suffix(".snap"))
+                .and_then(|s| s.split_once('-'))
+                .and_then(|(n, seq)| Some((n.parse().ok()?, seq.parse().ok()?)))
+            {
+                snapshots.push(RetainedSnapshot { number, last_seq, path });
+            } else if let Some(number) = name.strip_prefix("log-")
+                .and_then(|s| s.strip_suffix(".log"))
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                segments.push((number, path));
+            }
+        }
+    }
+
+    match &manifest {
+        Some(meta) => {
+            if meta.snapshot_path.exists() {
+                snapshots.push(RetainedSnapshot {
+                    number: meta.snapshot_number,
+                    last_seq: meta.last_seq,
+                    path: meta.snapshot_path.clone(),
+                });
+            }
+            segments.push((meta.snapshot_number + 1, meta.log_path.clone()));
+        }
+        None => segments.push((1, log_path.to_path_buf())),
+    }
+    segments.sort_by_key(|(number, _)| *number);
+
+    let target_seq = match target {
+        RestoreTarget::Seq(seq) => seq,
+        RestoreTarget::Time(millis) => seq_at_time(&snapshots, &segments, millis, &limits, key)?,
+    };
+
+    // start from the newest snapshot that does not go past the target
+    let mut index = Indexes::new();
+    let base = snapshots.iter()
+        .filter(|s| s.last_seq <= target_seq)
+        .max_by_key(|s| (s.last_seq, s.number));
+    let (base_number, mut applied_seq) = match base {
+        Some(s) => {
+            load_snapshot(&RealFs, &s.path, &mut index, None, &limits, key)?;
+            (s.number, s.last_seq)
+        }
+        None => (0, 0),
+    };
+
+    let mut done = false;
+    let mut pending = PendingChunks::new();
+    for (_, path) in segments.iter().filter(|(number, _)| *number > base_number) {
+        visit_records(path, &limits, key, |record| {
+            if let Some(seq) = record.seq {
+                if seq <= applied_seq {
+                    return Ok(true);
+                }
+                if seq != applied_seq + 1 && applied_seq < target_seq {
+                    return Err(StoreError::InvalidInput {
+                        msg: format!(
+                            "history gap in {}: expected seq {}, found {seq} (older logs were not retained?)",
+                            path.display(), applied_seq + 1
+                        ),
+                    });
+                }
+                if seq > target_seq {
+                    done = true;
+                    return Ok(false);
+                }
+                applied_seq = seq;
+            }
+            record.apply(&mut index, &mut pending)?;
+            Ok(true)
+        })?;
+
+        if done {
+            break;
+        }
+    }
+
+    if applied_seq < target_seq {
+        return Err(StoreError::InvalidInput {
+            msg: format!("seq {target_seq} is past the end of the retained history (last seq {applied_seq})"),
+        });
+    }
+
+    // write the result as a fresh data dir: one snapshot and an empty log
+    if dest_dir.exists() && fs::read_dir(dest_dir)?.next().is_some() {
+        return Err(StoreError::InvalidInput {
+            msg: format!("restore destination {} is not empty", dest_dir.display()),
+        });
+    }
+    fs::create_dir_all(dest_dir)?;
+    let dest_dir = dest_dir.canonicalize()?;
+
+    let keys = index.values().map(|index| index.len()).sum();
+    let snapshot_path = dest_dir.join(format!("snapshot-{:04}.snap", 1));
+    write_snapshot(&RealFs, index, &snapshot_path, Compression::None, key)?;
+
+    let log_name = log_path.file_name().unwrap_or_else(|| "data.log".as_ref());
+    let dest_log_path = dest_dir.join(log_name);
+    File::create(&dest_log_path)?;
+
+    write_manifest(&RealFs, &dest_dir.join("MANIFEST"), &SnapshotMeta {
+        snapshot_number: 1,
+        snapshot_path,
+        log_path: dest_log_path.clone(),
+        last_seq: applied_seq,
+        limits,
+        key_check,
+    })?;
+
+    Ok(RestoreReport {
+        last_seq: applied_seq,
+        keys,
+        base_snapshot: base.map(|s| s.number),
+        log_path: dest_log_path,
+    })
+}
+
+// Highest sequence number written at or before `millis`. Writes a snapshot covers may be
+// gone from the logs, so a snapshot written by then counts for everything it holds.
+fn seq_at_time(
+    snapshots: &[RetainedSnapshot],
+    segments: &[(u64, PathBuf)],
+    millis: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+) -> Result<u64> {
+    let mut seq_at = 0;
+    for snapshot in snapshots {
+        if snapshot.written_ms()? <= millis {
+            seq_at = seq_at.max(snapshot.last_seq);
+        }
+    }
+    for (_, path) in segments {
+        visit_records(path, limits, key, |record| {
+            if let (Some(seq), Some(ts)) = (record.seq, record.timestamp_ms)
+                && ts <= millis {
+                seq_at = seq_at.max(seq);
+            }
+            Ok(true)
+        })?;
+    }
+    // either nothing had been written yet, or what had is only in a snapshot taken later
+    if seq_at == 0 {
+        return Err(StoreError::InvalidInput {
+            msg: format!("no write at or before {millis} (unix ms) in the retained history"),
+        });
+    }
+    Ok(seq_at)
+}
+
+// Calls `f` for each record in the log at `path` until it returns false.
+// A torn tail is treated as the end of the log; nothing is modified on disk.
+fn visit_records(
+    path: &Path,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    mut f: impl FnMut(LogRecord) -> Result<bool>,
+) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut r = BufReader::new(File::open(path)?);
+    let preallocated = is_preallocated_log(&mut r)?;
+    loop {
+        let record_start = r.stream_position()?;
+        match read_record(&mut r, record_start, limits, key, preallocated) {
+            Ok(Some(record)) => {
+                if !f(record)? {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -1,25 +1,47 @@
 use tokio::sync::oneshot;
 use tokio::net::{TcpListener, TcpStream};
-use std::sync::mpsc;
-use crate::config::SnapshotMeta;
+use std::sync::{mpsc, Arc};
+use std::path::{Component, Path, PathBuf};
+use crate::backup::{write_backup, BackupInfo};
+use crate::config::{Limits, ServerOptions, SnapshotMeta, StoreStats, WriteOptions};
 use crate::error::{Result, StoreError};
-use crate::store::Store;
+use crate::store::{namespace_key, validate_kv, Store, WriteBatch, CHUNK_SIZE, DEFAULT_NAMESPACE};
 // Messages that clients can send to the store actor
 pub enum StoreMessage {
     Set {
+        namespace: String,
         key: Vec<u8>,
         value: Vec<u8>,
+        opts: WriteOptions,
         respond_to: oneshot::Sender<Result<()>>,
     },
     Get {
+        namespace: String,
         key: Vec<u8>,
         respond_to: oneshot::Sender<Result<Option<Vec<u8>>>>,
     },
     Del {
+        namespace: String,
         key: Vec<u8>,
+        opts: WriteOptions,
         respond_to: oneshot::Sender<Result<bool>>,
     },
+    // SETSTREAM's value, written on its own and moved into the index without another copy
+    SetStream {
+        namespace: String,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        opts: WriteOptions,
+        respond_to: oneshot::Sender<Result<()>>,
+    },
+    // GETSTREAM's value: the index's own copy, shared rather than copied off the store thread
+    GetStream {
+        namespace: String,
+        key: Vec<u8>,
+        respond_to: oneshot::Sender<Result<Option<Arc<Vec<u8>>>>>,
+    },
     Scan {
+        namespace: String,
         prefix: Option<String>,
         respond_to: oneshot::Sender<Result<Vec<String>>>,
     },
@@ -27,6 +49,16 @@ pub enum StoreMessage {
         respond_to: oneshot::Sender<Result<SnapshotMeta>>,
 
     },
+    Backup {
+        path: PathBuf,
+        respond_to: oneshot::Sender<Result<BackupInfo>>,
+    },
+    Limits {
+        respond_to: oneshot::Sender<Result<Limits>>,
+    },
+    Stats {
+        respond_to: oneshot::Sender<Result<StoreStats>>,
+    },
 }
 
 pub struct StoreActor {
@@ -38,51 +70,185 @@ impl StoreActor {
     pub fn new(receiver: mpsc::Receiver<StoreMessage>, store: Store) -> Self {
         Self {receiver, store}
     }
-    pub fn run(mut self) {
+    // Runs until every handle is dropped, then hands the store back.
+    //
+    // Group commit: whatever queued up while the previous group was being written is
+    // drained in one go, consecutive sets/dels are appended as a single batch (one fsync
+    // under FsyncAlways), and only then are their callers answered. Any other message
+    // commits the pending group first, so reads never see a write that isn't durable yet.
+    pub fn run(mut self) -> Store {
         // runs in a blocking thread
         while let Ok(msg) = self.receiver.recv() {
-            match msg {
-                StoreMessage::Set { key, value, respond_to } => {
-                    let result = self.store.set(&key, &value);
-                    let _ = respond_to.send(result);
-                },
-                StoreMessage::Get { key, respond_to } => {
-                    let result = self.store.get(&key);
-                    let response = Ok(result.map(|s| s.to_vec()));
-                    let _ = respond_to.send(response);
-                },
-                StoreMessage::Del { key, respond_to } => {
-                    let result = self.store.del(&key);
-                    let _ = respond_to.send(result);
-                },
-                StoreMessage::Scan { prefix, respond_to } => {
-                    let result = self.store.scan_prefix_str(prefix.as_deref());
-                    let _ = respond_to.send(Ok(result));
+            let mut group = PendingWrites::default();
+            let mut next = Some(msg);
+            while let Some(msg) = next {
+                self.handle(msg, &mut group);
+                next = if group.waiters.len() < MAX_GROUP_SIZE {
+                    self.receiver.try_recv().ok()
+                } else {
+                    None
+                };
+            }
+            self.commit(group);
+        }
+        self.store
+    }
+
+    fn handle(&mut self, msg: StoreMessage, group: &mut PendingWrites) {
+        match msg {
+            StoreMessage::Set { namespace, key, value, opts, respond_to } => {
+                // a bad key only fails its own request, not the rest of the group
+                if let Err(e) = validate_kv(&key, Some(&value), &self.store.limits()) {
+                    let _ = respond_to.send(Err(e));
+                    return;
+                }
+                group.batch.set_in(&namespace, &key, &value);
+                group.sync |= opts.sync;
+                group.waiters.push(Waiter::Set(respond_to));
+            },
+            StoreMessage::Del { namespace, key, opts, respond_to } => {
+                if let Err(e) = validate_kv(&key, None, &self.store.limits()) {
+                    let _ = respond_to.send(Err(e));
+                    return;
+                }
+                group.batch.del_in(&namespace, &key);
+                group.sync |= opts.sync;
+                group.waiters.push(Waiter::Del(respond_to));
+            },
+            other => {
+                self.commit(std::mem::take(group));
+                self.handle_other(other);
+            }
+        }
+    }
+
+    fn commit(&mut self, group: PendingWrites) {
+        if group.waiters.is_empty() {
+            return;
+        }
+        // one SYNC write upgrades the whole group; the others get the fsync for free
+        let opts = WriteOptions { sync: group.sync };
+        match self.store.write_with(group.batch, opts) {
+            Ok(existed) => {
+                for (waiter, existed) in group.waiters.into_iter().zip(existed) {
+                    match waiter {
+                        Waiter::Set(respond_to) => { let _ = respond_to.send(Ok(())); }
+                        Waiter::Del(respond_to) => { let _ = respond_to.send(Ok(existed)); }
+                    }
                 }
-                StoreMessage::Snapshot { respond_to } => {
-                    let result = self.store.create_snapshot();
-                    let _ = respond_to.send(result);
+            }
+            Err(e) => {
+                for waiter in group.waiters {
+                    match waiter {
+                        Waiter::Set(respond_to) => { let _ = respond_to.send(Err(e.duplicate())); }
+                        Waiter::Del(respond_to) => { let _ = respond_to.send(Err(e.duplicate())); }
+                    }
                 }
             }
         }
     }
+
+    fn handle_other(&mut self, msg: StoreMessage) {
+        match msg {
+            StoreMessage::Get { namespace, key, respond_to } => {
+                let response = self.store.namespace(&namespace)
+                    .map(|ns| ns.get(&key).map(|s| s.to_vec()));
+                let _ = respond_to.send(response);
+            },
+            StoreMessage::SetStream { namespace, key, value, opts, respond_to } => {
+                let response = self.store.namespace(&namespace)
+                    .and_then(|mut ns| ns.set_owned(&key, value, opts));
+                let _ = respond_to.send(response);
+            }
+            StoreMessage::GetStream { namespace, key, respond_to } => {
+                let response = self.store.namespace(&namespace)
+                    .map(|ns| ns.get_shared(&key));
+                let _ = respond_to.send(response);
+            }
+            StoreMessage::Scan { namespace, prefix, respond_to } => {
+                let response = self.store.namespace(&namespace)
+                    .map(|ns| ns.scan_prefix_str(prefix.as_deref()));
+                let _ = respond_to.send(response);
+            }
+            StoreMessage::Snapshot { respond_to } => {
+                let result = self.store.create_snapshot();
+                let _ = respond_to.send(result);
+            }
+            StoreMessage::Backup { path, respond_to } => {
+                // pin on the store thread, copy off it so writes keep flowing
+                match self.store.pin_for_backup() {
+                    Ok(pin) => {
+                        std::thread::spawn(move || {
+                            let _ = respond_to.send(write_backup(pin, &path));
+                        });
+                    }
+                    Err(e) => {
+                        let _ = respond_to.send(Err(e));
+                    }
+                }
+            }
+            StoreMessage::Limits { respond_to } => {
+                let _ = respond_to.send(Ok(self.store.limits()));
+            }
+            StoreMessage::Stats { respond_to } => {
+                let _ = respond_to.send(Ok(self.store.stats()));
+            }
+            StoreMessage::Set { .. } | StoreMessage::Del { .. } => unreachable!("writes are grouped in handle"),
+        }
+    }
+}
+
+// Upper bound on writes per group, so one busy burst can't delay its first caller forever
+const MAX_GROUP_SIZE: usize = 1024;
+
+// Writes collected by the actor but not yet in the log, with the callers waiting on them
+#[derive(Default)]
+struct PendingWrites {
+    batch: WriteBatch,
+    // some caller asked for SYNC
+    sync: bool,
+    waiters: Vec<Waiter>,
+}
+
+enum Waiter {
+    Set(oneshot::Sender<Result<()>>),
+    Del(oneshot::Sender<Result<bool>>),
 }
 
 // handle for clients
 #[derive(Clone)]
 pub struct StoreHandle {
     sender: mpsc::Sender<StoreMessage>,
+    // the namespace set, get, del and scan go to
+    namespace: String,
 }
 
 impl StoreHandle {
     pub fn new(sender: mpsc::Sender<StoreMessage>) -> StoreHandle {
-        Self { sender }
+        Self { sender, namespace: DEFAULT_NAMESPACE.to_string() }
     }
+
+    // A handle to the same store whose reads and writes go to namespace `name`
+    pub fn in_namespace(&self, name: &str) -> Result<StoreHandle> {
+        namespace_key(name)?;
+        Ok(Self { sender: self.sender.clone(), namespace: name.to_string() })
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
     pub async fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.set_with(key, value, WriteOptions::default()).await
+    }
+
+    pub async fn set_with(&self, key: Vec<u8>, value: Vec<u8>, opts: WriteOptions) -> Result<()> {
         let (tx, rx) = oneshot::channel();
         let msg = StoreMessage::Set {
+            namespace: self.namespace.clone(),
             key,
             value,
+            opts,
             respond_to: tx,
         };
 
@@ -96,6 +262,41 @@ impl StoreHandle {
     pub async fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
         let (tx, rx) = oneshot::channel();
         let msg = StoreMessage::Get {
+            namespace: self.namespace.clone(),
+            key,
+            respond_to: tx,
+        };
+
+        self.sender.send(msg)
+            .map_err(|_| StoreError::StoreClosed { msg: "actor closed".into() })?;
+
+        rx.await
+            .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
+    }
+
+    // Like set_with, but the value is written outside any group and kept as the stored copy
+    pub async fn set_stream(&self, key: Vec<u8>, value: Vec<u8>, opts: WriteOptions) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMessage::SetStream {
+            namespace: self.namespace.clone(),
+            key,
+            value,
+            opts,
+            respond_to: tx,
+        };
+
+        self.sender.send(msg)
+            .map_err(|_| StoreError::StoreClosed { msg: "actor closed".into() })?;
+
+        rx.await
+            .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
+    }
+
+    // Like get, but shares the stored value instead of copying it
+    pub async fn get_stream(&self, key: Vec<u8>) -> Result<Option<Arc<Vec<u8>>>> {
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMessage::GetStream {
+            namespace: self.namespace.clone(),
             key,
             respond_to: tx,
         };
@@ -108,9 +309,15 @@ impl StoreHandle {
     }
 
     pub async fn del(&self, key: Vec<u8>) -> Result<bool> {
+        self.del_with(key, WriteOptions::default()).await
+    }
+
+    pub async fn del_with(&self, key: Vec<u8>, opts: WriteOptions) -> Result<bool> {
         let (tx, rx) = oneshot::channel();
         let msg = StoreMessage::Del {
+            namespace: self.namespace.clone(),
             key,
+            opts,
             respond_to: tx,
         };
 
@@ -124,6 +331,7 @@ impl StoreHandle {
     pub async fn scan(&self, prefix: Option<&str>) -> Result<Vec<String>> {
         let (tx, rx) = oneshot::channel();
         let msg = StoreMessage::Scan {
+            namespace: self.namespace.clone(),
             prefix: prefix.map(|s| s.to_string()),
             respond_to: tx,
         };
@@ -146,12 +354,60 @@ impl StoreHandle {
         rx.await
             .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
     }
+
+    pub async fn backup(&self, path: PathBuf) -> Result<BackupInfo> {
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMessage::Backup {
+            path,
+            respond_to: tx,
+        };
+
+        self.sender.send(msg)
+            .map_err(|_| StoreError::StoreClosed { msg: "actor closed".into() })?;
+
+        rx.await
+            .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
+    }
+
+    pub async fn stats(&self) -> Result<StoreStats> {
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMessage::Stats {
+            respond_to: tx,
+        };
+
+        self.sender.send(msg)
+            .map_err(|_| StoreError::StoreClosed { msg: "actor closed".into() })?;
+
+        rx.await
+            .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
+    }
+
+    pub async fn limits(&self) -> Result<Limits> {
+        let (tx, rx) = oneshot::channel();
+        let msg = StoreMessage::Limits {
+            respond_to: tx,
+        };
+
+        self.sender.send(msg)
+            .map_err(|_| StoreError::StoreClosed { msg: "actor closed".into() })?;
+
+        rx.await
+            .map_err(|_| StoreError::StoreClosed { msg: "response channel closed".into() })?
+    }
 }
 
 // TCP server
 pub async fn run_server(
     address: &str, 
     store_handle: StoreHandle,
+) -> Result<()> {
+    run_server_with(address, store_handle, ServerOptions::default()).await
+}
+
+pub async fn run_server_with(
+    address: &str,
+    store_handle: StoreHandle,
+    opts: ServerOptions,
 ) -> Result<()> {
     let listener = TcpListener::bind(address).await?;
     println!("Server listening on {}", address);
@@ -160,18 +416,19 @@ pub async fn run_server(
         let (socket, addr) = listener.accept().await?;
         println!("New client connected: {addr}");
         let handle = store_handle.clone();
+        let opts = opts.clone();
 
         // Spawn a task for each connection
         tokio::spawn(async move{
-            if let Err(e) = handle_client(socket, handle).await {
+            if let Err(e) = handle_client(socket, handle, &opts).await {
                 eprintln!("Error handling client: {e}")
             }
         });
     }
 }
 
-async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()> {
-    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+async fn handle_client(mut stream: TcpStream, mut store: StoreHandle, opts: &ServerOptions) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 
     let (reader, mut writer) = stream.split();
     let mut reader = BufReader::new(reader);
@@ -184,15 +441,19 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
             break; // EOF
         }
 
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
+        let command = line.trim();
+        let parts: Vec<&str> = command.split_whitespace().collect();
         if parts.is_empty() {
             continue;
         }
         match parts[0] {
-            "SET" if parts.len() >= 3 => {
+            // SETSYNC is SET with an fsync before the reply; a value can end in any word, so
+            // there's no trailing flag
+            "SET" | "SETSYNC" if parts.len() >= 3 => {
+                let opts = if parts[0] == "SETSYNC" { WriteOptions::sync() } else { WriteOptions::default() };
                 let key = parts[1].as_bytes().to_vec();
                 let value = parts[2..].join(" ").into_bytes();
-                match store.set(key, value).await {
+                match store.set_with(key, value, opts).await {
                     Ok(()) => writer.write_all(b"OK\n").await?,
                     Err(e) => {
                         writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
@@ -212,9 +473,58 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
                     }
                 }
             }
+            // SETSTREAM <key> <len> [SYNC], then exactly <len> raw bytes of value
+            "SETSTREAM" if parts.len() >= 3 => {
+                let (parts, opts) = split_write_flags(&parts, 3);
+                let key = parts[1].as_bytes().to_vec();
+                let Ok(len) = parts[2].parse::<u64>() else {
+                    writer.write_all(b"ERROR: invalid length\n").await?;
+                    continue;
+                };
+
+                let limits = store.limits().await?;
+                if len > limits.max_val_len as u64 {
+                    // skip the body so the connection stays usable
+                    tokio::io::copy(&mut (&mut reader).take(len), &mut tokio::io::sink()).await?;
+                    writer.write_all(
+                        format!("ERROR: value too large ({len} bytes, limit is {} bytes)\n", limits.max_val_len).as_bytes()
+                    ).await?;
+                    continue;
+                }
+
+                let mut value = Vec::with_capacity(len as usize);
+                let read = (&mut reader).take(len).read_to_end(&mut value).await?;
+                if (read as u64) < len {
+                    break; // client hung up mid-value
+                }
+                match store.set_stream(key, value, opts).await {
+                    Ok(()) => writer.write_all(b"OK\n").await?,
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                    }
+                }
+            }
+            // replies VALUE <len>, then <len> raw bytes and a newline
+            "GETSTREAM" if parts.len() >= 2 => {
+                let key = parts[1].as_bytes().to_vec();
+                match store.get_stream(key).await {
+                    Ok(Some(value)) => {
+                        writer.write_all(format!("VALUE {}\n", value.len()).as_bytes()).await?;
+                        for chunk in value.chunks(CHUNK_SIZE) {
+                            writer.write_all(chunk).await?;
+                        }
+                        writer.write_all(b"\n").await?;
+                    }
+                    Ok(None) => writer.write_all(b"(nil)\n").await?,
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                    }
+                }
+            }
             "DEL" if parts.len() >=2 => {
+                let (parts, opts) = split_write_flags(&parts, 2);
                 let key = parts[1].as_bytes().to_vec();
-                match store.del(key).await {
+                match store.del_with(key, opts).await {
                     Ok(true) => writer.write_all(b"1\n").await?,
                     Ok(false) => writer.write_all(b"0\n").await?,
                     Err(e) => {
@@ -223,7 +533,7 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
                 }
             }
             "SCAN" => {
-                let prefix = parts.get(1).map(|s| *s);
+                let prefix = parts.get(1).copied();
                 match store.scan(prefix).await {
                     Ok(keys) => {
                         for key in keys {
@@ -237,6 +547,18 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
                     }
                 }
             }
+            // the rest of the connection uses namespace <name>
+            "SELECT" | "USE" if parts.len() >= 2 => {
+                match store.in_namespace(parts[1]) {
+                    Ok(handle) => {
+                        store = handle;
+                        writer.write_all(b"OK\n").await?;
+                    }
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                    }
+                }
+            }
             "SNAPSHOT" => {
                 match store.snapshot().await {
                     Ok(meta) =>{
@@ -249,6 +571,42 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
                     }
                 }
             }
+            "STATS" => {
+                match store.stats().await {
+                    Ok(stats) => {
+                        let lines = format!(
+                            "writes {}\ncommits {}\nsyncs {}\nvalue_bytes {}\nstored_value_bytes {}\ncompression_ratio {:.2}\nfailed_writes {}\nfailed_snapshots {}\nfailed_evictions {}\nread_only {}\nmemory_bytes {}\nevictions {}\nevicted_bytes {}\nOK\n",
+                            stats.writes, stats.commits, stats.syncs,
+                            stats.value_bytes, stats.stored_value_bytes, stats.compression_ratio(),
+                            stats.failed_writes, stats.failed_snapshots, stats.failed_evictions, stats.read_only as u8,
+                            stats.memory_bytes, stats.evictions, stats.evicted_bytes,
+                        );
+                        writer.write_all(lines.as_bytes()).await?;
+                    }
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                    }
+                }
+            }
+            "BACKUP" if parts.len() >= 2 => {
+                let path = match backup_path(opts.backup_dir.as_deref(), &parts[1..].join(" ")) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                        continue;
+                    }
+                };
+                match store.backup(path).await {
+                    Ok(info) => {
+                        writer.write_all(
+                            format!("OK seq {} {} bytes\n", info.last_seq, info.bytes).as_bytes()
+                        ).await?;
+                    }
+                    Err(e) => {
+                        writer.write_all(format!("ERROR: {e}\n").as_bytes()).await?;
+                    }
+                }
+            }
             _ => {
                 writer.write_all(b"ERROR: invalid command\n").await?;
             }
@@ -256,4 +614,29 @@ async fn handle_client(mut stream: TcpStream, store: StoreHandle) -> Result<()>
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+// A trailing SYNC on DEL/SETSTREAM asks for an fsync before the reply. Those commands have a
+// fixed number of parts, so it can't be part of anything else; it only counts as a flag when
+// the command still has `min_parts` without it, so `DEL SYNC` deletes the key "SYNC".
+fn split_write_flags<'a, 'b>(parts: &'a [&'b str], min_parts: usize) -> (&'a [&'b str], WriteOptions) {
+    match parts.split_last() {
+        Some((&"SYNC", rest)) if rest.len() >= min_parts => (rest, WriteOptions::sync()),
+        _ => (parts, WriteOptions::default()),
+    }
+}
+
+// Where `BACKUP <name>` writes: `name` inside the server's backup dir. Clients only get to
+// pick a file in there, not any path the server can write to.
+fn backup_path(backup_dir: Option<&Path>, name: &str) -> Result<PathBuf> {
+    let Some(backup_dir) = backup_dir else {
+        return Err(StoreError::InvalidInput { msg: "BACKUP is disabled; start the server with --backup-dir".into() });
+    };
+    let name = Path::new(name);
+    if !name.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(StoreError::InvalidInput {
+            msg: format!("backup name {} must be a relative path inside the backup dir, without ..", name.display()),
+        });
+    }
+    Ok(backup_dir.join(name))
+}
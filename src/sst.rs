@@ -0,0 +1,632 @@
+use std::{
+    borrow::Cow,
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use crate::{
+    compress::{compress, decompress, FLAG_MASK},
+    config::{Compression, Limits},
+    crypto::{EncryptionKey, AAD_SNAPSHOT},
+    error::{Result, StoreError},
+    mmap::Mmap,
+    store::{namespace_key, MAX_NAMESPACE_LEN},
+    vfs::{RealFs, Vfs, VfsFile},
+};
+
+// Sorted snapshot format (a sorted string table):
+//
+//   [magic: 8]
+//   [data block]...  entries in ascending key order, cut into blocks of about BLOCK_SIZE
+//   [index block]    per data block: [key_len: u32][last key][offset: u64][len: u64]
+//   [footer: 40]     [index_offset: u64][index_len: u64][entries: u64][blocks: u64][crc32: u32]["KVST"]
+//
+// A block is [flag: u8][len: u64][crc32: u32][len bytes]. The bytes are the block's entries,
+// [key_len: u32][key][val_len: u32][val] each, compressed as a whole when flag != 0 (see
+// compress.rs). In an encrypted data dir every block, the index included, is sealed after
+// compression (flag bit FLAG_SEALED, see crypto.rs) and the crc covers the sealed bytes, so a
+// good crc that fails authentication means the wrong key or tampering. The footer is never
+// sealed, only crc-protected: its index position and block count are checked against the
+// sealed index on open, but `entries` is a hint (verify compares it with the blocks).
+// The footer and index are enough to check a snapshot one block at a time, or to look a key
+// up by reading only the block that can hold it.
+//
+// A store with namespaces (see Store::namespace) writes NAMESPACED_MAGIC instead, and every
+// key in it is [name_len: u8][name][key], the default namespace having name_len 0. A store
+// with only the default namespace keeps writing MAGIC, which older versions can read.
+//
+// Legacy snapshots start with a key length, which is never 0, so the zero prefix of the
+// magic tells the two formats apart.
+pub(crate) const MAGIC: [u8; 8] = *b"\0\0\0\0KVST";
+const NAMESPACED_MAGIC: [u8; 8] = *b"\0\0\0\0KVSN";
+const FOOTER_MAGIC: [u8; 4] = *b"KVST";
+const FOOTER_LEN: u64 = 40;
+const BLOCK_HEADER_LEN: u64 = 1 + 8 + 4;
+const FLAG_SEALED: u8 = 0x01;
+// uncompressed size a data block is cut at; a bigger entry gets a block to itself
+const BLOCK_SIZE: usize = 64 * 1024;
+
+// Is `path` a sorted snapshot (as opposed to a legacy one)?
+pub(crate) fn is_sorted_snapshot(vfs: &dyn Vfs, path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    match vfs.open_read(path)?.read_exact(&mut magic) {
+        Ok(()) => Ok(magic == MAGIC || magic == NAMESPACED_MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// A key as it's stored in a namespaced snapshot
+pub(crate) fn namespaced_key(namespace: &str, key: &[u8]) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(1 + namespace.len() + key.len());
+    stored.push(namespace.len() as u8);
+    stored.extend_from_slice(namespace.as_bytes());
+    stored.extend_from_slice(key);
+    stored
+}
+
+// Writes a sorted snapshot; keys have to be added in strictly ascending order
+pub(crate) struct SstWriter<W: Write> {
+    w: W,
+    compression: Compression,
+    encryption: Option<EncryptionKey>,
+    offset: u64,
+    block: Vec<u8>,
+    last_key: Vec<u8>,
+    index: Vec<u8>,
+    entries: u64,
+    blocks: u64,
+    // block bytes before and after compression
+    raw_bytes: u64,
+    stored_bytes: u64,
+}
+
+impl<W: Write> SstWriter<W> {
+    pub(crate) fn new(w: W, compression: Compression, encryption: Option<EncryptionKey>) -> Result<Self> {
+        Self::with_magic(w, MAGIC, compression, encryption)
+    }
+
+    // For keys made by namespaced_key
+    pub(crate) fn new_namespaced(w: W, compression: Compression, encryption: Option<EncryptionKey>) -> Result<Self> {
+        Self::with_magic(w, NAMESPACED_MAGIC, compression, encryption)
+    }
+
+    fn with_magic(mut w: W, magic: [u8; 8], compression: Compression, encryption: Option<EncryptionKey>) -> Result<Self> {
+        w.write_all(&magic)?;
+        Ok(Self {
+            w,
+            compression,
+            encryption,
+            offset: MAGIC.len() as u64,
+            block: Vec::new(),
+            last_key: Vec::new(),
+            index: Vec::new(),
+            entries: 0,
+            blocks: 0,
+            raw_bytes: 0,
+            stored_bytes: 0,
+        })
+    }
+
+    pub(crate) fn add(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        if self.entries > 0 && key <= self.last_key.as_slice() {
+            return Err(StoreError::InvalidInput {
+                msg: "sorted snapshot keys must be added in ascending order".into(),
+            });
+        }
+
+        self.block.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        self.block.extend_from_slice(key);
+        self.block.extend_from_slice(&(val.len() as u32).to_le_bytes());
+        self.block.extend_from_slice(val);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.entries += 1;
+
+        if self.block.len() >= BLOCK_SIZE {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    // Writes the index and footer. Returns the writer and the block bytes before and after compression.
+    pub(crate) fn finish(mut self) -> Result<(W, u64, u64)> {
+        self.flush_block()?;
+        let index = std::mem::take(&mut self.index);
+        let (index_offset, index_len) = self.write_block(&index)?;
+
+        let mut footer = Vec::with_capacity(FOOTER_LEN as usize);
+        footer.extend_from_slice(&index_offset.to_le_bytes());
+        footer.extend_from_slice(&index_len.to_le_bytes());
+        footer.extend_from_slice(&self.entries.to_le_bytes());
+        footer.extend_from_slice(&self.blocks.to_le_bytes());
+        let crc = crc32fast::hash(&footer);
+        footer.extend_from_slice(&crc.to_le_bytes());
+        footer.extend_from_slice(&FOOTER_MAGIC);
+        self.w.write_all(&footer)?;
+
+        Ok((self.w, self.raw_bytes, self.stored_bytes))
+    }
+
+    fn flush_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::take(&mut self.block);
+        let (offset, len) = self.write_block(&block)?;
+        self.block = block;
+        self.block.clear();
+
+        self.index.extend_from_slice(&(self.last_key.len() as u32).to_le_bytes());
+        self.index.extend_from_slice(&self.last_key);
+        self.index.extend_from_slice(&offset.to_le_bytes());
+        self.index.extend_from_slice(&len.to_le_bytes());
+        self.blocks += 1;
+        Ok(())
+    }
+
+    // Returns the block's offset and length, header included
+    fn write_block(&mut self, data: &[u8]) -> Result<(u64, u64)> {
+        let compressed = compress(self.compression, data);
+        let (mut flag, mut stored) = match &compressed {
+            Some((flag, compressed)) => (*flag, compressed.as_slice()),
+            None => (0, data),
+        };
+        let sealed;
+        if let Some(key) = &self.encryption {
+            sealed = key.seal(AAD_SNAPSHOT, self.offset, stored);
+            stored = &sealed;
+            flag |= FLAG_SEALED;
+        }
+
+        self.w.write_all(&[flag])?;
+        self.w.write_all(&(stored.len() as u64).to_le_bytes())?;
+        self.w.write_all(&crc32fast::hash(stored).to_le_bytes())?;
+        self.w.write_all(stored)?;
+        self.raw_bytes += data.len() as u64;
+        self.stored_bytes += stored.len() as u64;
+
+        let offset = self.offset;
+        let len = BLOCK_HEADER_LEN + stored.len() as u64;
+        self.offset += len;
+        Ok((offset, len))
+    }
+}
+
+type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+// a block's entries as they lie in it
+type BorrowedEntries<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+// Where a data block is and the last key in it
+#[derive(Debug, Clone)]
+struct BlockHandle {
+    last_key: Vec<u8>,
+    offset: u64,
+    len: u64,
+}
+
+// Reads a sorted snapshot on demand. Opening only reads the footer and the index, so
+// memory use doesn't depend on the snapshot's size.
+pub struct SstReader {
+    source: Source,
+    path: PathBuf,
+    index: Vec<BlockHandle>,
+    entries: u64,
+    limits: Limits,
+    encryption: Option<EncryptionKey>,
+    // keys are namespaced_key's
+    namespaced: bool,
+}
+
+// Blocks are read from the file, or sliced straight out of a map of it, in which case an
+// uncompressed, unencrypted block's entries are handed out as slices of the map
+enum Source {
+    File(BufReader<Box<dyn VfsFile>>),
+    Mapped(Mmap),
+}
+
+impl SstReader {
+    // `limits` are the data dir's, which every entry is checked against
+    pub fn open(path: &Path, limits: &Limits) -> Result<Self> {
+        Self::open_with_key(path, limits, None)
+    }
+
+    // Like `open`, for a snapshot in an encrypted data dir
+    pub fn open_with_key(path: &Path, limits: &Limits, encryption: Option<&EncryptionKey>) -> Result<Self> {
+        Self::open_in(&RealFs, path, limits, encryption)
+    }
+
+    pub(crate) fn open_in(
+        vfs: &dyn Vfs,
+        path: &Path,
+        limits: &Limits,
+        encryption: Option<&EncryptionKey>,
+    ) -> Result<Self> {
+        let file = vfs.open_read(path)?;
+        let file_len = file.file_len()?;
+        Self::load(Source::File(BufReader::new(file)), file_len, path, limits, encryption)
+    }
+
+    // Like `open_in`, reading through a memory map; None if `vfs` can't map files
+    pub(crate) fn open_mapped(
+        vfs: &dyn Vfs,
+        path: &Path,
+        limits: &Limits,
+        encryption: Option<&EncryptionKey>,
+    ) -> Result<Option<Self>> {
+        let Some(map) = vfs.map(path)? else {
+            return Ok(None);
+        };
+        let file_len = map.len() as u64;
+        Self::load(Source::Mapped(map), file_len, path, limits, encryption).map(Some)
+    }
+
+    fn load(
+        source: Source,
+        file_len: u64,
+        path: &Path,
+        limits: &Limits,
+        encryption: Option<&EncryptionKey>,
+    ) -> Result<Self> {
+        let corrupt = |msg: String| StoreError::CorruptLog { msg: format!("{msg} in snapshot {}", path.display()) };
+        let mut reader = SstReader {
+            source,
+            path: path.to_path_buf(),
+            index: Vec::new(),
+            entries: 0,
+            limits: *limits,
+            encryption: encryption.cloned(),
+            namespaced: false,
+        };
+
+        let magic = reader.read_at(0, MAGIC.len() as u64)?;
+        reader.namespaced = magic == NAMESPACED_MAGIC;
+        if magic != MAGIC && !reader.namespaced {
+            return Err(corrupt("not a sorted snapshot".into()));
+        }
+        if file_len < MAGIC.len() as u64 + FOOTER_LEN {
+            return Err(corrupt(format!("file too short for a footer ({file_len} bytes)")));
+        }
+
+        let footer = reader.read_at(file_len - FOOTER_LEN, FOOTER_LEN)?;
+        let field = |at: usize| u64::from_le_bytes(footer[at..at + 8].try_into().unwrap());
+        let crc = u32::from_le_bytes(footer[32..36].try_into().unwrap());
+        if footer[36..] != FOOTER_MAGIC || crc32fast::hash(&footer[..32]) != crc {
+            return Err(corrupt("damaged footer".into()));
+        }
+        let (index_offset, index_len, entries, blocks) = (field(0), field(8), field(16), field(24));
+        if index_offset < MAGIC.len() as u64
+            || index_len < BLOCK_HEADER_LEN
+            || index_offset.checked_add(index_len) != Some(file_len - FOOTER_LEN) {
+            return Err(corrupt(format!("footer places the index at {index_offset}+{index_len}")));
+        }
+        reader.entries = entries;
+
+        // the crc is checked before decompressing, so the stored length can be trusted
+        let raw = reader.read_at(index_offset, index_len)?;
+        let index = reader.unpack(index_offset, index_len, &raw, usize::MAX)?;
+        reader.index = decode_index(&index, &reader.stored_limits()).map_err(|msg| corrupt(format!("{msg} in index at offset {index_offset}")))?;
+
+        if reader.index.len() as u64 != blocks {
+            return Err(corrupt(format!("index lists {} blocks, footer says {blocks}", reader.index.len())));
+        }
+        let mut expected = MAGIC.len() as u64;
+        for (i, handle) in reader.index.iter().enumerate() {
+            if handle.offset != expected || handle.len < BLOCK_HEADER_LEN {
+                return Err(corrupt(format!("index entry {i} points at {}+{}", handle.offset, handle.len)));
+            }
+            if i > 0 && handle.last_key <= reader.index[i - 1].last_key {
+                return Err(corrupt(format!("index entry {i} is out of order")));
+            }
+            expected += handle.len;
+        }
+        if expected != index_offset {
+            return Err(corrupt(format!("data blocks end at {expected}, index starts at {index_offset}")));
+        }
+
+        Ok(reader)
+    }
+
+    // Number of entries, according to the footer
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.index.len()
+    }
+
+    // Looks `key` up in the default namespace, reading only the one block that can hold it
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_in("", key)
+    }
+
+    // Like `get`, in namespace `namespace` ("" being the default one)
+    pub fn get_in(&mut self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = match (self.namespaced, namespace) {
+            (true, _) => Cow::Owned(namespaced_key(namespace, key)),
+            (false, "") => Cow::Borrowed(key),
+            (false, _) => return Ok(None),
+        };
+        let i = self.index.partition_point(|handle| handle.last_key.as_slice() < &*key);
+        if i == self.index.len() {
+            return Ok(None);
+        }
+        let found = self.read_block(i)?
+            .into_iter()
+            .find(|(k, _)| *k == *key)
+            .map(|(_, val)| val);
+        Ok(found)
+    }
+
+    // Every entry of the default namespace, in key order, one block at a time
+    pub fn for_each(&mut self, mut f: impl FnMut(Vec<u8>, Vec<u8>)) -> Result<()> {
+        self.for_each_namespaced(|namespace, key, val| {
+            if namespace.is_empty() {
+                f(key, val);
+            }
+        })
+    }
+
+    // Every entry of every namespace, by namespace and then key
+    pub fn for_each_namespaced(&mut self, mut f: impl FnMut(&str, Vec<u8>, Vec<u8>)) -> Result<()> {
+        for i in 0..self.index.len() {
+            self.visit_block(i, |namespace, key, val| f(namespace, key.to_vec(), val.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    // A key out of read_block: its namespace ("" for the default one) and the key itself
+    pub(crate) fn split_key<'a>(&self, stored: &'a [u8]) -> (&'a str, &'a [u8]) {
+        split_key(self.namespaced, stored)
+    }
+
+    // The key limit grows by the namespace a namespaced key carries
+    fn stored_limits(&self) -> Limits {
+        let mut limits = self.limits;
+        if self.namespaced {
+            limits.max_key_len += 1 + MAX_NAMESPACE_LEN;
+        }
+        limits
+    }
+
+    // Offset and length of data block `i`
+    pub(crate) fn block_span(&self, i: usize) -> (u64, u64) {
+        (self.index[i].offset, self.index[i].len)
+    }
+
+    // Decodes data block `i`, checking its crc, its order and that it agrees with the index
+    pub(crate) fn read_block(&mut self, i: usize) -> Result<Entries> {
+        let mut entries = Vec::new();
+        self.read_block_with(i, |key, val| entries.push((key.to_vec(), val.to_vec())))?;
+        Ok(entries)
+    }
+
+    // Like read_block, but hands `f` each entry's namespace, key and value where they lie in
+    // the block instead of copying them out
+    pub(crate) fn visit_block(&mut self, i: usize, mut f: impl FnMut(&str, &[u8], &[u8])) -> Result<()> {
+        let namespaced = self.namespaced;
+        self.read_block_with(i, |stored, val| {
+            let (namespace, key) = split_key(namespaced, stored);
+            f(namespace, key, val)
+        })
+    }
+
+    fn read_block_with(&mut self, i: usize, f: impl FnMut(&[u8], &[u8])) -> Result<()> {
+        let (offset, len) = self.block_span(i);
+        if len < BLOCK_HEADER_LEN {
+            return Err(StoreError::CorruptLog {
+                msg: format!("block at offset {offset} is shorter than its header in snapshot {}", self.path.display()),
+            });
+        }
+        if let Source::Mapped(map) = &self.source {
+            return self.decode_block(i, mapped_range(map, offset, len)?, f);
+        }
+        let raw = self.read_at(offset, len)?;
+        self.decode_block(i, &raw, f)
+    }
+
+    // Every data block decoded out of the map, split across up to `threads` threads by block
+    // range. Each thread starts a `T` with `init` and hands `f` the entries of its blocks in
+    // order, as visit_block does; the `T`s come back in block order. None unless the reader
+    // is mapped, since threads can't share a file cursor
+    pub(crate) fn read_blocks_parallel<T: Send>(
+        &self,
+        threads: usize,
+        init: impl Fn() -> T + Sync,
+        f: impl Fn(&mut T, &str, &[u8], &[u8]) + Sync,
+    ) -> Option<Result<Vec<T>>> {
+        let Source::Mapped(map) = &self.source else {
+            return None;
+        };
+        let blocks = self.index.len();
+        let per_thread = blocks.div_ceil(threads.max(1)).max(1);
+        let (init, f) = (&init, &f);
+
+        Some(std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..blocks)
+                .step_by(per_thread)
+                .map(|first| {
+                    scope.spawn(move || {
+                        let mut acc = init();
+                        for i in first..blocks.min(first + per_thread) {
+                            let (offset, len) = self.block_span(i);
+                            self.decode_block(i, mapped_range(map, offset, len)?, |stored, val| {
+                                let (namespace, key) = self.split_key(stored);
+                                f(&mut acc, namespace, key, val)
+                            })?;
+                        }
+                        Ok(acc)
+                    })
+                })
+                .collect();
+
+            workers.into_iter()
+                .map(|worker| worker.join().expect("snapshot loading thread panicked"))
+                .collect()
+        }))
+    }
+
+    // Checks block `i` as a whole, then hands `f` its entries in order, borrowed from `raw` (or
+    // from the buffer it was opened or decompressed into)
+    fn decode_block(&self, i: usize, raw: &[u8], mut f: impl FnMut(&[u8], &[u8])) -> Result<()> {
+        let handle = &self.index[i];
+        let limits = self.stored_limits();
+        let max_len = BLOCK_SIZE + 8 + limits.max_key_len + limits.max_val_len;
+        let data = self.unpack(handle.offset, handle.len, raw, max_len)?;
+        let corrupt = |msg: String| StoreError::CorruptLog {
+            msg: format!("{msg} in snapshot block at offset {}", handle.offset),
+        };
+
+        let entries = decode_entries(&data, &limits).map_err(corrupt)?;
+        if self.namespaced {
+            for (key, _) in &entries {
+                check_namespaced_key(key, &self.limits).map_err(corrupt)?;
+            }
+        }
+        let Some((last_key, _)) = entries.last() else {
+            return Err(corrupt("empty block".into()));
+        };
+        if *last_key != handle.last_key.as_slice() {
+            return Err(corrupt("last key doesn't match the index".into()));
+        }
+        if i > 0 && entries[0].0 <= self.index[i - 1].last_key.as_slice() {
+            return Err(corrupt("keys overlap the previous block".into()));
+        }
+        for (key, val) in entries {
+            f(key, val);
+        }
+        Ok(())
+    }
+
+    // `len` bytes at `offset`, copied out of the file or the map
+    fn read_at(&mut self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        match &mut self.source {
+            Source::File(r) => {
+                r.seek(SeekFrom::Start(offset))?;
+                let mut buf = vec![0u8; len as usize];
+                r.read_exact(&mut buf)?;
+                Ok(buf)
+            }
+            Source::Mapped(map) => Ok(mapped_range(map, offset, len)?.to_vec()),
+        }
+    }
+
+    // The contents of a raw block (header included): checked, opened and decompressed, and
+    // only copied if one of those had to
+    fn unpack<'a>(&self, offset: u64, len: u64, raw: &'a [u8], max_len: usize) -> Result<Cow<'a, [u8]>> {
+        let corrupt = |msg: String| StoreError::CorruptLog {
+            msg: format!("{msg} in snapshot block at offset {offset} of {}", self.path.display()),
+        };
+
+        // the index's length comes from the footer and a block's from the index; neither
+        // has to be long enough for a header
+        if (raw.len() as u64) < BLOCK_HEADER_LEN {
+            return Err(corrupt(format!("block of {} bytes is shorter than its header", raw.len())));
+        }
+        let (header, stored) = raw.split_at(BLOCK_HEADER_LEN as usize);
+        let flag = header[0];
+        let stored_len = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[9..13].try_into().unwrap());
+        if BLOCK_HEADER_LEN + stored_len != len {
+            return Err(corrupt(format!("block length {stored_len} doesn't match the index")));
+        }
+
+        if crc32fast::hash(stored) != crc {
+            return Err(corrupt("checksum mismatch".into()));
+        }
+        let stored = match (&self.encryption, flag & FLAG_SEALED != 0) {
+            (Some(key), true) => Cow::Owned(key.open(AAD_SNAPSHOT, offset, stored)
+                .ok_or_else(|| corrupt("authentication failure (tampered, or sealed by another key)".into()))?),
+            (None, true) => {
+                return Err(StoreError::Encryption {
+                    msg: format!("snapshot {} is encrypted and no key was given", self.path.display()),
+                });
+            }
+            (Some(_), false) => return Err(corrupt("unencrypted block in an encrypted data dir".into())),
+            (None, false) => Cow::Borrowed(stored),
+        };
+        if flag & FLAG_MASK == 0 {
+            return Ok(stored);
+        }
+        decompress(flag & FLAG_MASK, &stored, max_len).map(Cow::Owned).map_err(corrupt)
+    }
+}
+
+fn mapped_range(map: &[u8], offset: u64, len: u64) -> Result<&[u8]> {
+    usize::try_from(offset.saturating_add(len))
+        .ok()
+        .and_then(|end| map.get(offset as usize..end))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "snapshot block past the end of the file").into())
+}
+
+// A stored key's namespace and the key itself; a namespaced key has been through
+// check_namespaced_key, so it splits
+fn split_key(namespaced: bool, stored: &[u8]) -> (&str, &[u8]) {
+    if !namespaced {
+        return ("", stored);
+    }
+    let (namespace, key) = stored[1..].split_at(stored[0] as usize);
+    (std::str::from_utf8(namespace).unwrap(), key)
+}
+
+fn check_namespaced_key(stored: &[u8], limits: &Limits) -> std::result::Result<(), String> {
+    let name_len = stored[0] as usize;
+    let key_len = (stored.len() - 1).checked_sub(name_len).ok_or("namespace runs past the key")?;
+    if key_len == 0 || key_len > limits.max_key_len {
+        return Err(format!("invalid key length {key_len}"));
+    }
+    match std::str::from_utf8(&stored[1..1 + name_len]) {
+        Ok(name) if name_len == 0 || namespace_key(name).is_ok_and(|n| n == name) => Ok(()),
+        _ => Err("invalid namespace".into()),
+    }
+}
+
+fn decode_index(mut data: &[u8], limits: &Limits) -> std::result::Result<Vec<BlockHandle>, String> {
+    let mut index = Vec::new();
+    while !data.is_empty() {
+        let key_len = take_u32(&mut data)? as usize;
+        if key_len == 0 || key_len > limits.max_key_len {
+            return Err(format!("invalid key length {key_len}"));
+        }
+        let last_key = take(&mut data, key_len)?.to_vec();
+        let offset = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        let len = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+        index.push(BlockHandle { last_key, offset, len });
+    }
+    Ok(index)
+}
+
+fn decode_entries<'a>(mut data: &'a [u8], limits: &Limits) -> std::result::Result<BorrowedEntries<'a>, String> {
+    let mut entries: BorrowedEntries = Vec::new();
+    while !data.is_empty() {
+        let key_len = take_u32(&mut data)? as usize;
+        if key_len == 0 || key_len > limits.max_key_len {
+            return Err(format!("invalid key length {key_len}"));
+        }
+        let key = take(&mut data, key_len)?;
+        let val_len = take_u32(&mut data)? as usize;
+        if val_len > limits.max_val_len {
+            return Err(format!("invalid value length {val_len}"));
+        }
+        let val = take(&mut data, val_len)?;
+
+        if entries.last().is_some_and(|&(prev, _)| key <= prev) {
+            return Err("keys out of order".into());
+        }
+        entries.push((key, val));
+    }
+    Ok(entries)
+}
+
+fn take<'a>(data: &mut &'a [u8], n: usize) -> std::result::Result<&'a [u8], String> {
+    if data.len() < n {
+        return Err("truncated entry".into());
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Ok(head)
+}
+
+fn take_u32(data: &mut &[u8]) -> std::result::Result<u32, String> {
+    Ok(u32::from_le_bytes(take(data, 4)?.try_into().unwrap()))
+}
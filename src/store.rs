@@ -1,114 +1,643 @@
 use std::{
-    collections::HashMap,
-    fs::{OpenOptions, File},
-    io::{self, BufReader, BufWriter, Read, Write, Seek, SeekFrom},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write, Seek, SeekFrom},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use crate::{
     error::{Result, StoreError},
-    config::{Durability, StoreOptions, SnapshotMeta},
+    config::{Compression, Durability, EvictionPolicy, Limits, NamespaceOptions, RecoveryPolicy, StoreOptions, StoreStats, SnapshotMeta, WriteOptions},
+    sync::SyncTimer,
+    evict::Evictor,
+    compress::{compress, decompress, FLAG_MASK},
+    crypto::{EncryptionKey, AAD_LOG, AAD_SNAPSHOT, SEAL_OVERHEAD},
+    sst::{is_sorted_snapshot, namespaced_key, SstReader, SstWriter},
+    vfs::{Vfs, VfsFile},
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 
 
+// legacy records: [op][key_len: u32][key][val_len: u32][val]
+// The top two bits of the op byte say whether `val` is compressed (see compress.rs); only
+// OP_SET_SEQ and OP_CHUNK records ever have them set.
 const OP_SET: u8 = 1;
 const OP_DEL: u8 = 2;
-const MAX_KEY_LEN: usize = 1024;
-const MAX_VAL_LEN: usize = 1024 * 1024; // 1 MiB
+// sequenced records: [op][seq: u64][timestamp_ms: u64][key_len: u32][key][val_len: u32][val]
+const OP_SET_SEQ: u8 = 3;
+const OP_DEL_SEQ: u8 = 4;
+// values bigger than CHUNK_SIZE are written as a run of chunk records followed by a commit
+// record, both laid out like OP_SET_SEQ:
+//   chunk:  val = [offset: u64][up to CHUNK_SIZE bytes of the value]
+//   commit: val = [total_len: u64]
+// replay only applies the value once its commit record shows up
+const OP_CHUNK: u8 = 5;
+const OP_SET_CHUNKED: u8 = 6;
+pub(crate) const CHUNK_SIZE: usize = 256 * 1024;
+// in an encrypted data dir every record is sealed whole (see crypto.rs), with its offset in the
+// log as associated data:  [OP_SEALED][len: u32][nonce + ciphertext of one of the records above]
+const OP_SEALED: u8 = 7;
+// in a preallocated log every record (sealed or not) is framed with a checksum:
+//   [OP_CHECKED][len: u32][crc32 of record: u32][record]
+// past the logical end the file is zeros, so a torn record there doesn't come up short at
+// EOF the way it otherwise would; its missing bytes read as zeros, and only the crc tells
+const OP_CHECKED: u8 = 8;
+// a record in a namespace other than the default one (see Store::namespace) is prefixed with
+// the namespace's name; sealing and framing wrap the prefixed record:
+//   [OP_NAMESPACED][name_len: u8][name][a sequenced record]
+const OP_NAMESPACED: u8 = 9;
+// longest prefix OP_NAMESPACED adds
+const NAMESPACE_OVERHEAD: usize = 1 + 1 + MAX_NAMESPACE_LEN;
+pub(crate) const MAX_NAMESPACE_LEN: usize = u8::MAX as usize;
+// what the default namespace is called where one has to be named (--ns, SELECT); in the
+// index and on disk its name is ""
+pub const DEFAULT_NAMESPACE: &str = "default";
+// snapshot key lengths carry the compression flag in their top two bits
+const SNAPSHOT_FLAG_BITS: u32 = (FLAG_MASK as u32) << 24;
+const MAX_KEY_LIMIT: usize = !SNAPSHOT_FLAG_BITS as usize;
+// a sealed snapshot entry: [SEALED_ENTRY][len: u32][nonce + ciphertext of a plain entry].
+// Both flag bits set isn't a codec, so no plain entry starts like this.
+const SEALED_ENTRY: u32 = u32::MAX;
+// written by write_manifest; version 1 is the legacy colon-separated line
+const MANIFEST_VERSION: u64 = 2;
+
+const ARCHIVE_DIR: &str = "archive";
+
+// Entries of one namespace
+pub type Index = HashMap<Vec<u8>, Arc<Vec<u8>>>;
+// Every namespace's entries by name, the default namespace's under ""
+pub type Indexes = BTreeMap<String, Index>;
+
+// A single decoded log record
+#[derive(Debug, Clone)]
+pub(crate) struct LogRecord {
+    // "" for the default namespace
+    pub(crate) namespace: String,
+    pub(crate) op: u8,
+    // None for records written before sequence numbers existed
+    pub(crate) seq: Option<u64>,
+    pub(crate) timestamp_ms: Option<u64>,
+    pub(crate) key: Vec<u8>,
+    // None for tombstones
+    pub(crate) value: Option<Vec<u8>>,
+}
+
+impl LogRecord {
+    pub(crate) fn op_name(&self) -> &'static str {
+        match (self.op, &self.value) {
+            (OP_CHUNK, _) => "CHUNK",
+            (OP_SET_CHUNKED, _) => "SET-CHUNKED",
+            (_, Some(_)) => "SET",
+            (_, None) => "DEL",
+        }
+    }
+
+    // Chunk records are held in `pending` until the commit record for their value arrives
+    pub(crate) fn apply(self, indexes: &mut Indexes, pending: &mut PendingChunks) -> Result<()> {
+        let index = match indexes.get_mut(&self.namespace) {
+            Some(index) => index,
+            None => indexes.entry(self.namespace.clone()).or_default(),
+        };
+        let pending_key = (self.namespace, self.key);
+        match (self.op, self.value) {
+            (OP_CHUNK, Some(mut val)) => {
+                let offset = u64::from_le_bytes(val[..8].try_into().unwrap());
+                val.drain(..8);
+                match pending.get_mut(&pending_key) {
+                    _ if offset == 0 => { pending.insert(pending_key, val); }
+                    Some(buf) if buf.len() as u64 == offset => buf.extend_from_slice(&val),
+                    // the rest of a run whose start we never saw (or an abandoned one)
+                    _ => { pending.remove(&pending_key); }
+                }
+            }
+            (OP_SET_CHUNKED, Some(val)) => {
+                let total = u64::from_le_bytes(val[..8].try_into().unwrap());
+                match pending.remove(&pending_key) {
+                    Some(buf) if buf.len() as u64 == total => { index.insert(pending_key.1, Arc::new(buf)); }
+                    buf => {
+                        return Err(StoreError::CorruptLog {
+                            msg: format!(
+                                "chunked value{} is missing chunks ({} of {total} bytes found)",
+                                self.seq.map(|seq| format!(" at seq {seq}")).unwrap_or_default(),
+                                buf.map_or(0, |b| b.len()),
+                            ),
+                        });
+                    }
+                }
+            }
+            (_, Some(val)) => { index.insert(pending_key.1, Arc::new(val)); }
+            (_, None) => { index.remove(&pending_key.1); }
+        }
+        Ok(())
+    }
+}
+
+// Chunks replayed so far for values whose commit record hasn't been read yet, by namespace and key
+pub(crate) type PendingChunks = HashMap<(String, Vec<u8>), Vec<u8>>;
+
+// What a salvaging open had to skip to get past corruption
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    // (file, offset, length) of each skipped region
+    pub skipped_regions: Vec<(PathBuf, u64, u64)>,
+    pub skipped_bytes: u64,
+    // records known to be lost from gaps in the sequence numbers around skipped regions
+    pub lost_records: u64,
+    pub recovered_records: u64,
+    // where the damaged files were moved to
+    pub preserved: Vec<PathBuf>,
+}
+
+impl SalvageReport {
+    pub fn is_clean(&self) -> bool {
+        self.skipped_regions.is_empty()
+    }
+
+    fn skip(&mut self, path: &Path, offset: u64, len: u64) {
+        self.skipped_regions.push((path.to_path_buf(), offset, len));
+        self.skipped_bytes += len;
+    }
+}
 
 pub struct Store{
-    index: HashMap<Vec<u8>, Arc<Vec<u8>>>,
-    log: BufWriter<File>,
+    // every namespace with entries (or written to since open), the default one under ""
+    keyspaces: BTreeMap<String, Keyspace>,
+    vfs: Arc<dyn Vfs>,
+    log: BufWriter<Box<dyn VfsFile>>,
     log_path: PathBuf,
     base_dir: PathBuf,
     durability: Durability,
     pending_sync_writes: u64,
     snapshot_number: u64, // Track current snapshot number
+    snapshot_seq: u64, // last sequence number covered by the current snapshot
+    last_seq: u64,
     max_log_size: Option<u64>,
+    // logical end of the log: where the next record goes
     current_log_size: u64,
+    preallocate: Option<u64>,
+    // length of the log file, zeros past current_log_size when preallocating
+    log_capacity: u64,
+    retain_history: bool,
+    salvage: Option<SalvageReport>,
+    stats: StoreStats,
+    // background fsync for the interval durability modes
+    sync_timer: Option<SyncTimer>,
+    // what writes are checked against
+    limits: Limits,
+    // what the data dir has been written under (at least `limits`), kept in the MANIFEST
+    stored_limits: Limits,
+    compression: Compression,
+    encryption: Option<EncryptionKey>,
+    // the MANIFEST's proof of which key the dir is encrypted with (see EncryptionKey::key_check)
+    key_check: Option<Vec<u8>>,
+    // set after a failed write, until one gets through again (see commit_or_roll_back)
+    read_only: Option<ReadOnly>,
+    // what each namespace gets unless `namespace_options` says otherwise
+    max_memory: Option<u64>,
+    eviction: EvictionPolicy,
+    namespace_options: HashMap<String, NamespaceOptions>,
+}
+
+// One namespace's entries, and what keeps them under its memory budget
+struct Keyspace {
+    index: Index,
+    // key and value bytes in `index`
+    memory_bytes: u64,
+    max_memory: Option<u64>,
+    // set along with max_memory; behind a mutex because gets, which take &self, count as uses
+    evictor: Option<Mutex<Evictor>>,
+    // for values written to the log; snapshots use the store's
+    compression: Compression,
+}
+
+impl Keyspace {
+    fn note_read(&self, key: &[u8]) {
+        if let Some(evictor) = &self.evictor {
+            evictor.lock().unwrap().read(key);
+        }
+    }
+}
+
+struct ReadOnly {
+    cause: String,
+    // false if truncating the failed write off the log failed too; retried before the next write
+    rolled_back: bool,
 }
 
 impl Store {
     pub fn open(log_path: impl AsRef<Path>, opts: StoreOptions) -> Result<Self> {
         let log_path = log_path.as_ref().to_path_buf();
 
-        let base_dir = log_path.parent()
-            .map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf()))
-            .unwrap_or_else(|| {
-                std::env::current_dir()
-                    .unwrap_or_else(|_| PathBuf::from("."))
-            });
+        let base_dir = base_dir_for(&log_path);
         // println!("base dir: {:?}", base_dir);
         let manifest_path = base_dir.join("MANIFEST");
+        let vfs = Arc::clone(&opts.vfs);
 
-        let manifest = read_manifest(&manifest_path)?;
+        let manifest = read_manifest_with_version(&*vfs, &manifest_path)?;
+        let manifest_version = manifest.as_ref().map(|(_, version)| *version);
+        let manifest = manifest.map(|(meta, _)| meta);
 
-        let mut index = HashMap::new();
+        let dir_limits = manifest.as_ref().map(|meta| meta.limits).unwrap_or_default();
+        let limits = Limits {
+            max_key_len: opts.max_key_len.unwrap_or(dir_limits.max_key_len),
+            max_val_len: opts.max_val_len.unwrap_or(dir_limits.max_val_len),
+        };
+        check_limits(&limits)?;
+        for name in opts.namespaces.keys() {
+            namespace_key(name)?;
+        }
+        // lowering a limit only affects new writes; what's already on disk still has to load
+        let stored_limits = dir_limits.max(limits);
+
+        let encryption = opts.encryption;
+        let has_data = manifest.as_ref().is_some_and(|meta| meta.snapshot_number > 0)
+            || vfs.file_len(&log_path).is_ok_and(|len| len > 0);
+        let key_check = check_key(
+            &base_dir,
+            manifest.as_ref().and_then(|meta| meta.key_check.as_deref()),
+            encryption.as_ref(),
+            has_data,
+        )?;
+        let key = encryption.as_ref();
+
+        let mut index = Indexes::new();
         let actual_log_path: PathBuf;
         let snapshot_number: u64;
+        let snapshot_seq: u64;
+        let mut salvage = match opts.recovery {
+            RecoveryPolicy::Strict => None,
+            RecoveryPolicy::Salvage => Some(SalvageReport::default()),
+        };
 
-        match manifest {
+        match manifest.clone() {
             Some(meta) => {
                 actual_log_path = meta.log_path;
                 snapshot_number = meta.snapshot_number;
+                snapshot_seq = meta.last_seq;
 
-                if meta.snapshot_path.exists() {
-                    load_snapshot(&meta.snapshot_path, &mut index)?;
+                if vfs.exists(&meta.snapshot_path) {
+                    load_snapshot(&*vfs, &meta.snapshot_path, &mut index, salvage.as_mut(), &dir_limits, key)?;
                 }
             }
             None => {
                 actual_log_path = log_path.clone();
                 snapshot_number = 0;
+                snapshot_seq = 0;
             }
         }
         
+        // leftovers of an interrupted snapshot: a rotated log may hold writes the snapshot doesn't have
+        let orphans = find_orphans(&*vfs, &base_dir, &actual_log_path)?;
+        let mut rotated_seq = 0;
+        let mut recovered_records = 0;
+        let mut covered_logs = Vec::new();
+        for path in &orphans.rotated_logs {
+            let replay = replay_rotated(&*vfs, path, snapshot_seq, &mut index, &dir_limits, key)?;
+            rotated_seq = rotated_seq.max(replay.last_seq);
+            recovered_records += replay.applied;
+            // without sequence numbers there's no telling what the snapshot covers; leave it be
+            if !replay.legacy {
+                covered_logs.push((path.clone(), replay.applied == 0));
+            }
+        }
+
         // open once: read+write so replay can truncate;
-        let mut file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&actual_log_path)?;
+        let created = !vfs.exists(&actual_log_path);
+        let mut file = vfs.open(&actual_log_path)?;
+        // a new log's dir entry has to be durable before any write to it is
+        if created {
+            vfs.sync_dir(parent_dir(&actual_log_path))?;
+        }
 
-        replay_into(&mut file, &mut index)?; // will truncate if torn tail
+        // will truncate if torn tail
+        let (replayed_seq, log_end) =
+            replay_into(&*file, &actual_log_path, &mut index, salvage.as_mut(), &dir_limits, key)?;
+        // a log preallocated before but not now loses its zeros, since only framed records
+        // are safe to write into them
+        if opts.preallocate.is_none() && file.file_len()? > log_end {
+            file.set_len(log_end)?;
+        }
+        // the other way round, framed records and zeros can't go after unframed ones: replay
+        // only takes zeros for the end when the log starts framed. Such a log goes into a
+        // snapshot below, and preallocation starts on the fresh log that leaves.
+        let unframed_log = opts.preallocate.is_some() && log_end > 0 && {
+            let mut r = BufReader::new(file.try_clone()?);
+            r.seek(SeekFrom::Start(0))?;
+            !is_preallocated_log(&mut r)?
+        };
 
-        //after replay, go to EOF so appends don't overwrite anything
-        file.seek(SeekFrom::End(0))?;
-        let current_log_size = file.stream_position()?;
+        // record raised limits and a new key before anything is written under them, and move
+        // legacy absolute-path manifests to the relative format
+        match manifest {
+            Some(meta) if meta.limits != stored_limits
+                || meta.key_check != key_check
+                || manifest_version < Some(MANIFEST_VERSION) => {
+                write_manifest_file(&*vfs, &manifest_path, &SnapshotMeta {
+                    limits: stored_limits,
+                    key_check: key_check.clone(),
+                    ..meta
+                })?;
+            }
+            Some(_) => {}
+            // a new dir only needs a MANIFEST this early if it isn't using the defaults
+            None if stored_limits != Limits::default() || key_check.is_some() => {
+                write_manifest_file(&*vfs, &manifest_path, &SnapshotMeta {
+                    snapshot_number: 0,
+                    snapshot_path: base_dir.join(format!("snapshot-{:04}.snap", 0)),
+                    log_path: actual_log_path.clone(),
+                    last_seq: 0,
+                    limits: stored_limits,
+                    key_check: key_check.clone(),
+                })?;
+            }
+            None => {}
+        }
+
+        // after replay, go to the logical end so appends don't overwrite anything (in a
+        // preallocated log that's before EOF)
+        let current_log_size = file.seek(SeekFrom::Start(log_end))?;
+        let log_capacity = file.file_len()?;
+        let sync_timer = match opts.durability.sync_interval() {
+            Some(interval) => Some(SyncTimer::start(&*file, interval)?),
+            None => None,
+        };
         let log = BufWriter::new(file);
 
-        Ok(Store { 
-            index, 
+        let mut store = Store { 
+            keyspaces: BTreeMap::new(),
+            vfs,
             log,
             log_path: actual_log_path.clone(),
             base_dir,
             durability: opts.durability,
             pending_sync_writes: 0,
             snapshot_number,
+            snapshot_seq,
+            last_seq: snapshot_seq.max(rotated_seq).max(replayed_seq),
             max_log_size: opts.max_log_size,
             current_log_size,
-        })
+            preallocate: opts.preallocate,
+            log_capacity,
+            retain_history: opts.retain_history,
+            salvage,
+            stats: StoreStats::default(),
+            sync_timer,
+            limits,
+            stored_limits,
+            compression: opts.compression,
+            encryption,
+            key_check,
+            read_only: None,
+            max_memory: opts.max_memory,
+            eviction: opts.eviction,
+            namespace_options: opts.namespaces,
+        };
+        index.entry(String::new()).or_default();
+        for (name, index) in index {
+            let keyspace = store.new_keyspace(&name, index);
+            store.keyspaces.insert(name, keyspace);
+        }
+
+        // a .tmp never made it into the MANIFEST, so nothing refers to it
+        for path in orphans.tmp_files {
+            store.vfs.remove_file(&path)?;
+        }
+
+        // the damaged files stay as they are; everything recovered goes into a fresh snapshot.
+        // Same for writes recovered from rotated logs, so they're safe before the logs go.
+        if store.salvage.as_ref().is_some_and(|r| !r.is_clean()) || recovered_records > 0 || unframed_log {
+            store.create_snapshot()?;
+        }
+
+        for (path, covered) in covered_logs {
+            // a log the MANIFEST's snapshot already covers is the history segment leading up to it
+            let archived = archive_dir(&store.base_dir).join(format!("log-{snapshot_number:04}.log"));
+            if store.retain_history && covered && !store.vfs.exists(&archived) {
+                store.vfs.create_dir_all(archived.parent().unwrap())?;
+                store.vfs.rename(&path, &archived)?;
+            } else {
+                store.vfs.remove_file(&path)?;
+            }
+        }
+
+        // the budget may have shrunk since the last run
+        let names: Vec<String> = store.keyspaces.keys().cloned().collect();
+        for name in names {
+            store.evict_after_commit(&name);
+        }
+        Ok(store)
+
+    }
 
+    // Set when the store was opened with RecoveryPolicy::Salvage
+    pub fn salvage_report(&self) -> Option<&SalvageReport> {
+        self.salvage.as_ref()
     }
     
     pub fn set(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
-        validate_kv(key, Some(val))?;
-        self.append_set(key, val)?;
-        self.index.insert(key.to_vec(), Arc::new(val.to_vec()));
+        self.set_with(key, val, WriteOptions::default())
+    }
+
+    pub fn set_with(&mut self, key: &[u8], val: &[u8], opts: WriteOptions) -> Result<()> {
+        self.set_in("", key, val, opts)
+    }
+
+    fn set_in(&mut self, ns: &str, key: &[u8], val: &[u8], opts: WriteOptions) -> Result<()> {
+        self.set_owned_in(ns, key, val.to_vec(), opts)
+    }
+
+    // set_in for a value the caller is done with: it becomes the index's copy as it is
+    pub(crate) fn set_owned_in(&mut self, ns: &str, key: &[u8], val: Vec<u8>, opts: WriteOptions) -> Result<()> {
+        validate_kv(key, Some(&val), &self.limits)?;
+        self.commit_or_roll_back(|s| {
+            s.append_set(ns, key, &val)?;
+            s.commit_append(1, opts)
+        })?;
+        self.index_insert(ns, key.to_vec(), val);
+        self.evict_after_commit(ns);
+        // only after the index has the write, or the snapshot would miss it
+        self.maybe_auto_snapshot();
         Ok(())
 
     }
 
     pub fn del(&mut self, key: &[u8]) -> Result<bool> {
-        validate_kv(key, None)?;
-        self.append_del(key)?;
-        Ok(self.index.remove(key).is_some())
+        self.del_with(key, WriteOptions::default())
+    }
+
+    pub fn del_with(&mut self, key: &[u8], opts: WriteOptions) -> Result<bool> {
+        self.del_in("", key, opts)
+    }
+
+    fn del_in(&mut self, ns: &str, key: &[u8], opts: WriteOptions) -> Result<bool> {
+        validate_kv(key, None, &self.limits)?;
+        self.commit_or_roll_back(|s| {
+            s.append_del(ns, key)?;
+            s.commit_append(1, opts)
+        })?;
+        let existed = self.index_remove(ns, key);
+        self.maybe_auto_snapshot();
+        Ok(existed)
+    }
+
+    // Appends every write in the batch and commits them together: one flush (and at most
+    // one fsync, depending on durability) for the whole batch instead of one per write.
+    // Returns, for each op in order, whether its key existed right before that op ran.
+    pub fn write(&mut self, batch: WriteBatch) -> Result<Vec<bool>> {
+        self.write_with(batch, WriteOptions::default())
+    }
+
+    pub fn write_with(&mut self, mut batch: WriteBatch, opts: WriteOptions) -> Result<Vec<bool>> {
+        // validate everything up front so a bad entry doesn't leave half a batch in the log
+        for (ns, key, val) in &mut batch.ops {
+            *ns = namespace_key(ns)?.to_string();
+            validate_kv(key, val.as_deref(), &self.limits)?;
+        }
+        if batch.ops.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.commit_or_roll_back(|s| {
+            for (ns, key, val) in &batch.ops {
+                match val {
+                    Some(val) => s.append_set(ns, key, val)?,
+                    None => s.append_del(ns, key)?,
+                }
+            }
+            s.commit_append(batch.ops.len() as u64, opts)
+        })?;
+
+        let mut written = Vec::new();
+        let existed = batch.ops.into_iter()
+            .map(|(ns, key, val)| {
+                let existed = match val {
+                    Some(val) => self.index_insert(&ns, key, val),
+                    None => self.index_remove(&ns, &key),
+                };
+                if !written.contains(&ns) {
+                    written.push(ns);
+                }
+                existed
+            })
+            .collect();
+        for ns in written {
+            self.evict_after_commit(&ns);
+        }
+        self.maybe_auto_snapshot();
+        Ok(existed)
+    }
+
+    // Stores the value read from `reader`, writing it to the log a chunk at a time as it comes
+    // in (a value over CHUNK_SIZE is a run of chunk records, like set_with writes). Values live
+    // in the in-memory index, so the value is still held whole there, but that's the only copy
+    // ever made of it. Returns the value's length.
+    pub fn set_from_reader<R: Read>(&mut self, key: &[u8], reader: R, opts: WriteOptions) -> Result<u64> {
+        self.set_from_reader_in("", key, reader, opts)
+    }
+
+    fn set_from_reader_in<R: Read>(&mut self, ns: &str, key: &[u8], reader: R, opts: WriteOptions) -> Result<u64> {
+        validate_kv(key, None, &self.limits)?;
+        let limits = self.limits;
+        // one byte past the limit is enough to know it's too large
+        let mut reader = reader.take(limits.max_val_len as u64 + 1);
+        let mut val = Vec::new();
+        self.commit_or_roll_back(|s| {
+            // a full chunk only goes out once the next one has started: a value that turns out
+            // to fit in one chunk is written as a plain SET record instead
+            let mut written = 0;
+            loop {
+                let start = val.len();
+                let read = (&mut reader).take(CHUNK_SIZE as u64).read_to_end(&mut val)
+                    .map_err(|e| StoreError::InvalidInput { msg: format!("reading the value failed: {e}") })?;
+                validate_kv(key, Some(&val), &limits)?;
+                if read < CHUNK_SIZE {
+                    break;
+                }
+                if start > written {
+                    s.append_record(ns, OP_CHUNK, key, &(written as u64).to_le_bytes(), &val[written..start])?;
+                    written = start;
+                }
+            }
+
+            if val.len() <= CHUNK_SIZE {
+                s.append_record(ns, OP_SET_SEQ, key, &[], &val)?;
+            } else {
+                for chunk in val[written..].chunks(CHUNK_SIZE) {
+                    s.append_record(ns, OP_CHUNK, key, &(written as u64).to_le_bytes(), chunk)?;
+                    written += chunk.len();
+                }
+                s.append_record(ns, OP_SET_CHUNKED, key, &(written as u64).to_le_bytes(), &[])?;
+            }
+            s.commit_append(1, opts)
+        })?;
+        let len = val.len() as u64;
+        self.index_insert(ns, key.to_vec(), val);
+        self.evict_after_commit(ns);
+        self.maybe_auto_snapshot();
+        Ok(len)
     }
 
     pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
-        self.index.get(key).map(|v| v.as_slice())
+        self.get_in("", key)
+    }
+
+    // The index's own copy of the value, for handing a value off the store thread without copying it
+    pub(crate) fn get_shared_in(&self, ns: &str, key: &[u8]) -> Option<Arc<Vec<u8>>> {
+        let keyspace = self.keyspaces.get(ns)?;
+        keyspace.note_read(key);
+        keyspace.index.get(key).cloned()
+    }
+
+    fn get_in(&self, ns: &str, key: &[u8]) -> Option<&[u8]> {
+        let keyspace = self.keyspaces.get(ns)?;
+        keyspace.note_read(key);
+        keyspace.index.get(key).map(|v| v.as_slice())
+    }
+
+    // Writes the value for `key` to `w` a chunk at a time, straight out of the index with no
+    // copy. Returns its length, or None if the key isn't set.
+    pub fn get_to_writer<W: Write>(&self, key: &[u8], w: W) -> Result<Option<u64>> {
+        self.get_to_writer_in("", key, w)
+    }
+
+    fn get_to_writer_in<W: Write>(&self, ns: &str, key: &[u8], mut w: W) -> Result<Option<u64>> {
+        let Some(val) = self.get_in(ns, key) else {
+            return Ok(None);
+        };
+        for chunk in val.chunks(CHUNK_SIZE) {
+            w.write_all(chunk)?;
+        }
+        w.flush()?;
+        Ok(Some(val.len() as u64))
     }
+    // Key/value pairs whose key starts with `prefix`, sorted by key
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Vec<(&[u8], &[u8])> {
+        self.scan_prefix_in("", prefix)
+    }
+
+    fn scan_prefix_in(&self, ns: &str, prefix: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let Some(keyspace) = self.keyspaces.get(ns) else {
+            return Vec::new();
+        };
+        let mut pairs: Vec<(&[u8], &[u8])> = keyspace.index
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.as_slice(), v.as_slice()))
+            .collect();
+
+        pairs.sort_by(|a, b| a.0.cmp(b.0));
+        pairs
+    }
+
     pub fn scan_prefix_str(&self, prefix: Option<&str>) -> Vec<String> {
-        let mut keys: Vec<String> = self.index
+        self.scan_prefix_str_in("", prefix)
+    }
+
+    fn scan_prefix_str_in(&self, ns: &str, prefix: Option<&str>) -> Vec<String> {
+        let Some(keyspace) = self.keyspaces.get(ns) else {
+            return Vec::new();
+        };
+        let mut keys: Vec<String> = keyspace.index
             .keys()
             .filter(|k| {
                 match prefix {
@@ -123,49 +652,349 @@ impl Store {
         keys.sort();
         keys
     }
-    fn append_set(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
 
-        self.log.write_all(&[OP_SET])?;
-        write_u32(&mut self.log, key.len() as u32)?;
-        self.log.write_all(key)?;
-        write_u32(&mut self.log, val.len() as u32)?;
-        self.log.write_all(val)?;
-        self.commit_append()?;
-        self.current_log_size += set_record_size(key.len(), val.len());
-        self.maybe_auto_snapshot()?;
+    // A view of namespace `name`, with its own keys, sharing the store's log and snapshots.
+    // DEFAULT_NAMESPACE is the one the store's own methods use. Nothing is written for a
+    // namespace until something is set in it.
+    pub fn namespace(&mut self, name: &str) -> Result<Namespace<'_>> {
+        let ns = namespace_key(name)?.to_string();
+        Ok(Namespace { store: self, ns })
+    }
+
+    // Names of the namespaces with entries, DEFAULT_NAMESPACE included if it has any
+    pub fn namespaces(&self) -> Vec<String> {
+        self.keyspaces.iter()
+            .filter(|(_, keyspace)| !keyspace.index.is_empty())
+            .map(|(ns, _)| if ns.is_empty() { DEFAULT_NAMESPACE.to_string() } else { ns.clone() })
+            .collect()
+    }
+
+    // Sequence number of the most recent write
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    // Limits new writes are checked against
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        let background_syncs = self.sync_timer.as_ref().map_or(0, |t| t.syncs());
+        StoreStats {
+            syncs: self.stats.syncs + background_syncs,
+            read_only: self.read_only.is_some(),
+            memory_bytes: self.keyspaces.values().map(|keyspace| keyspace.memory_bytes).sum(),
+            ..self.stats.clone()
+        }
+    }
+
+    // Why writes are being refused, if they are: the error that made the last write fail.
+    // Reads keep working meanwhile, and every write is retried until one gets through.
+    pub fn read_only_cause(&self) -> Option<&str> {
+        self.read_only.as_ref().map(|r| r.cause.as_str())
+    }
+
+    // A namespace's settings: its own where StoreOptions::namespaces has them, else the store's
+    fn new_keyspace(&self, ns: &str, index: Index) -> Keyspace {
+        let name = if ns.is_empty() { DEFAULT_NAMESPACE } else { ns };
+        let opts = self.namespace_options.get(name).cloned().unwrap_or_default();
+        let max_memory = opts.max_memory.or(self.max_memory);
+        let eviction = opts.eviction.unwrap_or(self.eviction);
+        Keyspace {
+            memory_bytes: index.iter().map(|(key, val)| (key.len() + val.len()) as u64).sum(),
+            max_memory,
+            evictor: max_memory.map(|_| Mutex::new(Evictor::new(eviction, index.keys()))),
+            compression: opts.compression.unwrap_or(self.compression),
+            index,
+        }
+    }
+
+    fn keyspace_mut(&mut self, ns: &str) -> &mut Keyspace {
+        if !self.keyspaces.contains_key(ns) {
+            let keyspace = self.new_keyspace(ns, Index::new());
+            self.keyspaces.insert(ns.to_string(), keyspace);
+        }
+        self.keyspaces.get_mut(ns).unwrap()
+    }
+
+    // Every change to an index after open goes through index_insert and index_remove, which
+    // keep memory_bytes and the evictor in step with it. Both return whether the key was set.
+    fn index_insert(&mut self, ns: &str, key: Vec<u8>, val: Vec<u8>) -> bool {
+        let keyspace = self.keyspace_mut(ns);
+        if let Some(evictor) = &mut keyspace.evictor {
+            evictor.get_mut().unwrap().written(&key);
+        }
+        let key_len = key.len();
+        keyspace.memory_bytes += (key_len + val.len()) as u64;
+        match keyspace.index.insert(key, Arc::new(val)) {
+            Some(old) => {
+                keyspace.memory_bytes -= (key_len + old.len()) as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn index_remove(&mut self, ns: &str, key: &[u8]) -> bool {
+        let Some(keyspace) = self.keyspaces.get_mut(ns) else {
+            return false;
+        };
+        if let Some(evictor) = &mut keyspace.evictor {
+            evictor.get_mut().unwrap().removed(key);
+        }
+        match keyspace.index.remove(key) {
+            Some(old) => {
+                keyspace.memory_bytes -= (key.len() + old.len()) as u64;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Deletes entries of namespace `ns`, in the order its eviction policy says, until they
+    // fit in its max_memory. They're logged like any other delete, so they stay gone after a
+    // restart.
+    // Runs once a write is committed, so failing to evict doesn't fail the write: it's logged
+    // and counted in stats, the namespace stays over its budget, and the next write tries again
+    fn evict_after_commit(&mut self, ns: &str) {
+        let failed_writes = self.stats.failed_writes;
+        if let Err(e) = self.evict(ns) {
+            eprintln!("eviction failed: {e}");
+            self.stats.failed_writes = failed_writes;
+            self.stats.failed_evictions += 1;
+        }
+    }
+
+    fn evict(&mut self, ns: &str) -> Result<()> {
+        let Some(keyspace) = self.keyspaces.get_mut(ns) else {
+            return Ok(());
+        };
+        let (Some(max_memory), Some(evictor)) = (keyspace.max_memory, &mut keyspace.evictor) else {
+            return Ok(());
+        };
+        let mut over = keyspace.memory_bytes.saturating_sub(max_memory);
+        let mut victims = Vec::new();
+        for key in evictor.get_mut().unwrap().victims() {
+            if over == 0 {
+                break;
+            }
+            over = over.saturating_sub((key.len() + keyspace.index[key].len()) as u64);
+            victims.push(key.to_vec());
+        }
+        if victims.is_empty() {
+            return Ok(());
+        }
+
+        self.commit_or_roll_back(|s| {
+            for key in &victims {
+                s.append_del(ns, key)?;
+            }
+            s.commit_append(victims.len() as u64, WriteOptions::default())
+        })?;
+        let mut evicted_bytes = 0;
+        for key in &victims {
+            evicted_bytes += key.len() as u64 + self.get_in(ns, key).map_or(0, |val| val.len() as u64);
+            self.index_remove(ns, key);
+        }
+        self.stats.evictions += victims.len() as u64;
+        self.stats.evicted_bytes += evicted_bytes;
+        Ok(())
+    }
+
+    // Runs `append`, which appends records and commits them, so that if it fails the log is
+    // left exactly as it was: a record cut short by ENOSPC (or still sitting in the BufWriter)
+    // would otherwise have every later write appended after it. After a failure the store is
+    // read-only; the next write is the retry, and the first one to succeed ends it.
+    fn commit_or_roll_back<T>(&mut self, append: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if let Err(e) = self.finish_roll_back() {
+            return Err(self.refuse(e));
+        }
+
+        let (log_size, last_seq, pending_sync_writes) = (self.current_log_size, self.last_seq, self.pending_sync_writes);
+        let stats = self.stats.clone();
+        match append(self) {
+            Ok(val) => {
+                self.read_only = None;
+                Ok(val)
+            }
+            Err(e) => {
+                self.current_log_size = log_size;
+                self.last_seq = last_seq;
+                self.pending_sync_writes = pending_sync_writes;
+                self.stats = StoreStats { failed_writes: stats.failed_writes + 1, ..stats };
+
+                let was_read_only = self.read_only.is_some();
+                let rolled_back = self.truncate_log(log_size).is_ok();
+                // bad input (set_from_reader only finds out part-way through writing) is no sign of
+                // a failing disk: once it's cut back off the log, things carry on as before
+                if rolled_back && matches!(e, StoreError::InvalidInput { .. }) {
+                    self.stats.failed_writes = stats.failed_writes;
+                    return Err(e);
+                }
+                self.read_only = Some(ReadOnly { cause: e.to_string(), rolled_back });
+                // the first failure is reported as itself, the retries as what they are
+                if was_read_only { Err(self.refuse(e)) } else { Err(e) }
+            }
+        }
+    }
 
+    // Truncates a failed write off the log if that didn't work the first time round
+    fn finish_roll_back(&mut self) -> Result<()> {
+        if let Some(read_only) = &self.read_only
+            && !read_only.rolled_back
+        {
+            self.truncate_log(self.current_log_size)?;
+            if let Some(read_only) = &mut self.read_only {
+                read_only.rolled_back = true;
+            }
+        }
         Ok(())
     }
 
-    fn append_del(&mut self, key: &[u8]) -> Result<()> {
-        self.log.write_all(&[OP_DEL])?;
-        write_u32(&mut self.log, key.len() as u32)?;
-        self.log.write_all(key)?;
-        self.commit_append()?;
-        self.current_log_size += del_record_size(key.len());
-        self.maybe_auto_snapshot()?;
+    fn refuse(&mut self, e: StoreError) -> StoreError {
+        let cause = e.to_string();
+        if let Some(read_only) = &mut self.read_only {
+            read_only.cause = cause.clone();
+        }
+        StoreError::ReadOnly { msg: format!("the last write failed ({cause}); writes resume once one succeeds") }
+    }
+
+    // Cuts the log back to `len`, dropping whatever the BufWriter still holds: flushing that
+    // would put the failed write's leftovers back after the cut. A preallocated log gets its
+    // space back as zeros, if the disk still has room for it.
+    fn truncate_log(&mut self, len: u64) -> Result<()> {
+        let file = self.log.get_ref().try_clone()?;
+        let (_, _unwritten) = std::mem::replace(&mut self.log, BufWriter::new(file)).into_parts();
+        self.log.get_ref().set_len(len)?;
+        let capacity = self.log_capacity;
+        self.log_capacity = len;
+        if self.preallocate.is_some() && capacity > len && self.log.get_ref().allocate(capacity).is_ok() {
+            self.log_capacity = capacity;
+        }
+        self.log.seek(SeekFrom::Start(len))?;
         Ok(())
     }
 
-    fn commit_append(&mut self) -> Result<()> {
-        match self.durability {
-            Durability::Flush => {
-                self.log.flush()?;
-            },
-            Durability::FsyncAlways => {
-                self.log.flush()?;
-                self.log.get_ref().sync_data()?; // OS -> disk (data)
-            },
-            Durability::FsyncEveryN(n) => {
-                self.pending_sync_writes += 1;
+    fn next_seq(&mut self) -> u64 {
+        self.last_seq += 1;
+        self.last_seq
+    }
+
+    fn append_set(&mut self, ns: &str, key: &[u8], val: &[u8]) -> Result<()> {
+        if val.len() <= CHUNK_SIZE {
+            return self.append_record(ns, OP_SET_SEQ, key, &[], val);
+        }
 
-                self.log.flush()?;
+        let mut offset = 0u64;
+        for chunk in val.chunks(CHUNK_SIZE) {
+            self.append_record(ns, OP_CHUNK, key, &offset.to_le_bytes(), chunk)?;
+            offset += chunk.len() as u64;
+        }
+        self.append_record(ns, OP_SET_CHUNKED, key, &offset.to_le_bytes(), &[])
+    }
 
-                if n > 0 && self.pending_sync_writes >= n {
-                    self.log.get_ref().sync_data()?;
-                    self.pending_sync_writes = 0;
-                }
+    // A sequenced record whose value is `header` followed by `val` (compressed if that helps)
+    fn append_record(&mut self, ns: &str, op: u8, key: &[u8], header: &[u8], val: &[u8]) -> Result<()> {
+        let seq = self.next_seq();
+        let compression = self.keyspace_mut(ns).compression;
+        let compressed = compress(compression, val);
+        let (flag, stored) = match &compressed {
+            Some((flag, compressed)) => (*flag, compressed.as_slice()),
+            None => (0, val),
+        };
+        self.stats.value_bytes += val.len() as u64;
+        self.stats.stored_value_bytes += stored.len() as u64;
+        let val = stored;
+        let val_len = header.len() + val.len();
+
+        let mut record = Vec::with_capacity(NAMESPACE_OVERHEAD + 1 + 8 + 8 + 4 + key.len() + 4 + val_len);
+        push_namespace(&mut record, ns);
+        record.push(op | flag);
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(&now_millis().to_le_bytes());
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        record.extend_from_slice(key);
+        record.extend_from_slice(&(val_len as u32).to_le_bytes());
+        record.extend_from_slice(header);
+        record.extend_from_slice(val);
+        self.write_record(&record)
+    }
+
+    fn append_del(&mut self, ns: &str, key: &[u8]) -> Result<()> {
+        let seq = self.next_seq();
+
+        let mut record = Vec::with_capacity(NAMESPACE_OVERHEAD + 1 + 8 + 8 + 4 + key.len());
+        push_namespace(&mut record, ns);
+        record.push(OP_DEL_SEQ);
+        record.extend_from_slice(&seq.to_le_bytes());
+        record.extend_from_slice(&now_millis().to_le_bytes());
+        record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        record.extend_from_slice(key);
+        self.write_record(&record)
+    }
+
+    // Appends an encoded record, sealed first in an encrypted data dir and framed in a
+    // preallocated log
+    fn write_record(&mut self, record: &[u8]) -> Result<()> {
+        let mut sealed_record;
+        let mut record = record;
+        if let Some(key) = &self.encryption {
+            // current_log_size is where this record starts
+            let sealed = key.seal(AAD_LOG, self.current_log_size, record);
+            sealed_record = Vec::with_capacity(1 + 4 + sealed.len());
+            sealed_record.push(OP_SEALED);
+            write_u32(&mut sealed_record, sealed.len() as u32)?;
+            sealed_record.extend_from_slice(&sealed);
+            record = &sealed_record;
+        }
+
+        let Some(segment) = self.preallocate else {
+            self.log.write_all(record)?;
+            self.current_log_size += record.len() as u64;
+            return Ok(());
+        };
+
+        let len = 1 + 4 + 4 + record.len() as u64;
+        let end = self.current_log_size + len;
+        if end > self.log_capacity {
+            let capacity = end.div_ceil(segment) * segment;
+            self.log.get_ref().allocate(capacity)?;
+            self.log_capacity = capacity;
+        }
+        self.log.write_all(&[OP_CHECKED])?;
+        write_u32(&mut self.log, record.len() as u32)?;
+        write_u32(&mut self.log, crc32fast::hash(record))?;
+        self.log.write_all(record)?;
+        self.current_log_size = end;
+        Ok(())
+    }
+
+    // Makes the last `records` appends durable according to the durability mode
+    fn commit_append(&mut self, records: u64, opts: WriteOptions) -> Result<()> {
+        self.stats.commits += 1;
+        self.stats.writes += records;
+        self.log.flush()?;
+
+        let sync_now = match self.durability {
+            Durability::Flush | Durability::FsyncInterval(_) => false,
+            Durability::FsyncAlways => true,
+            Durability::FsyncEveryN(n) | Durability::FsyncEveryNOrInterval(n, _) => {
+                self.pending_sync_writes += records;
+                n > 0 && self.pending_sync_writes >= n
             }
+        };
+
+        // a write can ask for an fsync the mode wouldn't have done yet
+        if sync_now || opts.sync {
+            self.log.get_ref().sync_data()?; // OS -> disk (data)
+            self.stats.syncs += 1;
+            self.pending_sync_writes = 0;
+            if let Some(timer) = &self.sync_timer {
+                timer.mark_clean();
+            }
+        } else if let Some(timer) = &self.sync_timer {
+            timer.mark_dirty()?;
         }
         Ok(())
     }
@@ -192,38 +1021,79 @@ impl Store {
 
         let mut old_log_path = log_path.with_extension(format!("log.{timestamp}"));
 
-        if old_log_path.is_relative() {
-            if let Ok(cwd) = std::env::current_dir() {
-                old_log_path = cwd.join(&old_log_path);
-            }
+        if old_log_path.is_relative()
+            && let Ok(cwd) = std::env::current_dir() {
+            old_log_path = cwd.join(&old_log_path);
         }
         
 
-        // flush and close current log
+        // flush and close current log, without its preallocated zeros
         self.log.flush()?;
+        if self.log_capacity > self.current_log_size {
+            self.log.get_ref().set_len(self.current_log_size)?;
+        }
         self.log.get_ref().sync_all()?; // sync everything to disk
 
         // Move curent log to the rotated name
-        std::fs::rename(log_path, &old_log_path)?;
-
+        self.vfs.rename(log_path, &old_log_path)?;
+    
         // Open fresh log file
-        let mut file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(log_path)?;
+        let mut file = self.vfs.open(log_path)?;
 
         file.seek(SeekFrom::End(0))?;
+        self.vfs.sync_dir(parent_dir(log_path))?;
+        if let Some(timer) = &self.sync_timer {
+            timer.set_log(&*file)?;
+        }
         self.log = BufWriter::new(file);
 
         self.current_log_size = 0;
+        self.log_capacity = 0;
 
         Ok(old_log_path)
     }
 
-    pub fn snapshot_view(&self) -> HashMap<Vec<u8>, Arc<Vec<u8>>> {
-        // Clone the entire HashMap
-        self.index.clone()
+    // Pins the current snapshot and log tail for a backup by hard-linking them into a
+    // private directory, so rotation and snapshot cleanup can't delete them mid-copy.
+    pub fn pin_for_backup(&mut self) -> Result<BackupPin> {
+        self.log.flush()?;
+        self.log.get_ref().sync_data()?;
+        let log_len = self.current_log_size;
+
+        let pin_dir = self.base_dir.join(format!("backup-{}.pin", now_millis()));
+        self.vfs.create_dir_all(&pin_dir)?;
+        let mut pin = BackupPin {
+            vfs: Arc::clone(&self.vfs),
+            dir: pin_dir,
+            snapshot: None,
+            snapshot_number: self.snapshot_number,
+            snapshot_seq: self.snapshot_seq,
+            last_seq: self.last_seq,
+            log: PathBuf::new(),
+            log_len,
+            limits: self.stored_limits,
+            key_check: self.key_check.clone(),
+        };
+
+        let snapshot_path = self.base_dir.join(format!("snapshot-{:04}.snap", self.snapshot_number));
+        if self.snapshot_number > 0 && self.vfs.exists(&snapshot_path) {
+            let pinned = pin.dir.join("snapshot");
+            self.vfs.hard_link(&snapshot_path, &pinned)?;
+            pin.snapshot = Some(pinned);
+        }
+
+        pin.log = pin.dir.join("log");
+        self.vfs.hard_link(&self.log_path, &pin.log)?;
+
+        Ok(pin)
+    }
+
+    pub fn snapshot_view(&self) -> Indexes {
+        // Clone the entire HashMap of every namespace
+        self.keyspaces.iter()
+            .filter(|(_, keyspace)| !keyspace.index.is_empty())
+            .map(|(ns, keyspace)| (ns.clone(), keyspace.index.clone()))
+            .collect()
     }
     
     fn next_snapshot_number(&mut self) -> u64 {
@@ -234,8 +1104,13 @@ impl Store {
     pub fn create_snapshot(
         &mut self,
     ) -> Result<SnapshotMeta> {
-        // rotate to new log immediately
-        let old_log_path = self.rotate_log(&self.log_path.clone())?;
+        // Crash safety comes from the order: the snapshot and then the MANIFEST are committed
+        // while the old log is still in place, and only then is the log rotated. A crash before
+        // the MANIFEST rename leaves the old snapshot + full log; one after it leaves the new
+        // snapshot + a log whose records it already covers (replaying them again is harmless).
+        self.finish_roll_back()?;
+        self.log.flush()?;
+        self.log.get_ref().sync_data()?;
 
         let view = self.snapshot_view();
 
@@ -244,7 +1119,10 @@ impl Store {
         let snapshot_path = self.base_dir.join(format!("snapshot-{:04}.snap", snapshot_num));
 
         // write snapshot in current thread
-        write_snapshot(view, &snapshot_path)?;
+        let (value_bytes, stored_value_bytes) =
+            write_snapshot(&*self.vfs, view, &snapshot_path, self.compression, self.encryption.as_ref())?;
+        self.stats.value_bytes += value_bytes;
+        self.stats.stored_value_bytes += stored_value_bytes;
 
         // write manifest
         let manifest_path = self.base_dir.join("MANIFEST");
@@ -252,15 +1130,57 @@ impl Store {
             snapshot_number: snapshot_num,
             snapshot_path: snapshot_path.clone(),
             log_path: self.log_path.clone(),
+            last_seq: self.last_seq,
+            limits: self.stored_limits,
+            key_check: self.key_check.clone(),
         };
-        write_manifest(&manifest_path, &meta)?;
+        write_manifest(&*self.vfs, &manifest_path, &meta)?;
+
+        let old_log_path = self.rotate_log(&self.log_path.clone())?;
+
+        let prev_snapshot_num = snapshot_num - 1;
+        let prev_snapshot_seq = self.snapshot_seq;
+        self.snapshot_seq = self.last_seq;
+
+        // a salvaged open keeps the damaged snapshot and log for inspection
+        if let Some(report) = self.salvage.as_mut()
+            && !report.is_clean() && report.preserved.is_empty() {
+            let suffix = format!("corrupt-{}", now_millis());
+            let prev_snapshot_path = self.base_dir.join(format!("snapshot-{:04}.snap", prev_snapshot_num));
+            for damaged in [prev_snapshot_path, old_log_path.clone()] {
+                if self.vfs.exists(&damaged) {
+                    let mut name = damaged.file_name().unwrap_or_default().to_os_string();
+                    name.push(format!(".{suffix}"));
+                    let preserved = damaged.with_file_name(name);
+                    self.vfs.rename(&damaged, &preserved)?;
+                    report.preserved.push(preserved);
+                }
+            }
+        }
+
+        // keep the superseded snapshot and rotated log around for point-in-time restore
+        if self.retain_history {
+            let archive_dir = self.base_dir.join(ARCHIVE_DIR);
+            self.vfs.create_dir_all(&archive_dir)?;
+
+            let prev_snapshot_path = self.base_dir.join(format!("snapshot-{:04}.snap", prev_snapshot_num));
+            if self.vfs.exists(&prev_snapshot_path) {
+                self.vfs.rename(
+                    &prev_snapshot_path,
+                    &archive_dir.join(format!("snapshot-{:04}-{}.snap", prev_snapshot_num, prev_snapshot_seq)),
+                )?;
+            }
+            if self.vfs.exists(&old_log_path) {
+                self.vfs.rename(&old_log_path, &archive_dir.join(format!("log-{:04}.log", snapshot_num)))?;
+            }
+        }
 
         // clean up old files
-        cleanup_old_snapshots(&self.base_dir, snapshot_num)?;
+        cleanup_old_snapshots(&*self.vfs, &self.base_dir, snapshot_num)?;
 
         // delete the rotated log
-        if old_log_path.exists() {
-            std::fs::remove_file(&old_log_path)?;
+        if self.vfs.exists(&old_log_path) {
+            self.vfs.remove_file(&old_log_path)?;
         }
 
         Ok(meta)
@@ -268,172 +1188,774 @@ impl Store {
     }
 
     
-    fn maybe_auto_snapshot(&mut self) -> Result<()> {
+    // Re-encrypts the data dir under `new_key`, or decrypts it with None. Everything is
+    // snapshotted under the old key first, leaving the log empty; the MANIFEST of the second
+    // snapshot, written under the new key, is what switches the dir over.
+    pub fn rekey(&mut self, new_key: Option<EncryptionKey>) -> Result<SnapshotMeta> {
+        let archive = archive_dir(&self.base_dir);
+        if self.retain_history || self.vfs.read_dir(&archive).is_ok_and(|entries| !entries.is_empty()) {
+            return Err(StoreError::InvalidInput {
+                msg: format!("can't change the key of a data dir with retained history in {}", archive.display()),
+            });
+        }
+
+        self.create_snapshot()?;
+        let new_check = new_key.as_ref().map(EncryptionKey::key_check);
+        let old_check = std::mem::replace(&mut self.key_check, new_check.clone());
+        let old_key = std::mem::replace(&mut self.encryption, new_key);
+        let res = self.create_snapshot();
+        // the dir only switched over if the new MANIFEST made it; otherwise writes from here
+        // on have to go to the log under the key it still names
+        if res.is_err() {
+            let switched = read_manifest(&*self.vfs, &self.base_dir.join("MANIFEST"))
+                .is_ok_and(|meta| meta.is_some_and(|meta| meta.key_check == new_check));
+            if !switched {
+                self.key_check = old_check;
+                self.encryption = old_key;
+            }
+        }
+        res
+    }
+
+    // Runs after a write is already committed, so a snapshot that fails doesn't fail the
+    // write: it's logged and counted in stats, and the next write tries again
+    fn maybe_auto_snapshot(&mut self) {
         let Some(max_size) = self.max_log_size else {
-            return Ok(());
+            return;
         };
 
         if self.current_log_size >= max_size {
             // Trigger snapshot creation
-            self.create_snapshot()?;
+            if let Err(e) = self.create_snapshot() {
+                eprintln!("auto-snapshot failed: {e}");
+                self.stats.failed_snapshots += 1;
+            }
         }
-
-        Ok(())
     }
     
 }
 
-fn write_u32<W: Write>(w: &mut W, n: u32) -> Result<()> {
-    w.write_all(&n.to_le_bytes())?;
-    Ok(())
+// One namespace of a store, see Store::namespace. Its methods work like the store's own.
+pub struct Namespace<'a> {
+    store: &'a mut Store,
+    // "" for the default namespace
+    ns: String,
 }
 
-fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
-    let mut buf = [0u8; 4];
-    r.read_exact(&mut buf)?;
-    Ok(u32::from_le_bytes(buf))
-}
+impl Namespace<'_> {
+    pub fn name(&self) -> &str {
+        if self.ns.is_empty() { DEFAULT_NAMESPACE } else { &self.ns }
+    }
 
-fn set_record_size(key_len: usize, val_len: usize) -> u64 {
-    1 +
-    4 +
-    key_len as u64 +
-    4 +
-    val_len as u64
+    pub fn set(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.set_with(key, val, WriteOptions::default())
+    }
+
+    pub fn set_with(&mut self, key: &[u8], val: &[u8], opts: WriteOptions) -> Result<()> {
+        self.store.set_in(&self.ns, key, val, opts)
+    }
+
+    pub fn del(&mut self, key: &[u8]) -> Result<bool> {
+        self.del_with(key, WriteOptions::default())
+    }
+
+    pub fn del_with(&mut self, key: &[u8], opts: WriteOptions) -> Result<bool> {
+        self.store.del_in(&self.ns, key, opts)
+    }
+
+    // Every op of `batch` goes to this namespace, whatever it was added with
+    pub fn write(&mut self, batch: WriteBatch) -> Result<Vec<bool>> {
+        self.write_with(batch, WriteOptions::default())
+    }
+
+    pub fn write_with(&mut self, mut batch: WriteBatch, opts: WriteOptions) -> Result<Vec<bool>> {
+        for (ns, _, _) in &mut batch.ops {
+            *ns = self.name().to_string();
+        }
+        self.store.write_with(batch, opts)
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.store.get_in(&self.ns, key)
+    }
+
+    pub fn set_from_reader<R: Read>(&mut self, key: &[u8], reader: R, opts: WriteOptions) -> Result<u64> {
+        self.store.set_from_reader_in(&self.ns, key, reader, opts)
+    }
+
+    pub fn get_to_writer<W: Write>(&self, key: &[u8], w: W) -> Result<Option<u64>> {
+        self.store.get_to_writer_in(&self.ns, key, w)
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.store.limits()
+    }
+
+    pub(crate) fn set_owned(&mut self, key: &[u8], val: Vec<u8>, opts: WriteOptions) -> Result<()> {
+        self.store.set_owned_in(&self.ns, key, val, opts)
+    }
+
+    pub(crate) fn get_shared(&self, key: &[u8]) -> Option<Arc<Vec<u8>>> {
+        self.store.get_shared_in(&self.ns, key)
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Vec<(&[u8], &[u8])> {
+        self.store.scan_prefix_in(&self.ns, prefix)
+    }
+
+    pub fn scan_prefix_str(&self, prefix: Option<&str>) -> Vec<String> {
+        self.store.scan_prefix_str_in(&self.ns, prefix)
+    }
 }
 
-fn del_record_size(key_len: usize) -> u64 {
-    1 +
-    4 +
-    key_len as u64
+// A group of writes applied with a single commit, see Store::write
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    // (namespace, key, value), the value None for deletes
+    ops: Vec<(String, Vec<u8>, Option<Vec<u8>>)>,
 }
 
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-fn replay_into(
-    file: &mut File,
-    index: &mut HashMap<Vec<u8>, Arc<Vec<u8>>>,
-) -> Result<()> {
+    pub fn set(&mut self, key: &[u8], val: &[u8]) {
+        self.set_in(DEFAULT_NAMESPACE, key, val);
+    }
 
-    let reader_file = file.try_clone()?;
-    let mut r = BufReader::new(reader_file);
-   
-    loop {
-        let record_start = r.stream_position()?; // byte offset current record
+    pub fn del(&mut self, key: &[u8]) {
+        self.del_in(DEFAULT_NAMESPACE, key);
+    }
+
+    // Like `set`, in namespace `namespace`. A bad name fails the whole batch in Store::write.
+    pub fn set_in(&mut self, namespace: &str, key: &[u8], val: &[u8]) {
+        self.ops.push((namespace.to_string(), key.to_vec(), Some(val.to_vec())));
+    }
+
+    pub fn del_in(&mut self, namespace: &str, key: &[u8]) {
+        self.ops.push((namespace.to_string(), key.to_vec(), None));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+// A consistent snapshot + log tail held for the duration of a backup.
+// The pinned links are removed when the pin is dropped.
+pub struct BackupPin {
+    // the store's, which the pinned files live on
+    pub(crate) vfs: Arc<dyn Vfs>,
+    pub(crate) dir: PathBuf,
+    pub(crate) snapshot: Option<PathBuf>,
+    pub(crate) snapshot_number: u64,
+    pub(crate) snapshot_seq: u64,
+    pub(crate) last_seq: u64,
+    pub(crate) log: PathBuf,
+    // only this many bytes of the pinned log belong to the backup
+    pub(crate) log_len: u64,
+    pub(crate) limits: Limits,
+    pub(crate) key_check: Option<Vec<u8>>,
+}
+
+impl Drop for BackupPin {
+    fn drop(&mut self) {
+        let _ = self.vfs.remove_dir_all(&self.dir);
+    }
+}
 
-        //read op byte
-        let mut op = [0u8; 1];
-        match r.read_exact(&mut op) {
-            Ok(_) => {},
-            Err(e ) if e.kind() == io::ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(e.into()),
+fn write_u32<W: Write>(w: &mut W, n: u32) -> Result<()> {
+    w.write_all(&n.to_le_bytes())?;
+    Ok(())
+}
+
+fn read_u32<R: Read>(r: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Directory that holds the MANIFEST, snapshots and archive for a log path
+pub(crate) fn base_dir_for(log_path: &Path) -> PathBuf {
+    log_path.parent()
+        // a bare file name has an empty parent; that's the current dir
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| p.canonicalize().unwrap_or_else(|_| p.to_path_buf()))
+        .unwrap_or_else(|| {
+            std::env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from("."))
+        })
+}
+
+pub(crate) fn archive_dir(base_dir: &Path) -> PathBuf {
+    base_dir.join(ARCHIVE_DIR)
+}
+
+// Reads one record starting at `record_start`; `key` is the data dir's, if it's encrypted.
+// Returns Ok(None) on a clean EOF; a torn record surfaces as an UnexpectedEof io error.
+pub(crate) fn read_record<R: Read>(
+    r: &mut R,
+    record_start: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    preallocated: bool,
+) -> Result<Option<LogRecord>> {
+    let mut op = [0u8; 1];
+    match r.read_exact(&mut op) {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    // no record starts with a zero, so in a preallocated log this is the space past the
+    // logical end, as long as it's zeros all the way; anywhere else zeros are damage
+    if op[0] == 0 && preallocated && zeros_to_end(r)? {
+        return Ok(None);
+    }
+    if op[0] == OP_CHECKED {
+        return read_checked_record(r, record_start, limits, key);
+    }
+    if op[0] == OP_SEALED {
+        return read_sealed_record(r, record_start, limits, key).map(Some);
+    }
+    // everything in an encrypted log is sealed; anything else was slipped in
+    if key.is_some() {
+        return Err(StoreError::CorruptLog {
+            msg: format!("unencrypted record at offset {record_start} in an encrypted log"),
+        });
+    }
+    if op[0] == OP_NAMESPACED {
+        return read_namespaced_record(r, record_start, limits).map(Some);
+    }
+
+    let flag = op[0] & FLAG_MASK;
+    let op = match op[0] & !FLAG_MASK {
+        base @ (OP_SET_SEQ | OP_CHUNK) => base,
+        base if flag == 0 => base,
+        _ => {
+            return Err(StoreError::CorruptLog {
+                msg: format!("unknown op code: {} at offset {record_start}", op[0])
+            });
+        }
+    };
+
+    let (seq, timestamp_ms) = match op {
+        OP_SET | OP_DEL => (None, None),
+        OP_SET_SEQ | OP_DEL_SEQ | OP_CHUNK | OP_SET_CHUNKED => (Some(read_u64(r)?), Some(read_u64(r)?)),
+        other => {
+            return Err(StoreError::CorruptLog {
+                msg: format!("unknown op code: {other} at offset {record_start}")
+            });
         }
+    };
+
+    let key_len = read_u32(r)? as usize;
+    if key_len == 0 || key_len > limits.max_key_len {
+        return Err(StoreError::CorruptLog {
+            msg: format!("invalid key length {key_len} at offset {record_start} during replay")
+        });
+    }
 
-        // At this point, UnexpectedEof means a torn record -> truncate
-        let res: Result<()> = (|| {
-            let key_len = read_u32(&mut r)? as usize;
-            if key_len == 0 || key_len > MAX_KEY_LEN {
+    let mut key = vec![0u8; key_len];
+    r.read_exact(&mut key)?;
+
+    let value = match op {
+        OP_SET | OP_SET_SEQ | OP_CHUNK | OP_SET_CHUNKED => {
+            let val_len = read_u32(r)? as usize;
+            let valid = match op {
+                OP_CHUNK => val_len > 8 && val_len <= 8 + CHUNK_SIZE,
+                OP_SET_CHUNKED => val_len == 8,
+                _ => val_len <= limits.max_val_len,
+            };
+            if !valid {
                 return Err(StoreError::CorruptLog {
-                    msg: format!("invalid key length {key_len} at offset {record_start} during replay")
+                    msg: format!("invalid value length {val_len} at offset {record_start} during replay")
                 });
             }
+            let mut val = vec![0u8; val_len];
+            r.read_exact(&mut val)?;
+            if flag != 0 {
+                val = decompress_record_value(op, flag, val, limits)
+                    .map_err(|msg| StoreError::CorruptLog { msg: format!("{msg} at offset {record_start}") })?;
+            }
+            Some(val)
+        }
+        _ => None,
+    };
 
-            let mut key = vec![0u8; key_len];
-            r.read_exact(&mut key)?;
+    Ok(Some(LogRecord { namespace: String::new(), op, seq, timestamp_ms, key, value }))
+}
 
-            match op[0] {
-                OP_SET => {
-                    let val_len = read_u32(&mut r)? as usize;
-                    if val_len > MAX_VAL_LEN {
-                        return Err(StoreError::CorruptLog { 
-                            msg: format!("invalid value length {val_len} at offset {record_start} during replay")
-                        });
-                    }
-                    let mut val = vec![0u8; val_len];
-                    r.read_exact(&mut val)?;
-                    index.insert(key, Arc::new(val));
-                    Ok(())
+// (takes a dyn Read so read_record isn't instantiated for an ever deeper Chain)
+fn read_namespaced_record(r: &mut dyn Read, record_start: u64, limits: &Limits) -> Result<LogRecord> {
+    let corrupt = |what: &str| StoreError::CorruptLog {
+        msg: format!("{what} at offset {record_start} during replay"),
+    };
+    let mut len = [0u8; 1];
+    r.read_exact(&mut len)?;
+    let mut name = vec![0u8; len[0] as usize];
+    r.read_exact(&mut name)?;
+    let name = String::from_utf8(name).map_err(|_| corrupt("invalid namespace name"))?;
+    if namespace_key(&name).ok() != Some(name.as_str()) {
+        return Err(corrupt("invalid namespace name"));
+    }
+
+    // only a plain sequenced record goes inside; sealing and framing go around the prefix
+    let mut op = [0u8; 1];
+    r.read_exact(&mut op)?;
+    if !matches!(op[0] & !FLAG_MASK, OP_SET_SEQ | OP_DEL_SEQ | OP_CHUNK | OP_SET_CHUNKED) {
+        return Err(corrupt(&format!("op code {} in a namespaced record", op[0])));
+    }
+    let mut inner: &mut dyn Read = &mut io::Cursor::new(op).chain(r);
+    let record = read_record(&mut inner, record_start, limits, None, false)?
+        .ok_or_else(|| corrupt("empty namespaced record"))?;
+    Ok(LogRecord { namespace: name, ..record })
+}
+
+fn read_sealed_record<R: Read>(
+    r: &mut R,
+    record_start: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+) -> Result<LogRecord> {
+    let Some(key) = key else {
+        return Err(StoreError::Encryption {
+            msg: format!("record at offset {record_start} is encrypted and no key was given"),
+        });
+    };
+
+    let max_len = SEAL_OVERHEAD + NAMESPACE_OVERHEAD + 1 + 8 + 8 + 4 + limits.max_key_len + 4 + limits.max_val_len.max(8 + CHUNK_SIZE);
+    let len = read_u32(r)? as usize;
+    if len < SEAL_OVERHEAD || len > max_len {
+        return Err(StoreError::CorruptLog {
+            msg: format!("invalid sealed record length {len} at offset {record_start} during replay")
+        });
+    }
+    let mut sealed = vec![0u8; len];
+    r.read_exact(&mut sealed)?;
+
+    let plain = key.open(AAD_LOG, record_start, &sealed).ok_or_else(|| StoreError::CorruptLog {
+        msg: format!("record at offset {record_start} failed authentication (tampered, or sealed by another key)"),
+    })?;
+    let mut rest = plain.as_slice();
+    match read_record(&mut rest, record_start, limits, None, false) {
+        Ok(Some(record)) if rest.is_empty() => Ok(record),
+        Err(e) if e.is_corrupt_log() => Err(e),
+        _ => Err(StoreError::CorruptLog { msg: format!("malformed sealed record at offset {record_start}") }),
+    }
+}
+
+fn read_checked_record<R: Read>(
+    r: &mut R,
+    record_start: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+) -> Result<Option<LogRecord>> {
+    let max_len = 1 + 4 + SEAL_OVERHEAD + NAMESPACE_OVERHEAD + 1 + 8 + 8 + 4 + limits.max_key_len + 4 + limits.max_val_len.max(8 + CHUNK_SIZE);
+    let len = read_u32(r)? as usize;
+    let crc = read_u32(r)?;
+
+    let mut record = Vec::new();
+    if len > 0 && len <= max_len {
+        r.take(len as u64).read_to_end(&mut record)?;
+    }
+    if record.len() != len || record.is_empty() || crc32fast::hash(&record) != crc {
+        // a torn append is the last thing in the log, with nothing but zeros after it
+        if zeros_to_end(r)? {
+            return Err(StoreError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("torn record at offset {record_start}"),
+            )));
+        }
+        return Err(StoreError::CorruptLog {
+            msg: format!("checksum mismatch in record at offset {record_start}"),
+        });
+    }
+
+    let mut rest = record.as_slice();
+    match read_record(&mut rest, record_start, limits, key, false) {
+        Ok(Some(record)) if rest.is_empty() => Ok(Some(record)),
+        Err(e) if e.is_corrupt_log() || matches!(e, StoreError::Encryption { .. }) => Err(e),
+        _ => Err(StoreError::CorruptLog { msg: format!("malformed record at offset {record_start}") }),
+    }
+}
+
+// A preallocated log frames every record (OP_CHECKED) and is zeros past the last one, so
+// its first byte says whether zeros running to the end are unused space or damage. Looks at
+// the start of `r` without consuming anything.
+pub(crate) fn is_preallocated_log<R: BufRead>(r: &mut R) -> Result<bool> {
+    Ok(matches!(r.fill_buf()?.first(), Some(&(0 | OP_CHECKED))))
+}
+
+// true if `r` has nothing but zeros left
+fn zeros_to_end<R: Read>(r: &mut R) -> Result<bool> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match r.read(&mut buf)? {
+            0 => return Ok(true),
+            n if buf[..n].iter().any(|&b| b != 0) => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// A chunk record's offset header is never compressed, only the data after it
+fn decompress_record_value(op: u8, flag: u8, val: Vec<u8>, limits: &Limits) -> std::result::Result<Vec<u8>, String> {
+    if op != OP_CHUNK {
+        return decompress(flag, &val, limits.max_val_len);
+    }
+    let (header, data) = val.split_at(8);
+    let mut out = header.to_vec();
+    out.extend_from_slice(&decompress(flag, data, CHUNK_SIZE)?);
+    Ok(out)
+}
+
+
+// Files an interrupted snapshot leaves in the data dir
+struct Orphans {
+    // snapshot-N.tmp / MANIFEST.tmp
+    tmp_files: Vec<PathBuf>,
+    // `data.log.<timestamp>`, oldest first
+    rotated_logs: Vec<PathBuf>,
+}
+
+fn find_orphans(vfs: &dyn Vfs, base_dir: &Path, log_path: &Path) -> Result<Orphans> {
+    let mut orphans = Orphans { tmp_files: Vec::new(), rotated_logs: Vec::new() };
+    let mut rotated = Vec::new();
+
+    let dir = if base_dir.as_os_str().is_empty() { Path::new(".") } else { base_dir };
+    for path in vfs.read_dir(dir)? {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        if name == "MANIFEST.tmp" || (name.starts_with("snapshot-") && name.ends_with(".tmp")) {
+            orphans.tmp_files.push(path);
+        } else if let Some(stamp) = rotated_log_stamp(log_path, name) {
+            rotated.push((stamp, path));
+        }
+    }
+
+    rotated.sort();
+    orphans.rotated_logs = rotated.into_iter().map(|(_, path)| path).collect();
+    Ok(orphans)
+}
+
+// rotate_log renames `data.log` to `data.log.<timestamp>`; returns the timestamp if `name` is one of those
+pub(crate) fn rotated_log_stamp(log_path: &Path, name: &str) -> Option<u64> {
+    let stem = log_path.file_stem()?.to_str()?;
+    let stamp = name.strip_prefix(stem)?.strip_prefix(".log.")?;
+    if stamp.is_empty() || !stamp.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    stamp.parse().ok()
+}
+
+struct RotatedReplay {
+    // records newer than the snapshot, now applied to the index
+    applied: u64,
+    last_seq: u64,
+    // has records without sequence numbers
+    legacy: bool,
+}
+
+// Applies the records of a rotated log that come after `snapshot_seq`. Read-only; a torn
+// tail ends the log like it would on replay.
+fn replay_rotated(
+    vfs: &dyn Vfs,
+    path: &Path,
+    snapshot_seq: u64,
+    index: &mut Indexes,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+) -> Result<RotatedReplay> {
+    let mut replay = RotatedReplay { applied: 0, last_seq: 0, legacy: false };
+    let mut r = BufReader::new(vfs.open_read(path)?);
+    let preallocated = is_preallocated_log(&mut r)?;
+    let mut pending = PendingChunks::new();
+
+    loop {
+        let record_start = r.stream_position()?;
+        let record = match read_record(&mut r, record_start, limits, key, preallocated) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) if e.is_unexpected_eof() => break,
+            Err(e) => return Err(e),
+        };
+
+        match record.seq {
+            Some(seq) if seq > snapshot_seq => {
+                replay.last_seq = replay.last_seq.max(seq);
+                replay.applied += 1;
+                record.apply(index, &mut pending)?;
+            }
+            Some(_) => {}
+            None => replay.legacy = true,
+        }
+    }
+    Ok(replay)
+}
+
+// Replays the log into `index` and returns the highest sequence number seen.
+// With `salvage`, corrupt records are skipped instead of failing the replay.
+fn replay_into(
+    file: &dyn VfsFile,
+    path: &Path,
+    index: &mut Indexes,
+    mut salvage: Option<&mut SalvageReport>,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+) -> Result<(u64, u64)> {
+
+    let reader_file = file.try_clone()?;
+    let len = reader_file.file_len()?;
+    let mut r = BufReader::new(reader_file);
+    let preallocated = is_preallocated_log(&mut r)?;
+    let mut last_seq = 0;
+    let mut pending = PendingChunks::new();
+   
+    loop {
+        let record_start = r.stream_position()?; // byte offset current record
+
+        match read_record(&mut r, record_start, limits, key, preallocated) {
+            Ok(Some(record)) => {
+                if let Some(seq) = record.seq {
+                    last_seq = last_seq.max(seq);
                 }
-                OP_DEL => {
-                    index.remove(&key);
-                    Ok(())
+                if let Some(report) = salvage.as_deref_mut() {
+                    report.recovered_records += 1;
                 }
-                other => {
-                    return Err(StoreError::CorruptLog {
-                        msg: format!("unknown op code: {other} at offset {record_start}")
-                    });
+                if let Err(e) = record.apply(index, &mut pending) {
+                    // a salvaged log can lose chunks to a skipped region; that value is gone
+                    match salvage.as_deref_mut() {
+                        Some(report) => report.lost_records += 1,
+                        None => return Err(e),
+                    }
                 }
             }
-        })();
-
-        match res {
-            Ok(()) => continue,
+            // the logical end: EOF, or where preallocated zeros start
+            Ok(None) => return Ok((last_seq, record_start)),
             Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 // Crash-safe tail handling: truncate torn record
-                r.get_ref().set_len(record_start)?;
+                // (a salvaged log is moved aside untouched, so leave it alone)
+                if salvage.as_ref().is_none_or(|report| report.is_clean()) {
+                    r.get_ref().set_len(record_start)?;
+                    return Ok((last_seq, record_start));
+                }
                 break;
             }
+            Err(StoreError::CorruptLog { .. }) if salvage.is_some() => {
+                let report = salvage.as_deref_mut().unwrap();
+                match find_next_record(&mut r, record_start + 1, len, last_seq, limits, key, preallocated)? {
+                    Some((resume_at, next_seq)) => {
+                        report.skip(path, record_start, resume_at - record_start);
+                        if let Some(next) = next_seq
+                            && last_seq > 0 {
+                            report.lost_records += next.saturating_sub(last_seq + 1);
+                        }
+                        r.seek(SeekFrom::Start(resume_at))?;
+                    }
+                    None => {
+                        report.skip(path, record_start, len - record_start);
+                        break;
+                    }
+                }
+            }
             Err(e) => return Err(e),
         }
-        
     }
 
-    Ok(())
+    Ok((last_seq, len))
+}
+
+// Scans forward from `from` for the next offset that holds a plausible record: it decodes,
+// its seq (if any) is past `last_seq`, and the record after it decodes too (or is the end of the log).
+// Returns the offset and the seq of the record found there.
+pub(crate) fn find_next_record<R: Read + Seek>(
+    r: &mut R,
+    from: u64,
+    len: u64,
+    last_seq: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    preallocated: bool,
+) -> Result<Option<(u64, Option<u64>)>> {
+    for offset in from..len {
+        r.seek(SeekFrom::Start(offset))?;
+        let record = match read_record(r, offset, limits, key, preallocated) {
+            Ok(Some(record)) => record,
+            // nothing but EOF or zeros from here on
+            Ok(None) => return Ok(None),
+            Err(_) => continue,
+        };
+        if record.seq.is_some_and(|seq| seq <= last_seq) {
+            continue;
+        }
+
+        let next_start = r.stream_position()?;
+        let confirmed = match read_record(r, next_start, limits, key, preallocated) {
+            Ok(Some(next)) => match (record.seq, next.seq) {
+                (Some(seq), Some(next_seq)) => next_seq > seq,
+                _ => true,
+            },
+            Ok(None) => true,
+            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => true,
+            Err(_) => false,
+        };
+        if confirmed {
+            return Ok(Some((offset, record.seq)));
+        }
+    }
+    Ok(None)
+}
+
+// What a namespace is called internally: "" for DEFAULT_NAMESPACE, else `name` itself if it's
+// 1 to 255 of [A-Za-z0-9_.:-]
+pub(crate) fn namespace_key(name: &str) -> Result<&str> {
+    if name == DEFAULT_NAMESPACE {
+        return Ok("");
+    }
+    let valid = |c: u8| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b':' | b'-');
+    if name.is_empty() || name.len() > MAX_NAMESPACE_LEN || !name.bytes().all(valid) {
+        return Err(StoreError::InvalidInput {
+            msg: format!("invalid namespace name {name:?} (1 to {MAX_NAMESPACE_LEN} of A-Z a-z 0-9 _ . : -)"),
+        });
+    }
+    Ok(name)
 }
 
-fn validate_kv(key: &[u8], val: Option<&[u8]>) -> Result<()> {
+// The OP_NAMESPACED prefix of a record in namespace `ns`; nothing for the default one
+fn push_namespace(record: &mut Vec<u8>, ns: &str) {
+    if !ns.is_empty() {
+        record.push(OP_NAMESPACED);
+        record.push(ns.len() as u8);
+        record.extend_from_slice(ns.as_bytes());
+    }
+}
+
+pub(crate) fn validate_kv(key: &[u8], val: Option<&[u8]>, limits: &Limits) -> Result<()> {
     if key.is_empty() { 
         return Err(StoreError::InvalidInput { msg: "key cannot be empty".into() });
     }
-    if key.len() > MAX_KEY_LEN {
-        return Err(StoreError::InvalidInput { msg: format!("key too large (>{MAX_KEY_LEN} bytes)") });
+    if key.len() > limits.max_key_len {
+        return Err(StoreError::InvalidInput {
+            msg: format!("key too large ({} bytes, limit is {} bytes)", key.len(), limits.max_key_len),
+        });
     }
-    if let Some(v) = val {
-        if v.len() > MAX_VAL_LEN {
-            return Err(StoreError::InvalidInput { msg: format!("value too large (>{MAX_VAL_LEN} bytes)") });
-        }
+    if let Some(v) = val
+        && v.len() > limits.max_val_len {
+        return Err(StoreError::InvalidInput {
+            msg: format!("value too large ({} bytes, limit is {} bytes)", v.len(), limits.max_val_len),
+        });
     }
     Ok(())
 }
 
-// Write snapshot view to disk
-// called from backgroun thread after getting the view
+// Lengths are stored as u32, so that's as far as the limits go (less two bits for keys,
+// which snapshots use for the compression flag)
+fn check_limits(limits: &Limits) -> Result<()> {
+    if limits.max_key_len == 0 {
+        return Err(StoreError::InvalidInput { msg: "max key length must be at least 1 byte".into() });
+    }
+    if limits.max_key_len > MAX_KEY_LIMIT {
+        return Err(StoreError::InvalidInput { msg: format!("max key length can't exceed {MAX_KEY_LIMIT} bytes") });
+    }
+    if limits.max_key_len > u32::MAX as usize || limits.max_val_len > u32::MAX as usize {
+        return Err(StoreError::InvalidInput { msg: format!("size limits can't exceed {} bytes", u32::MAX) });
+    }
+    Ok(())
+}
+
+// Checks the key a store is opened with against the MANIFEST's key check, and returns the
+// key check the dir has from now on. A dir with data can only change keys through rekey.
+pub(crate) fn check_key(
+    base_dir: &Path,
+    key_check: Option<&[u8]>,
+    encryption: Option<&EncryptionKey>,
+    has_data: bool,
+) -> Result<Option<Vec<u8>>> {
+    let dir = base_dir.display();
+    let msg = match (key_check, encryption) {
+        (Some(check), Some(key)) if key.matches(check) => return Ok(Some(check.to_vec())),
+        (Some(_), Some(_)) => format!("wrong key for the encrypted data dir {dir}"),
+        (Some(_), None) => format!("data dir {dir} is encrypted; open it with its key (--key-file or KVS_KEY)"),
+        (None, Some(_)) if has_data => format!("data dir {dir} isn't encrypted; encrypt it with `kvs rekey` first"),
+        (None, Some(key)) => return Ok(Some(key.key_check())),
+        (None, None) => return Ok(None),
+    };
+    Err(StoreError::Encryption { msg })
+}
+
+// Write snapshot view to disk, in the sorted block format (see sst.rs)
+// called from backgroun thread after getting the view.
+// Returns the block bytes written before and after compression.
 pub fn write_snapshot(
-    view: HashMap<Vec<u8>, Arc<Vec<u8>>>,
+    vfs: &dyn Vfs,
+    view: Indexes,
     snapshot_path: &Path,
-) -> Result<()> {
+    compression: Compression,
+    encryption: Option<&EncryptionKey>,
+) -> Result<(u64, u64)> {
     // Ensure parent directory exists
     if let Some(parent) = snapshot_path.parent() {
-        std::fs::create_dir_all(parent)?;
+        vfs.create_dir_all(parent)?;
     }
 
     // Open temporary file for atomi writing
     let tmp_path = snapshot_path.with_extension("tmp");
-    let file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(&tmp_path)?;
-
-    let mut writer = BufWriter::new(file);
-
-    // snapshot format is same as log format
-    // [key_len: u32][key: bytes][val_len: u32][val: bytes]
-    for (key, val_arc) in view.iter() {
-        // write key
-        write_u32(&mut writer, key.len() as u32)?;
-        writer.write_all(key)?;
-
-        //write value
-        write_u32(&mut writer, val_arc.len() as u32)?;
-        writer.write_all(val_arc.as_slice())?;
-    }
-
-    // flush and sync
-    writer.flush()?;
-    writer.get_ref().sync_all()?;
+    let file = vfs.create(&tmp_path)?;
+
+    // only a store that has used namespaces needs the namespaced format
+    let namespaced = view.iter().any(|(ns, index)| !ns.is_empty() && !index.is_empty());
+    let mut entries: Vec<_> = view.iter()
+        .flat_map(|(ns, index)| index.iter().map(move |(key, val)| match namespaced {
+            true => (Cow::Owned(namespaced_key(ns, key)), val),
+            false => (Cow::Borrowed(key.as_slice()), val),
+        }))
+        .collect();
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let written = (|| -> Result<(u64, u64)> {
+        let w = BufWriter::new(file);
+        let mut sst = match namespaced {
+            true => SstWriter::new_namespaced(w, compression, encryption.cloned())?,
+            false => SstWriter::new(w, compression, encryption.cloned())?,
+        };
+        for (key, val_arc) in entries {
+            sst.add(&key, val_arc.as_slice())?;
+        }
+        let (mut writer, value_bytes, stored_value_bytes) = sst.finish()?;
+
+        // flush and sync
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok((value_bytes, stored_value_bytes))
+    })();
+    // a snapshot that didn't fit (ENOSPC) shouldn't leave its partial .tmp taking up the space
+    let (value_bytes, stored_value_bytes) = written.inspect_err(|_| {
+        let _ = vfs.remove_file(&tmp_path);
+    })?;
 
     // atomically rename temp file to final snapshot
-    std::fs::rename(&tmp_path, snapshot_path)
+    vfs.rename(&tmp_path, snapshot_path)
         .map_err(|e| {
             StoreError::Io(io::Error::new(
                 e.kind(),
@@ -441,41 +1963,80 @@ pub fn write_snapshot(
                     tmp_path, snapshot_path, e)
             ))
         })?;
+    // the rename itself is only durable once the directory is synced
+    vfs.sync_dir(parent_dir(snapshot_path))?;
 
-    Ok(())
+    Ok((value_bytes, stored_value_bytes))
 
 
 }
 
-// write manifest file that tracks current snapshot/log
+// write manifest file that tracks current snapshot/log.
 pub fn write_manifest(
+    vfs: &dyn Vfs,
     manifest_path: &Path,
     snapshot_meta: &SnapshotMeta,
 ) -> Result<()> {
-   let mut file = OpenOptions::new()
-    .create(true)
-    .truncate(true)
-    .write(true)
-    .open(manifest_path)?;
-
-    writeln!(
-        &mut file,
-        "{}:{}:{}",
-        snapshot_meta.snapshot_number,
-        snapshot_meta.snapshot_path.display(),
-        snapshot_meta.log_path.display()
-
-    )?;
+    write_manifest_file(vfs, manifest_path, snapshot_meta)?;
     println!("snapshot saved to {}", snapshot_meta.snapshot_path.display());
+    Ok(())
+}
 
+// Written to MANIFEST.tmp and renamed over the old one, so a crash leaves either the old
+// or the new MANIFEST, never a partial one. The snapshot and log must live next to the
+// MANIFEST and only their file names are stored, so the whole dir can be moved or copied elsewhere.
+//   {"version":2,"snapshot_number":3,"snapshot":"snapshot-0003.snap","log":"data.log","last_seq":42,
+//    "max_key_len":1024,"max_val_len":1048576}
+// plus "key_check" (base64) in an encrypted data dir.
+fn write_manifest_file(vfs: &dyn Vfs, manifest_path: &Path, snapshot_meta: &SnapshotMeta) -> Result<()> {
+    let dir = base_dir_for(manifest_path);
+    let relative = |path: &Path| -> Result<String> {
+        // a relative --log like logs/data.log names the same dir as its canonical base dir
+        if base_dir_for(path) != dir {
+            return Err(StoreError::InvalidInput {
+                msg: format!("{} is not in the MANIFEST's dir {}", path.display(), dir.display()),
+            });
+        }
+        let name = path.file_name().and_then(|name| name.to_str());
+        name.map(str::to_string).ok_or_else(|| StoreError::InvalidInput {
+            msg: format!("path {} is not valid UTF-8", path.display()),
+        })
+    };
+
+    let mut manifest = serde_json::json!({
+        "version": MANIFEST_VERSION,
+        "snapshot_number": snapshot_meta.snapshot_number,
+        "snapshot": relative(&snapshot_meta.snapshot_path)?,
+        "log": relative(&snapshot_meta.log_path)?,
+        "last_seq": snapshot_meta.last_seq,
+        "max_key_len": snapshot_meta.limits.max_key_len,
+        "max_val_len": snapshot_meta.limits.max_val_len,
+    });
+    if let Some(key_check) = &snapshot_meta.key_check {
+        manifest["key_check"] = BASE64.encode(key_check).into();
+    }
+
+    let tmp_path = manifest_path.with_extension("tmp");
+    let mut file = vfs.create(&tmp_path)?;
+
+    writeln!(&mut file, "{manifest}")?;
     file.sync_all()?;
+    drop(file);
 
+    vfs.rename(&tmp_path, manifest_path)?;
+    vfs.sync_dir(parent_dir(manifest_path))?;
     Ok(())
 }
 
-fn cleanup_old_snapshots(base_dir: &Path, current_num: u64) -> Result<()> {
-    use std::fs;
+// Directory holding `path`; a bare file name lives in the current directory
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
 
+fn cleanup_old_snapshots(vfs: &dyn Vfs, base_dir: &Path, current_num: u64) -> Result<()> {
     let base_dir = if base_dir == Path::new(".") {
         std::env::current_dir()?
     } else {
@@ -488,49 +2049,49 @@ fn cleanup_old_snapshots(base_dir: &Path, current_num: u64) -> Result<()> {
             .unwrap_or_else(|_| base_dir.to_path_buf())
     };
 
-    let entries = fs::read_dir(base_dir)?;
-
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
-
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if name.starts_with("snapshot-") && name.ends_with(".snap") {
-                // extract number
-                if let Some(num_str) = name.strip_prefix("snapshot-")
-                    .and_then(|s| s.strip_suffix(".snap"))
-                {
-                    if let Ok(num) = num_str.parse::<u64>() {
-                        // delete snapshot if older than current number
-                        if num < current_num {
-                            fs::remove_file(&path)?;
-                        }
-                    }
-                }
-            }
+    for path in vfs.read_dir(&base_dir)? {
+        if let Some(name) = path.file_name().and_then(|n| n.to_str())
+            && name.starts_with("snapshot-") && name.ends_with(".snap")
+            // extract number
+            && let Some(num_str) = name.strip_prefix("snapshot-")
+                .and_then(|s| s.strip_suffix(".snap"))
+            && let Ok(num) = num_str.parse::<u64>()
+            // delete snapshot if older than current number
+            && num < current_num {
+            vfs.remove_file(&path)?;
         }
     }
 
     Ok(())
 }
 
-fn read_manifest(manifest_path: &Path) -> Result<Option<SnapshotMeta>> {
-    use std::io::BufRead;
-    if !manifest_path.exists() {
+pub(crate) fn read_manifest(vfs: &dyn Vfs, manifest_path: &Path) -> Result<Option<SnapshotMeta>> {
+    Ok(read_manifest_with_version(vfs, manifest_path)?.map(|(meta, _)| meta))
+}
+
+// Also returns the format version found, so Store::open can migrate legacy manifests.
+// Paths come back resolved against the MANIFEST's directory.
+fn read_manifest_with_version(vfs: &dyn Vfs, manifest_path: &Path) -> Result<Option<(SnapshotMeta, u64)>> {
+    if !vfs.exists(manifest_path) {
         return Ok(None);
     }
 
-    let file = File::open(manifest_path)?;
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
+    let mut contents = String::new();
+    vfs.open_read(manifest_path)?.read_to_string(&mut contents)?;
+    let contents = contents.trim();
+    let dir = manifest_path.parent().unwrap_or(Path::new(""));
 
-    reader.read_line(&mut line)?;
-    let line = line.trim();
+    if contents.starts_with('{') {
+        return parse_manifest_json(contents, dir).map(|meta| Some((meta, MANIFEST_VERSION)));
+    }
 
+    // legacy: a single `number:snapshot_path:log_path[:last_seq]` line with absolute paths
+    // (manifests written before sequence numbers have no 4th part)
+    let line = contents.lines().next().unwrap_or_default();
     let parts: Vec<&str> = line.split(':').collect();
-    if parts.len() != 3 {
+    if parts.len() != 3 && parts.len() != 4 {
         return Err(StoreError::CorruptLog { 
-            msg: format!("Invalid MANIFEST format: expected 3 colon-seperated parts, got {}", parts.len())
+            msg: format!("Invalid MANIFEST format: expected 3 or 4 colon-seperated parts, got {}", parts.len())
         });
     }
 
@@ -539,56 +2100,277 @@ fn read_manifest(manifest_path: &Path) -> Result<Option<SnapshotMeta>> {
             msg: format!("invalid snapshot number in MANIFEST: {e}")
         })?;
 
-    let snapshot_path = PathBuf::from(parts[1]);
-    let log_path = PathBuf::from(parts[2]);
+    // the snapshot and log always live next to the MANIFEST, wherever the dir has been moved to
+    let relocate = |p: &str| match Path::new(p).file_name() {
+        Some(name) => dir.join(name),
+        None => PathBuf::from(p),
+    };
+    let snapshot_path = relocate(parts[1]);
+    let log_path = relocate(parts[2]);
+
+    let last_seq: u64 = match parts.get(3) {
+        Some(p) => p.parse()
+            .map_err(|e| StoreError::CorruptLog {
+                msg: format!("invalid sequence number in MANIFEST: {e}")
+            })?,
+        None => 0,
+    };
+
+    let limits = Limits::default();
+    Ok(Some((SnapshotMeta { snapshot_number, snapshot_path, log_path, last_seq, limits, key_check: None }, 1)))
+}
+
+fn parse_manifest_json(contents: &str, dir: &Path) -> Result<SnapshotMeta> {
+    let invalid = |msg: String| StoreError::CorruptLog { msg: format!("invalid MANIFEST: {msg}") };
+
+    let value: serde_json::Value = serde_json::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+    let number = |name: &str| value.get(name)
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| invalid(format!("missing number field {name:?}")));
+    // a file in the data dir, named without any directory
+    let path = |name: &str| {
+        let p = value.get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| invalid(format!("missing string field {name:?}")))?;
+        match Path::new(p).file_name() {
+            Some(file_name) if file_name == p => Ok(dir.join(p)),
+            _ => Err(invalid(format!("field {name:?} is not a file name: {p:?}"))),
+        }
+    };
+
+    let version = number("version")?;
+    if version > MANIFEST_VERSION {
+        return Err(invalid(format!("version {version} is newer than this build supports ({MANIFEST_VERSION})")));
+    }
+    let key_check = match value.get("key_check") {
+        None => None,
+        Some(v) => Some(v.as_str()
+            .and_then(|check| BASE64.decode(check).ok())
+            .ok_or_else(|| invalid("invalid field \"key_check\"".into()))?),
+    };
 
-    Ok(Some(SnapshotMeta { snapshot_number, snapshot_path, log_path }))
+    Ok(SnapshotMeta {
+        snapshot_number: number("snapshot_number")?,
+        snapshot_path: path("snapshot")?,
+        log_path: path("log")?,
+        last_seq: number("last_seq")?,
+        limits: Limits {
+            max_key_len: number("max_key_len")? as usize,
+            max_val_len: number("max_val_len")? as usize,
+        },
+        key_check,
+    })
 }
 
-fn load_snapshot(
+// Reads one entry of an unsorted snapshot starting at `entry_start`; Ok(None) at EOF.
+// [key_len: u32][key: bytes][val_len: u32][val: bytes], the top two bits of key_len being the
+// compression flag for `val`; a sealed entry is that, sealed whole with `entry_start` as
+// associated data, behind SEALED_ENTRY.
+pub(crate) fn read_snapshot_entry<R: Read>(
+    r: &mut R,
+    entry_start: u64,
+    limits: &Limits,
+    encryption: Option<&EncryptionKey>,
+) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let (key_len, flag) = match read_u32(r) {
+        Ok(SEALED_ENTRY) => return read_sealed_entry(r, entry_start, limits, encryption).map(Some),
+        Ok(_) if encryption.is_some() => {
+            return Err(StoreError::CorruptLog {
+                msg: format!("unencrypted entry at offset {entry_start} in an encrypted snapshot"),
+            });
+        }
+        Ok(len) => ((len & !SNAPSHOT_FLAG_BITS) as usize, (len >> 24) as u8),
+        Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if key_len == 0 || key_len > limits.max_key_len {
+        return Err(StoreError::CorruptLog {
+             msg: format!("Invalid key length {key_len} in snapshot at offset {entry_start}") 
+            });
+    }
+
+    // Read key
+    let mut key = vec![0u8; key_len];
+    r.read_exact(&mut key)?;
+
+    let val_len = read_u32(r)? as usize;
+    if val_len > limits.max_val_len {
+        return Err(StoreError::CorruptLog {
+             msg: format!("Invalid value length {val_len} in snapshot at offset {entry_start}") 
+            });
+    }
+
+    // read val
+    let mut val = vec![0u8; val_len];
+    r.read_exact(&mut val)?;
+    if flag != 0 {
+        val = decompress(flag, &val, limits.max_val_len).map_err(|msg| StoreError::CorruptLog {
+            msg: format!("{msg} in snapshot at offset {entry_start}"),
+        })?;
+    }
+
+    Ok(Some((key, val)))
+}
+
+fn read_sealed_entry<R: Read>(
+    r: &mut R,
+    entry_start: u64,
+    limits: &Limits,
+    encryption: Option<&EncryptionKey>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let Some(encryption) = encryption else {
+        return Err(StoreError::Encryption {
+            msg: format!("snapshot entry at offset {entry_start} is encrypted and no key was given"),
+        });
+    };
+
+    let len = read_u32(r)? as usize;
+    if len < SEAL_OVERHEAD || len > SEAL_OVERHEAD + 4 + limits.max_key_len + 4 + limits.max_val_len {
+        return Err(StoreError::CorruptLog {
+            msg: format!("invalid sealed entry length {len} in snapshot at offset {entry_start}")
+        });
+    }
+    let mut sealed = vec![0u8; len];
+    r.read_exact(&mut sealed)?;
+
+    let plain = encryption.open(AAD_SNAPSHOT, entry_start, &sealed).ok_or_else(|| StoreError::CorruptLog {
+        msg: format!("snapshot entry at offset {entry_start} failed authentication (tampered, or sealed by another key)"),
+    })?;
+    let mut rest = plain.as_slice();
+    match read_snapshot_entry(&mut rest, entry_start, limits, None) {
+        Ok(Some(entry)) if rest.is_empty() => Ok(entry),
+        Err(e) if e.is_corrupt_log() => Err(e),
+        _ => Err(StoreError::CorruptLog { msg: format!("malformed sealed entry in snapshot at offset {entry_start}") }),
+    }
+}
+
+pub(crate) fn load_snapshot(
+    vfs: &dyn Vfs,
     snapshot_path: &Path,
-    index: &mut HashMap<Vec<u8>, Arc<Vec<u8>>>
+    index: &mut Indexes,
+    salvage: Option<&mut SalvageReport>,
+    limits: &Limits,
+    encryption: Option<&EncryptionKey>,
 ) -> Result<()> {
-    if !snapshot_path.exists() {
+    if !vfs.exists(snapshot_path) {
         return Ok(());
     }
+    if is_sorted_snapshot(vfs, snapshot_path)? {
+        return load_sorted_snapshot(vfs, snapshot_path, index, salvage, limits, encryption);
+    }
 
-    let file = File::open(snapshot_path)?;
-    let mut reader = BufReader::new(file);
+    // parse straight out of a map when the vfs can give us one: entries are copied out of it
+    // directly, with no buffer refills or read calls in between
+    // (it predates namespaces, so everything in it is in the default one)
+    let index = index.entry(String::new()).or_default();
+    if let Some(map) = vfs.map(snapshot_path)? {
+        return load_legacy_snapshot(&mut io::Cursor::new(&map[..]), map.len() as u64, snapshot_path, index, salvage, limits, encryption);
+    }
+    let file = vfs.open_read(snapshot_path)?;
+    let len = file.file_len()?;
+    load_legacy_snapshot(&mut BufReader::new(file), len, snapshot_path, index, salvage, limits, encryption)
+}
 
+// Has to be read front to back, since entries have no markers to find the next one by
+fn load_legacy_snapshot<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+    snapshot_path: &Path,
+    index: &mut Index,
+    salvage: Option<&mut SalvageReport>,
+    limits: &Limits,
+    encryption: Option<&EncryptionKey>,
+) -> Result<()> {
     // read key-value pairs until EOF
     loop {
-        let key_len = match read_u32(&mut reader) {
-            Ok(len) => len as usize,
-            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+        let entry_start = reader.stream_position()?;
+        match read_snapshot_entry(reader, entry_start, limits, encryption) {
+            Ok(Some((key, val))) => { index.insert(key, Arc::new(val)); }
+            Ok(None) => break,
+            // snapshot entries have no markers to resync on, so salvage keeps what came before
+            Err(e) if salvage.is_some() && (e.is_corrupt_log() || e.is_unexpected_eof()) => {
+                salvage.unwrap().skip(snapshot_path, entry_start, len - entry_start);
                 break;
             }
             Err(e) => return Err(e),
-        };
-
-        if key_len == 0 || key_len > MAX_KEY_LEN {
-            return Err(StoreError::CorruptLog {
-                 msg: format!("Invalid key length {key_len} in snapshot") 
-                });
         }
+    }
 
-        // Read key
-        let mut key = vec![0u8; key_len];
-        reader.read_exact(&mut key)?;
+    Ok(())
+}
 
-        let val_len = read_u32(&mut reader)? as usize;
-        if val_len > MAX_VAL_LEN {
-            return Err(StoreError::CorruptLog {
-                 msg: format!("Invalid value length {val_len} in snapshot") 
-                });
+fn load_sorted_snapshot(
+    vfs: &dyn Vfs,
+    snapshot_path: &Path,
+    index: &mut Indexes,
+    mut salvage: Option<&mut SalvageReport>,
+    limits: &Limits,
+    encryption: Option<&EncryptionKey>,
+) -> Result<()> {
+    // The fast path: map the file and decode its blocks on every core. Any trouble (including
+    // a damaged snapshot, which salvage wants to go through block by block) falls back to
+    // reading it the ordinary way below.
+    if salvage.is_none()
+        && let Ok(Some(reader)) = SstReader::open_mapped(vfs, snapshot_path, limits, encryption)
+    {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let parts = reader.read_blocks_parallel(threads, Indexes::new, |part, namespace, key, val| {
+            insert_snapshot_entry(part, namespace, key, val)
+        });
+        if let Some(Ok(parts)) = parts {
+            // no key is in two blocks, so the threads' indexes only have to be moved in
+            for part in parts {
+                for (namespace, mut entries) in part {
+                    match index.get_mut(&namespace) {
+                        Some(ns_index) => {
+                            if ns_index.len() < entries.len() {
+                                std::mem::swap(ns_index, &mut entries);
+                            }
+                            ns_index.extend(entries);
+                        }
+                        None => {
+                            index.insert(namespace, entries);
+                        }
+                    }
+                }
+            }
+            return Ok(());
         }
+    }
 
-        // read val
-        let mut val = vec![0u8; val_len];
-        reader.read_exact(&mut val)?;
+    let mut reader = match SstReader::open_in(vfs, snapshot_path, limits, encryption) {
+        Ok(reader) => reader,
+        // without the footer and index the blocks can't be found
+        Err(e) if salvage.is_some() && (e.is_corrupt_log() || e.is_unexpected_eof()) => {
+            let len = vfs.file_len(snapshot_path)?;
+            salvage.unwrap().skip(snapshot_path, 0, len);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
 
-        index.insert(key, Arc::new(val));
+    for i in 0..reader.blocks() {
+        match reader.visit_block(i, |namespace, key, val| insert_snapshot_entry(index, namespace, key, val)) {
+            Ok(()) => {}
+            // unlike the legacy format, the index says where the next block starts
+            Err(e) if salvage.is_some() && (e.is_corrupt_log() || e.is_unexpected_eof()) => {
+                let (offset, len) = reader.block_span(i);
+                salvage.as_deref_mut().unwrap().skip(snapshot_path, offset, len);
+            }
+            Err(e) => return Err(e),
+        }
     }
-
     Ok(())
-}
\ No newline at end of file
+}
+
+// Copies a snapshot entry, as it lies in its block, into its namespace's index; the only
+// copy it gets on the way in
+fn insert_snapshot_entry(index: &mut Indexes, namespace: &str, key: &[u8], val: &[u8]) {
+    if !index.contains_key(namespace) {
+        index.insert(namespace.to_string(), Index::new());
+    }
+    index.get_mut(namespace).unwrap().insert(key.to_vec(), Arc::new(val.to_vec()));
+}
@@ -0,0 +1,123 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use crate::vfs::VfsFile;
+
+// Background fsync for the interval durability modes.
+// The store flushes every commit to the OS and marks the log dirty; the timer thread
+// fsyncs it at most `interval` later. A failed background fsync is handed back to the
+// store and returned from its next write.
+pub(crate) struct SyncTimer {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+    syncs: AtomicU64,
+}
+
+struct State {
+    log: Arc<dyn VfsFile>,
+    dirty: bool,
+    stop: bool,
+    error: Option<io::Error>,
+}
+
+impl SyncTimer {
+    pub(crate) fn start(log: &dyn VfsFile, interval: Duration) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State { log: Arc::from(log.try_clone()?), dirty: false, stop: false, error: None }),
+            wake: Condvar::new(),
+            syncs: AtomicU64::new(0),
+        });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new()
+                .name("kvs-fsync".into())
+                .spawn(move || run(&shared, interval))?
+        };
+        Ok(Self { shared, thread: Some(thread) })
+    }
+
+    // Called after each commit has been flushed to the OS
+    pub(crate) fn mark_dirty(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        state.dirty = true;
+        Ok(())
+    }
+
+    // The store just synced the log itself, nothing is pending
+    pub(crate) fn mark_clean(&self) {
+        self.shared.state.lock().unwrap().dirty = false;
+    }
+
+    // Points the timer at a new log file after rotation
+    pub(crate) fn set_log(&self, log: &dyn VfsFile) -> io::Result<()> {
+        let log = Arc::from(log.try_clone()?);
+        self.shared.state.lock().unwrap().log = log;
+        Ok(())
+    }
+
+    pub(crate) fn syncs(&self) -> u64 {
+        self.shared.syncs.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for SyncTimer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stop = true;
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Shared, interval: Duration) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        // a stop that came before this thread got to wait would otherwise sleep a whole interval
+        if !state.stop {
+            state = shared.wake.wait_timeout(state, interval).unwrap().0;
+        }
+        if state.stop {
+            if state.dirty {
+                let _ = state.log.sync_data();
+            }
+            break;
+        }
+        if !state.dirty {
+            continue;
+        }
+
+        // fsync outside the lock so writers aren't held up by it
+        state.dirty = false;
+        let log = Arc::clone(&state.log);
+        drop(state);
+        let res = log.sync_data();
+        state = shared.state.lock().unwrap();
+
+        match res {
+            Ok(()) => {
+                shared.syncs.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                // try again next tick, and let the next write know
+                state.dirty = true;
+                state.error = Some(e);
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{BufRead, Write};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::{
+    error::{Result, StoreError},
+    store::{validate_kv, Namespace, WriteBatch},
+};
+
+// Text formats for moving a keyspace between stores.
+// Every row says how its key and value are encoded: `utf8` when both are valid UTF-8, `base64` otherwise.
+//   jsonl: {"key":"user:1","value":"Alice","encoding":"utf8"}
+//   csv:   key,value,encoding   (header row, RFC 4180 quoting)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Jsonl,
+    Csv,
+}
+
+const ENC_UTF8: &str = "utf8";
+const ENC_BASE64: &str = "base64";
+
+// Streams every pair of namespace `ns` whose key starts with `prefix` to `w`, sorted by key.
+// Rows don't say which namespace they came from. Returns the number of pairs.
+pub fn export<W: Write>(ns: &Namespace, prefix: &[u8], format: DumpFormat, mut w: W) -> Result<u64> {
+    if format == DumpFormat::Csv {
+        writeln!(w, "key,value,encoding")?;
+    }
+
+    let mut count = 0;
+    for (key, val) in ns.scan_prefix(prefix) {
+        let (key, val, encoding) = match (std::str::from_utf8(key), std::str::from_utf8(val)) {
+            (Ok(k), Ok(v)) => (k.to_string(), v.to_string(), ENC_UTF8),
+            _ => (BASE64.encode(key), BASE64.encode(val), ENC_BASE64),
+        };
+
+        match format {
+            DumpFormat::Jsonl => writeln!(
+                w,
+                "{{\"key\":{},\"value\":{},\"encoding\":\"{encoding}\"}}",
+                serde_json::Value::from(key),
+                serde_json::Value::from(val),
+            )?,
+            DumpFormat::Csv => writeln!(w, "{},{},{encoding}", csv_field(&key), csv_field(&val))?,
+        }
+        count += 1;
+    }
+
+    w.flush()?;
+    Ok(count)
+}
+
+// Reads pairs from `r` and writes them to namespace `ns` in batches of `batch_size`, so each
+// batch costs one flush/fsync instead of one per key. Returns the number of pairs imported.
+pub fn import<R: BufRead>(ns: &mut Namespace, format: DumpFormat, r: R, batch_size: usize) -> Result<u64> {
+    let batch_size = batch_size.max(1);
+    let limits = ns.limits();
+    let mut batch = WriteBatch::new();
+
+    let count = read_rows(format, r, |key, val| {
+        // catch bad pairs on their own line rather than when their batch is written
+        validate_kv(&key, Some(&val), &limits)?;
+        batch.set(&key, &val);
+        if batch.len() >= batch_size {
+            ns.write(std::mem::take(&mut batch))?;
+        }
+        Ok(())
+    })?;
+
+    ns.write(batch)?;
+    Ok(count)
+}
+
+// Decodes every row of an export in `format`, calling `f` with the raw key and value.
+// Returns the number of rows.
+pub fn read_rows<R: BufRead>(
+    format: DumpFormat,
+    mut r: R,
+    mut f: impl FnMut(Vec<u8>, Vec<u8>) -> Result<()>,
+) -> Result<u64> {
+    let mut count = 0;
+    let mut line_no = 0;
+
+    if format == DumpFormat::Csv {
+        match read_csv_record(&mut r, &mut line_no)? {
+            Some(header) if header == ["key", "value", "encoding"] => {}
+            _ => return Err(invalid(1, "expected CSV header \"key,value,encoding\"")),
+        }
+    }
+
+    loop {
+        let row = match format {
+            DumpFormat::Jsonl => read_jsonl_row(&mut r, &mut line_no)?,
+            DumpFormat::Csv => match read_csv_record(&mut r, &mut line_no)? {
+                None => None,
+                Some(fields) if fields.len() == 1 && fields[0].is_empty() => continue,
+                Some(fields) => match <[String; 3]>::try_from(fields) {
+                    Ok([key, val, encoding]) => Some((key, val, encoding)),
+                    Err(fields) => {
+                        return Err(invalid(line_no, &format!("expected 3 fields, got {}", fields.len())));
+                    }
+                },
+            },
+        };
+        let Some((key, val, encoding)) = row else {
+            break;
+        };
+
+        let (key, val) = match encoding.as_str() {
+            ENC_UTF8 => (key.into_bytes(), val.into_bytes()),
+            ENC_BASE64 => (
+                BASE64.decode(&key).map_err(|e| invalid(line_no, &format!("bad base64 key: {e}")))?,
+                BASE64.decode(&val).map_err(|e| invalid(line_no, &format!("bad base64 value: {e}")))?,
+            ),
+            other => return Err(invalid(line_no, &format!("unknown encoding {other:?}"))),
+        };
+
+        f(key, val).map_err(|e| match e {
+            StoreError::InvalidInput { msg } => invalid(line_no, &msg),
+            e => e,
+        })?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn read_jsonl_row<R: BufRead>(r: &mut R, line_no: &mut u64) -> Result<Option<(String, String, String)>> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        *line_no += 1;
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let row: serde_json::Value = serde_json::from_str(&line)
+        .map_err(|e| invalid(*line_no, &format!("bad JSON: {e}")))?;
+    let field = |name: &str| row.get(name).and_then(|v| v.as_str()).map(str::to_string);
+
+    let key = field("key").ok_or_else(|| invalid(*line_no, "missing string field \"key\""))?;
+    let val = field("value").ok_or_else(|| invalid(*line_no, "missing string field \"value\""))?;
+    let encoding = field("encoding").unwrap_or_else(|| ENC_UTF8.to_string());
+    Ok(Some((key, val, encoding)))
+}
+
+// Reads one CSV record, which may span lines when a quoted field contains newlines
+fn read_csv_record<R: BufRead>(r: &mut R, line_no: &mut u64) -> Result<Option<Vec<String>>> {
+    let mut record = String::new();
+    loop {
+        if r.read_line(&mut record)? == 0 {
+            if record.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid(*line_no, "unterminated quoted field"));
+        }
+        *line_no += 1;
+        // an escaped quote ("") doesn't change the parity
+        if record.matches('"').count().is_multiple_of(2) {
+            break;
+        }
+    }
+
+    let record = record.strip_suffix('\n').unwrap_or(&record);
+    let record = record.strip_suffix('\r').unwrap_or(record);
+
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) => in_quotes = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    Ok(Some(fields))
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn invalid(line: u64, msg: &str) -> StoreError {
+    StoreError::InvalidInput { msg: format!("line {line}: {msg}") }
+}
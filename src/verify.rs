@@ -0,0 +1,311 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use crate::{
+    config::Limits,
+    crypto::EncryptionKey,
+    error::{Result, StoreError},
+    store::{
+        base_dir_for, find_next_record, is_preallocated_log, read_manifest, read_record, read_snapshot_entry,
+        rotated_log_stamp,
+    },
+    sst::{is_sorted_snapshot, SstReader},
+    vfs::RealFs,
+};
+
+// A single problem found while verifying a data dir
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub path: PathBuf,
+    // byte offset inside `path`, when the problem has one
+    pub offset: Option<u64>,
+    pub msg: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.msg)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub problems: Vec<Problem>,
+    // things worth knowing that aren't damage, like a log the snapshot already covers
+    pub notes: Vec<Problem>,
+    pub snapshot_entries: usize,
+    pub log_records: usize,
+    pub last_seq: u64,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, path: &Path, offset: Option<u64>, msg: impl Into<String>) {
+        self.problems.push(Problem { path: path.to_path_buf(), offset, msg: msg.into() });
+    }
+
+    fn note(&mut self, path: &Path, offset: Option<u64>, msg: impl Into<String>) {
+        self.notes.push(Problem { path: path.to_path_buf(), offset, msg: msg.into() });
+    }
+}
+
+// Checks the MANIFEST, snapshot and log of the data dir owning `log_path`, plus any
+// leftover files in it. Only reads; nothing on disk is modified.
+pub fn verify_data_dir(log_path: &Path) -> Result<VerifyReport> {
+    verify_data_dir_with_key(log_path, None)
+}
+
+// Like `verify_data_dir`; the snapshot and log of an encrypted data dir are only checked
+// (and authenticated) with its key
+pub fn verify_data_dir_with_key(log_path: &Path, key: Option<&EncryptionKey>) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let base_dir = base_dir_for(log_path);
+    let manifest_path = base_dir.join("MANIFEST");
+
+    let mut actual_log_path = log_path.to_path_buf();
+    let mut snapshot: Option<(u64, PathBuf)> = None;
+    let mut snapshot_seq = 0;
+    let mut limits = Limits::default();
+    let mut key_check = None;
+
+    match read_manifest(&RealFs, &manifest_path) {
+        Ok(Some(meta)) => {
+            if meta.snapshot_number > 0 && !meta.snapshot_path.exists() {
+                report.problem(&manifest_path, None, format!(
+                    "snapshot {} listed in MANIFEST does not exist", meta.snapshot_path.display()
+                ));
+            }
+            if !meta.log_path.exists() {
+                report.problem(&manifest_path, None, format!(
+                    "log {} listed in MANIFEST does not exist", meta.log_path.display()
+                ));
+            }
+            actual_log_path = meta.log_path;
+            snapshot_seq = meta.last_seq;
+            limits = meta.limits;
+            key_check = meta.key_check;
+            snapshot = Some((meta.snapshot_number, meta.snapshot_path));
+        }
+        Ok(None) => {}
+        Err(e) => report.problem(&manifest_path, None, e.to_string()),
+    }
+
+    // a key for a dir that isn't encrypted has nothing to open
+    let key = key_check.as_ref().and(key);
+    let readable = match (&key_check, key) {
+        (Some(check), Some(key)) if !key.matches(check) => {
+            report.problem(&manifest_path, None, "wrong key for this encrypted data dir (snapshot and log not checked)");
+            false
+        }
+        (Some(_), None) => {
+            report.problem(&manifest_path, None, "data dir is encrypted; snapshot and log can't be checked without its key");
+            false
+        }
+        _ => true,
+    };
+
+    if readable
+        && let Some((_, snapshot_path)) = &snapshot
+        && snapshot_path.exists() {
+        verify_snapshot(snapshot_path, &limits, key, &mut report)?;
+    }
+
+    if readable && actual_log_path.exists() {
+        verify_log(&actual_log_path, snapshot_seq, &limits, key, &mut report)?;
+    }
+
+    let current_snapshot = snapshot.as_ref().map(|(number, _)| *number).unwrap_or(0);
+    find_leftovers(&base_dir, &actual_log_path, current_snapshot, &mut report)?;
+
+    Ok(report)
+}
+
+fn verify_snapshot(path: &Path, limits: &Limits, key: Option<&EncryptionKey>, report: &mut VerifyReport) -> Result<()> {
+    if is_sorted_snapshot(&RealFs, path)? {
+        return verify_sorted_snapshot(path, limits, key, report);
+    }
+    let len = fs::metadata(path)?.len();
+    let mut r = BufReader::new(File::open(path)?);
+
+    loop {
+        let entry_start = r.stream_position()?;
+        match read_snapshot_entry(&mut r, entry_start, limits, key) {
+            Ok(Some(_)) => report.snapshot_entries += 1,
+            Ok(None) => {
+                if entry_start < len {
+                    report.problem(path, Some(entry_start), format!(
+                        "truncated entry at offset {entry_start} ({} bytes)", len - entry_start
+                    ));
+                }
+                break;
+            }
+            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                report.problem(path, Some(entry_start), format!(
+                    "truncated entry at offset {entry_start} ({} bytes)", len - entry_start
+                ));
+                break;
+            }
+            Err(StoreError::CorruptLog { msg }) => {
+                report.problem(path, Some(entry_start), format!(
+                    "{msg} ({} bytes after it not checked)", len - entry_start
+                ));
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Checks every block against its checksum and the index, one block in memory at a time
+fn verify_sorted_snapshot(
+    path: &Path,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    report: &mut VerifyReport,
+) -> Result<()> {
+    let mut reader = match SstReader::open_with_key(path, limits, key) {
+        Ok(reader) => reader,
+        Err(e) if e.is_corrupt_log() || e.is_unexpected_eof() => {
+            report.problem(path, None, format!("{e} (no blocks checked)"));
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = 0;
+    for i in 0..reader.blocks() {
+        match reader.read_block(i) {
+            Ok(block) => entries += block.len() as u64,
+            Err(StoreError::CorruptLog { msg }) => {
+                report.problem(path, Some(reader.block_span(i).0), msg);
+            }
+            Err(e) if e.is_unexpected_eof() => {
+                let (offset, len) = reader.block_span(i);
+                report.problem(path, Some(offset), format!("truncated block at offset {offset} ({len} bytes)"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if report.problems.iter().all(|p| p.path != path) && entries != reader.entries() {
+        report.problem(path, None, format!("footer says {} entries, blocks hold {entries}", reader.entries()));
+    }
+    report.snapshot_entries += entries as usize;
+    Ok(())
+}
+
+fn verify_log(
+    path: &Path,
+    snapshot_seq: u64,
+    limits: &Limits,
+    key: Option<&EncryptionKey>,
+    report: &mut VerifyReport,
+) -> Result<()> {
+    let len = fs::metadata(path)?.len();
+    let mut r = BufReader::new(File::open(path)?);
+    let preallocated = is_preallocated_log(&mut r)?;
+    let mut prev_seq = None;
+    // records the snapshot already has, from a crash between its MANIFEST and the log
+    // rotation; they can only come first
+    let mut covered = 0;
+
+    loop {
+        let record_start = r.stream_position()?;
+        match read_record(&mut r, record_start, limits, key, preallocated) {
+            Ok(Some(record)) => {
+                report.log_records += 1;
+                let Some(seq) = record.seq else {
+                    continue;
+                };
+
+                if seq <= snapshot_seq && prev_seq.is_none_or(|prev| prev <= snapshot_seq) {
+                    covered += 1;
+                } else if seq <= snapshot_seq {
+                    report.problem(path, Some(record_start), format!(
+                        "record at offset {record_start} has seq {seq}, already covered by the snapshot (seq {snapshot_seq})"
+                    ));
+                }
+                if let Some(prev) = prev_seq
+                    && seq <= prev {
+                    report.problem(path, Some(record_start), format!(
+                        "record at offset {record_start} has seq {seq}, not greater than the previous seq {prev}"
+                    ));
+                }
+                prev_seq = Some(seq);
+                report.last_seq = report.last_seq.max(seq);
+            }
+            Ok(None) => break,
+            Err(StoreError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                report.problem(path, Some(record_start), format!(
+                    "torn record at offset {record_start} ({} bytes); it would be truncated on open",
+                    len - record_start
+                ));
+                break;
+            }
+            // pick up at the next record that reads, the way salvage would
+            Err(StoreError::CorruptLog { msg }) => {
+                let last_seq = prev_seq.unwrap_or(0);
+                match find_next_record(&mut r, record_start + 1, len, last_seq, limits, key, preallocated)? {
+                    Some((resume_at, _)) => {
+                        report.problem(path, Some(record_start), format!(
+                            "{msg} ({} bytes up to the next readable record at offset {resume_at})",
+                            resume_at - record_start
+                        ));
+                        r.seek(SeekFrom::Start(resume_at))?;
+                    }
+                    None => {
+                        report.problem(path, Some(record_start), format!(
+                            "{msg} (no readable record in the {} bytes after it)", len - record_start
+                        ));
+                        break;
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if covered > 0 {
+        report.note(path, Some(0), format!(
+            "the first {covered} records are already covered by the snapshot (seq {snapshot_seq}): a crash \
+             after the snapshot was recorded but before the log was rotated; the next snapshot drops them"
+        ));
+    }
+    Ok(())
+}
+
+// Files an interrupted snapshot or backup can leave behind
+fn find_leftovers(base_dir: &Path, log_path: &Path, current_snapshot: u64, report: &mut VerifyReport) -> Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(base_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+
+    for path in entries {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+
+        if name.ends_with(".tmp") {
+            report.problem(&path, None, "orphaned temporary file from an interrupted snapshot");
+        } else if rotated_log_stamp(log_path, name).is_some() {
+            report.problem(&path, None,
+                "rotated log left behind by an interrupted snapshot; the next open replays any writes the snapshot lacks and removes it");
+        } else if let Some(number) = name.strip_prefix("snapshot-")
+            .and_then(|s| s.strip_suffix(".snap"))
+            .and_then(|n| n.parse::<u64>().ok())
+            && number != current_snapshot {
+            report.problem(&path, None, format!("stale snapshot (MANIFEST points at snapshot {current_snapshot})"));
+        } else if name.starts_with("backup-") && name.ends_with(".pin") {
+            report.problem(&path, None, "orphaned backup pin directory");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,934 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use crate::mmap::Mmap;
+
+// Everything a Store does to files and directories goes through a Vfs, so tests can run
+// one in memory, or on a filesystem that fails in chosen ways (see MemFs and FaultFs)
+pub trait Vfs: fmt::Debug + Send + Sync {
+    // read + write, created if missing, never truncated
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+    // read only; fails if missing
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+    // write, created if missing, truncated if not
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+    // replaces `to` if it exists
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    // paths of everything directly inside `dir`, in no particular order
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+    // makes creates, renames and removes inside `dir` durable
+    fn sync_dir(&self, dir: &Path) -> io::Result<()>;
+    // a second name for `src`, or a copy of it where links aren't possible
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()>;
+    fn file_len(&self, path: &Path) -> io::Result<u64>;
+    fn exists(&self, path: &Path) -> bool;
+    // the whole file mapped into memory, for reading snapshots without copying them through
+    // a buffer; None if this Vfs can't (callers then read it the ordinary way)
+    fn map(&self, _path: &Path) -> io::Result<Option<Mmap>> {
+        Ok(None)
+    }
+}
+
+// An open file. Reads and writes go through its own cursor.
+pub trait VfsFile: Read + Write + Seek + Send + Sync {
+    fn sync_data(&self) -> io::Result<()>;
+    fn sync_all(&self) -> io::Result<()>;
+    fn set_len(&self, len: u64) -> io::Result<()>;
+    // grows the file to at least `len` bytes with the space reserved up front where the
+    // filesystem can (fallocate); the new bytes read as zeros
+    fn allocate(&self, len: u64) -> io::Result<()>;
+    fn file_len(&self) -> io::Result<u64>;
+    // another handle to the same file
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>>;
+}
+
+// The real filesystem, through std::fs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect()
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        #[cfg(not(unix))]
+        let _ = dir;
+        Ok(())
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        if fs::hard_link(src, dst).is_err() {
+            fs::copy(src, dst)?;
+        }
+        Ok(())
+    }
+
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn map(&self, path: &Path) -> io::Result<Option<Mmap>> {
+        Mmap::map(&File::open(path)?)
+    }
+}
+
+impl VfsFile for File {
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        File::sync_all(self)
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn allocate(&self, len: u64) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        // mode 0 grows the file size too, unlike FALLOC_FL_KEEP_SIZE
+        let res = unsafe { libc::fallocate(self.as_raw_fd(), 0, 0, len as libc::off_t) };
+        match res {
+            0 => Ok(()),
+            _ => match io::Error::last_os_error() {
+                // filesystems without fallocate (some network ones) still get a zero-filled file
+                e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => grow(self, len),
+                e => Err(e),
+            },
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn allocate(&self, len: u64) -> io::Result<()> {
+        grow(self, len)
+    }
+
+    fn file_len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(File::try_clone(self)?))
+    }
+}
+
+// a sparse extension: zeros, but with no space set aside for them
+fn grow(file: &File, len: u64) -> io::Result<()> {
+    if file.metadata()?.len() < len {
+        file.set_len(len)?;
+    }
+    Ok(())
+}
+
+// Files kept in memory, by path; use absolute paths. Each file remembers what its last
+// fsync made durable, and each directory which files were in it at its last `sync_dir`;
+// `crash` throws the rest away, as a power cut would: unsynced data, and creates, renames,
+// links and removes the directory wasn't synced after. Directories themselves exist
+// implicitly, and creating or removing one is durable at once.
+#[derive(Debug, Clone, Default)]
+pub struct MemFs {
+    state: Arc<Mutex<MemState>>,
+}
+
+#[derive(Debug, Default)]
+struct MemState {
+    files: HashMap<PathBuf, Arc<Mutex<MemContents>>>,
+    // `files` as of each directory's last sync_dir
+    durable_files: HashMap<PathBuf, Arc<Mutex<MemContents>>>,
+    dirs: HashSet<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+struct MemContents {
+    data: Vec<u8>,
+    durable: Vec<u8>,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Current contents of `path`, synced or not
+    pub fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+        state.files.get(path).map(|contents| contents.lock().unwrap().data.clone())
+    }
+
+    // Replaces `path` with `data`, durably; for setting up (or damaging) files in tests
+    pub fn write(&self, path: &Path, data: &[u8]) {
+        let contents = Arc::new(Mutex::new(MemContents { data: data.to_vec(), durable: data.to_vec() }));
+        let mut state = self.state.lock().unwrap();
+        state.files.insert(path.to_path_buf(), Arc::clone(&contents));
+        state.durable_files.insert(path.to_path_buf(), contents);
+    }
+
+    // Loses everything written since each file's last fsync, and every file created, renamed,
+    // linked or removed since its directory's last sync_dir
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.files = state.durable_files.clone();
+        for contents in state.files.values() {
+            let mut contents = contents.lock().unwrap();
+            contents.data = contents.durable.clone();
+        }
+    }
+
+    fn handle(&self, path: &Path, create: bool, writable: bool) -> io::Result<Box<dyn VfsFile>> {
+        let mut state = self.state.lock().unwrap();
+        let contents = match state.files.get(path) {
+            Some(contents) => Arc::clone(contents),
+            None if create => {
+                let contents = Arc::new(Mutex::new(MemContents::default()));
+                state.files.insert(path.to_path_buf(), Arc::clone(&contents));
+                contents
+            }
+            None => return Err(not_found(path)),
+        };
+        Ok(Box::new(MemFile { contents, pos: 0, writable }))
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{}: no such file", path.display()))
+}
+
+impl Vfs for MemFs {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.handle(path, true, true)
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.handle(path, false, false)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        let file = self.handle(path, true, true)?;
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let contents = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.files.retain(|file, _| !file.starts_with(path));
+        state.durable_files.retain(|file, _| !file.starts_with(path));
+        state.dirs.retain(|dir| !dir.starts_with(path));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.dirs.extend(path.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state.lock().unwrap();
+        let files = state.files.keys().chain(&state.dirs);
+        Ok(files.filter(|path| path.parent() == Some(dir)).cloned().collect())
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let MemState { files, durable_files, .. } = &mut *state;
+        durable_files.retain(|path, _| path.parent() != Some(dir));
+        for (path, contents) in files.iter().filter(|(path, _)| path.parent() == Some(dir)) {
+            durable_files.insert(path.clone(), Arc::clone(contents));
+        }
+        Ok(())
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let contents = state.files.get(src).cloned().ok_or_else(|| not_found(src))?;
+        state.files.insert(dst.to_path_buf(), contents);
+        Ok(())
+    }
+
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        let state = self.state.lock().unwrap();
+        let contents = state.files.get(path).ok_or_else(|| not_found(path))?;
+        Ok(contents.lock().unwrap().data.len() as u64)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock().unwrap();
+        state.files.contains_key(path) || state.dirs.contains(path)
+    }
+}
+
+struct MemFile {
+    contents: Arc<Mutex<MemContents>>,
+    pos: u64,
+    writable: bool,
+}
+
+impl Read for MemFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let contents = self.contents.lock().unwrap();
+        let start = (self.pos as usize).min(contents.data.len());
+        let n = buf.len().min(contents.data.len() - start);
+        buf[..n].copy_from_slice(&contents.data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "file opened read-only"));
+        }
+        let mut contents = self.contents.lock().unwrap();
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        // writing past the end leaves a hole of zeros, as on disk
+        if contents.data.len() < end {
+            contents.data.resize(end, 0);
+        }
+        contents.data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.contents.lock().unwrap().data.len() as i64;
+        let pos = match pos {
+            SeekFrom::Start(n) => n as i64,
+            SeekFrom::End(n) => len + n,
+            SeekFrom::Current(n) => self.pos as i64 + n,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+impl VfsFile for MemFile {
+    fn sync_data(&self) -> io::Result<()> {
+        let mut contents = self.contents.lock().unwrap();
+        contents.durable = contents.data.clone();
+        Ok(())
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.contents.lock().unwrap().data.resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn allocate(&self, len: u64) -> io::Result<()> {
+        let data = &mut self.contents.lock().unwrap().data;
+        if (data.len() as u64) < len {
+            data.resize(len as usize, 0);
+        }
+        Ok(())
+    }
+
+    fn file_len(&self) -> io::Result<u64> {
+        Ok(self.contents.lock().unwrap().data.len() as u64)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(MemFile { contents: Arc::clone(&self.contents), pos: self.pos, writable: self.writable }))
+    }
+}
+
+// Wraps another Vfs and injects the failures it's told to:
+//   torn writes   `tear_after(n)`: the write that crosses the next n bytes only gets as far
+//                 as n, and every write (or truncate) after it fails, as if the process died
+//                 mid-write
+//   ENOSPC        `set_space(Some(n))`: n more bytes fit, then writes fail with StorageFull
+//   lost fsyncs   `lose_fsyncs(true)`: fsyncs report success without making anything durable
+// Clones share their faults, so a test can keep one and hand the other to a Store.
+#[derive(Debug, Clone)]
+pub struct FaultFs<V> {
+    inner: V,
+    faults: Arc<Mutex<Faults>>,
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    tear_after: Option<u64>,
+    torn: bool,
+    space: Option<u64>,
+    lose_fsyncs: bool,
+    lost_fsyncs: u64,
+}
+
+impl<V: Vfs> FaultFs<V> {
+    pub fn new(inner: V) -> Self {
+        Self { inner, faults: Arc::new(Mutex::new(Faults::default())) }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn tear_after(&self, bytes: u64) {
+        self.faults.lock().unwrap().tear_after = Some(bytes);
+    }
+
+    // has a write been torn (and writes failed since)?
+    pub fn torn(&self) -> bool {
+        self.faults.lock().unwrap().torn
+    }
+
+    // None is unlimited
+    pub fn set_space(&self, bytes: Option<u64>) {
+        self.faults.lock().unwrap().space = bytes;
+    }
+
+    pub fn lose_fsyncs(&self, lose: bool) {
+        self.faults.lock().unwrap().lose_fsyncs = lose;
+    }
+
+    // fsyncs (files and directories) that reported success without doing anything
+    pub fn lost_fsyncs(&self) -> u64 {
+        self.faults.lock().unwrap().lost_fsyncs
+    }
+
+    // Clears every fault; the data already written or lost stays as it is
+    pub fn heal(&self) {
+        *self.faults.lock().unwrap() = Faults::default();
+    }
+
+    fn wrap(&self, file: Box<dyn VfsFile>) -> Box<dyn VfsFile> {
+        Box::new(FaultFile { inner: file, faults: Arc::clone(&self.faults) })
+    }
+}
+
+impl Faults {
+    // true if the fsync should be skipped
+    fn lose_fsync(&mut self) -> bool {
+        if self.lose_fsyncs {
+            self.lost_fsyncs += 1;
+        }
+        self.lose_fsyncs
+    }
+}
+
+impl<V: Vfs> Vfs for FaultFs<V> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(self.inner.open(path)?))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(self.inner.open_read(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(self.inner.create(path)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir_all(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        if self.faults.lock().unwrap().lose_fsync() {
+            return Ok(());
+        }
+        self.inner.sync_dir(dir)
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.inner.hard_link(src, dst)
+    }
+
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        self.inner.file_len(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+}
+
+struct FaultFile {
+    inner: Box<dyn VfsFile>,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl Read for FaultFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for FaultFile {
+    // A write that runs into a fault is cut short rather than failed, like a real short
+    // write; the next one gets the error
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut faults = self.faults.lock().unwrap();
+        if faults.torn {
+            return Err(io::Error::other("write after a torn write (injected)"));
+        }
+        if faults.space == Some(0) && !buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::StorageFull, "no space left on device (injected)"));
+        }
+
+        let mut n = buf.len() as u64;
+        if let Some(space) = &mut faults.space {
+            n = n.min(*space);
+            *space -= n;
+        }
+        if let Some(left) = faults.tear_after {
+            if n >= left && !buf.is_empty() {
+                n = left;
+                faults.tear_after = None;
+                faults.torn = true;
+            } else {
+                faults.tear_after = Some(left - n);
+            }
+        }
+        drop(faults);
+
+        if n == 0 && !buf.is_empty() {
+            return Err(io::Error::other("torn write (injected)"));
+        }
+        self.inner.write_all(&buf[..n as usize])?;
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for FaultFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn sync_data(&self) -> io::Result<()> {
+        if self.faults.lock().unwrap().lose_fsync() {
+            return Ok(());
+        }
+        self.inner.sync_data()
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        if self.faults.lock().unwrap().lose_fsync() {
+            return Ok(());
+        }
+        self.inner.sync_all()
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        // the process is "dead" after a torn write, so it can't tidy up after it either
+        if self.faults.lock().unwrap().torn {
+            return Err(io::Error::other("truncate after a torn write (injected)"));
+        }
+        self.inner.set_len(len)
+    }
+
+    // reserving space takes space, so it can run out of it like a write
+    fn allocate(&self, len: u64) -> io::Result<()> {
+        let mut faults = self.faults.lock().unwrap();
+        if faults.torn {
+            return Err(io::Error::other("allocate after a torn write (injected)"));
+        }
+        let grow = len.saturating_sub(self.inner.file_len()?);
+        if let Some(space) = &mut faults.space {
+            if grow > *space {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "no space left on device (injected)"));
+            }
+            *space -= grow;
+        }
+        self.inner.allocate(len)
+    }
+
+    fn file_len(&self) -> io::Result<u64> {
+        self.inner.file_len()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(FaultFile { inner: self.inner.try_clone()?, faults: Arc::clone(&self.faults) }))
+    }
+}
+
+// Wraps another Vfs and keeps a log of every change made through it: opens, writes, fsyncs,
+// renames and the rest (reads aren't recorded). The log can be replayed onto a fresh MemFs
+// up to any point, which is what a crash test needs to see the disk as a crash there would
+// have left it; see `crash_points`.
+#[derive(Debug, Clone)]
+pub struct RecordingFs<V> {
+    inner: V,
+    trace: Arc<Mutex<Trace>>,
+}
+
+#[derive(Debug, Default)]
+struct Trace {
+    ops: Vec<VfsOp>,
+    next_file: usize,
+}
+
+// One recorded change. Files are numbered in the order they were opened for writing;
+// clones of a handle share its number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VfsOp {
+    Open { file: usize, path: PathBuf, truncate: bool },
+    Write { file: usize, pos: u64, data: Vec<u8> },
+    SetLen { file: usize, len: u64 },
+    Allocate { file: usize, len: u64 },
+    Sync { file: usize },
+    Rename { from: PathBuf, to: PathBuf },
+    RemoveFile { path: PathBuf },
+    RemoveDirAll { path: PathBuf },
+    CreateDirAll { path: PathBuf },
+    HardLink { src: PathBuf, dst: PathBuf },
+    SyncDir { path: PathBuf },
+}
+
+impl<V: Vfs> RecordingFs<V> {
+    pub fn new(inner: V) -> Self {
+        Self { inner, trace: Arc::default() }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    // Everything recorded so far
+    pub fn ops(&self) -> Vec<VfsOp> {
+        self.trace.lock().unwrap().ops.clone()
+    }
+
+    // How many ops have been recorded; a workload notes this around each step to know
+    // which ops belong to it
+    pub fn len(&self) -> usize {
+        self.trace.lock().unwrap().ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record(&self, op: VfsOp) {
+        self.trace.lock().unwrap().ops.push(op);
+    }
+
+    fn wrap(&self, path: &Path, truncate: bool, file: Box<dyn VfsFile>) -> Box<dyn VfsFile> {
+        let mut trace = self.trace.lock().unwrap();
+        let id = trace.next_file;
+        trace.next_file += 1;
+        trace.ops.push(VfsOp::Open { file: id, path: path.to_path_buf(), truncate });
+        Box::new(RecordingFile { inner: file, id, trace: Arc::clone(&self.trace) })
+    }
+}
+
+impl<V: Vfs> Vfs for RecordingFs<V> {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(path, false, self.inner.open(path)?))
+    }
+
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        self.inner.open_read(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(self.wrap(path, true, self.inner.create(path)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)?;
+        self.record(VfsOp::Rename { from: from.to_path_buf(), to: to.to_path_buf() });
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)?;
+        self.record(VfsOp::RemoveFile { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir_all(path)?;
+        self.record(VfsOp::RemoveDirAll { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)?;
+        self.record(VfsOp::CreateDirAll { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(dir)
+    }
+
+    fn sync_dir(&self, dir: &Path) -> io::Result<()> {
+        self.inner.sync_dir(dir)?;
+        self.record(VfsOp::SyncDir { path: dir.to_path_buf() });
+        Ok(())
+    }
+
+    fn hard_link(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.inner.hard_link(src, dst)?;
+        self.record(VfsOp::HardLink { src: src.to_path_buf(), dst: dst.to_path_buf() });
+        Ok(())
+    }
+
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        self.inner.file_len(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+}
+
+struct RecordingFile {
+    inner: Box<dyn VfsFile>,
+    id: usize,
+    trace: Arc<Mutex<Trace>>,
+}
+
+impl RecordingFile {
+    fn record(&self, op: VfsOp) {
+        self.trace.lock().unwrap().ops.push(op);
+    }
+}
+
+impl Read for RecordingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for RecordingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pos = self.inner.stream_position()?;
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.record(VfsOp::Write { file: self.id, pos, data: buf[..n].to_vec() });
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for RecordingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl VfsFile for RecordingFile {
+    fn sync_data(&self) -> io::Result<()> {
+        self.inner.sync_data()?;
+        self.record(VfsOp::Sync { file: self.id });
+        Ok(())
+    }
+
+    fn sync_all(&self) -> io::Result<()> {
+        self.inner.sync_all()?;
+        self.record(VfsOp::Sync { file: self.id });
+        Ok(())
+    }
+
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len)?;
+        self.record(VfsOp::SetLen { file: self.id, len });
+        Ok(())
+    }
+
+    fn allocate(&self, len: u64) -> io::Result<()> {
+        self.inner.allocate(len)?;
+        self.record(VfsOp::Allocate { file: self.id, len });
+        Ok(())
+    }
+
+    fn file_len(&self) -> io::Result<u64> {
+        self.inner.file_len()
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(RecordingFile { inner: self.inner.try_clone()?, id: self.id, trace: Arc::clone(&self.trace) }))
+    }
+}
+
+// A moment a recorded run could have died at: after the first `at` ops, with
+//   KeepUnsynced   everything written so far still there (the process died, the OS didn't)
+//   DropUnsynced   only what was fsynced (power loss)
+//   TornWrite      op `at`, a write, half done on top of KeepUnsynced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashPoint {
+    pub at: usize,
+    pub kind: CrashKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashKind {
+    KeepUnsynced,
+    DropUnsynced,
+    TornWrite,
+}
+
+// Every crash point of a recorded run, in order
+pub fn crash_points(ops: &[VfsOp]) -> Vec<CrashPoint> {
+    let mut points = Vec::new();
+    for at in 0..=ops.len() {
+        points.push(CrashPoint { at, kind: CrashKind::KeepUnsynced });
+        points.push(CrashPoint { at, kind: CrashKind::DropUnsynced });
+        if let Some(VfsOp::Write { data, .. }) = ops.get(at)
+            && data.len() > 1
+        {
+            points.push(CrashPoint { at, kind: CrashKind::TornWrite });
+        }
+    }
+    points
+}
+
+impl CrashPoint {
+    // The disk as this crash would have left it, for a run recorded from an empty disk
+    pub fn disk(&self, ops: &[VfsOp]) -> io::Result<MemFs> {
+        let fs = MemFs::new();
+        let mut files: HashMap<usize, Box<dyn VfsFile>> = HashMap::new();
+        for op in &ops[..self.at] {
+            apply(&fs, &mut files, op)?;
+        }
+
+        match self.kind {
+            CrashKind::KeepUnsynced => {}
+            CrashKind::DropUnsynced => fs.crash(),
+            CrashKind::TornWrite => {
+                if let Some(VfsOp::Write { file, pos, data }) = ops.get(self.at) {
+                    let half = VfsOp::Write { file: *file, pos: *pos, data: data[..data.len() / 2].to_vec() };
+                    apply(&fs, &mut files, &half)?;
+                }
+            }
+        }
+        Ok(fs)
+    }
+}
+
+fn apply(fs: &MemFs, files: &mut HashMap<usize, Box<dyn VfsFile>>, op: &VfsOp) -> io::Result<()> {
+    match op {
+        VfsOp::Open { file, path, truncate } => {
+            let handle = if *truncate { fs.create(path)? } else { fs.open(path)? };
+            files.insert(*file, handle);
+        }
+        VfsOp::Write { file, pos, data } => {
+            let handle = opened(files, *file)?;
+            handle.seek(SeekFrom::Start(*pos))?;
+            handle.write_all(data)?;
+        }
+        VfsOp::SetLen { file, len } => opened(files, *file)?.set_len(*len)?,
+        VfsOp::Allocate { file, len } => opened(files, *file)?.allocate(*len)?,
+        VfsOp::Sync { file } => opened(files, *file)?.sync_all()?,
+        VfsOp::Rename { from, to } => fs.rename(from, to)?,
+        VfsOp::RemoveFile { path } => fs.remove_file(path)?,
+        VfsOp::RemoveDirAll { path } => fs.remove_dir_all(path)?,
+        VfsOp::CreateDirAll { path } => fs.create_dir_all(path)?,
+        VfsOp::HardLink { src, dst } => fs.hard_link(src, dst)?,
+        VfsOp::SyncDir { path } => fs.sync_dir(path)?,
+    }
+    Ok(())
+}
+
+fn opened(files: &mut HashMap<usize, Box<dyn VfsFile>>, id: usize) -> io::Result<&mut Box<dyn VfsFile>> {
+    files.get_mut(&id).ok_or_else(|| io::Error::other(format!("file {id} used before it was opened")))
+}
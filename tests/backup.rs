@@ -0,0 +1,89 @@
+use std::fs;
+
+use kvs::backup::{restore_from_archive, write_backup};
+use kvs::config::StoreOptions;
+use kvs::store::Store;
+
+#[test]
+fn backup_is_consistent_while_store_keeps_writing() {
+    let src = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+    let archive = out.path().join("backup.kvsbak");
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+
+    let pin = s.pin_for_backup().unwrap();
+
+    // writes and a snapshot after the pin must not leak into (or break) the backup
+    s.set(b"c", b"3").unwrap();
+    s.create_snapshot().unwrap();
+
+    let info = write_backup(pin, &archive).unwrap();
+    assert_eq!(info.last_seq, 2);
+
+    let dest = out.path().join("restored");
+    restore_from_archive(&archive, &dest, "data.log").unwrap();
+
+    let restored = Store::open(dest.join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(restored.get(b"a").unwrap(), b"1");
+    assert_eq!(restored.get(b"b").unwrap(), b"2");
+    assert!(restored.get(b"c").is_none());
+    assert_eq!(restored.last_seq(), 2);
+
+    // the pin directory is gone once the backup is written
+    assert!(!fs::read_dir(src.path()).unwrap()
+        .any(|e| e.unwrap().file_name().to_string_lossy().ends_with(".pin")));
+}
+
+#[test]
+fn corrupted_archive_is_rejected() {
+    let src = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let archive = out.path().join("backup.kvsbak");
+
+    let mut s = Store::open(src.path().join("data.log"), StoreOptions::default()).unwrap();
+    s.set(b"key", b"value").unwrap();
+    write_backup(s.pin_for_backup().unwrap(), &archive).unwrap();
+
+    // flip a byte inside the log entry
+    let mut bytes = fs::read(&archive).unwrap();
+    let at = bytes.len() - 6;
+    bytes[at] ^= 0xff;
+    fs::write(&archive, bytes).unwrap();
+
+    let dest = out.path().join("restored");
+    let err = restore_from_archive(&archive, &dest, "data.log").unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"));
+    assert!(!dest.exists());
+
+    // a destination that was already there stays, empty
+    fs::create_dir(&dest).unwrap();
+    assert!(restore_from_archive(&archive, &dest, "data.log").is_err());
+    assert_eq!(fs::read_dir(&dest).unwrap().count(), 0);
+}
+
+#[test]
+fn backup_keeps_size_limits() {
+    let src = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+    let archive = out.path().join("backup.kvsbak");
+    let doc = vec![b'x'; 2 * 1024 * 1024];
+
+    let opts = StoreOptions { max_val_len: Some(16 * 1024 * 1024), ..Default::default() };
+    let mut s = Store::open(&log, opts).unwrap();
+    s.set(b"doc", &doc).unwrap();
+
+    let info = write_backup(s.pin_for_backup().unwrap(), &archive).unwrap();
+    assert_eq!(info.limits.max_val_len, 16 * 1024 * 1024);
+
+    let dest = out.path().join("restored");
+    restore_from_archive(&archive, &dest, "data.log").unwrap();
+
+    let restored = Store::open(dest.join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(restored.get(b"doc").unwrap().len(), doc.len());
+}
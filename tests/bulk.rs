@@ -0,0 +1,45 @@
+use kvs::bulk::BulkLoader;
+use kvs::config::StoreOptions;
+use kvs::store::Store;
+
+#[test]
+fn bulk_load_builds_openable_data_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut loader = BulkLoader::create(&log).unwrap();
+    loader.add(b"zebra", b"1").unwrap();
+    loader.add(b"apple", b"2").unwrap();
+    loader.add(b"zebra", b"3").unwrap();
+    assert_eq!(loader.entries(), 3);
+    let meta = loader.finish().unwrap();
+    assert_eq!(meta.snapshot_number, 1);
+    assert_eq!(std::fs::metadata(&log).unwrap().len(), 0);
+    // written in the sorted format, like any other snapshot
+    assert!(std::fs::read(&meta.snapshot_path).unwrap().starts_with(b"\0\0\0\0KVST"));
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"apple").unwrap(), b"2");
+    assert_eq!(s.get(b"zebra").unwrap(), b"3");
+
+    s.set(b"mango", b"4").unwrap();
+    drop(s);
+    let s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.scan_prefix_str(None), ["apple", "mango", "zebra"]);
+}
+
+#[test]
+fn bulk_load_rejects_existing_data_and_invalid_pairs() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut loader = BulkLoader::create(&log).unwrap();
+    assert!(loader.add(b"", b"empty key").is_err());
+    assert!(loader.add(b"k", &vec![0u8; 2 * 1024 * 1024]).is_err());
+    drop(loader);
+    // nothing left behind by the abandoned load
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    Store::open(&log, StoreOptions::default()).unwrap().set(b"a", b"1").unwrap();
+    assert!(BulkLoader::create(&log).is_err());
+}
@@ -1,4 +1,5 @@
 use assert_cmd::cargo::cargo_bin_cmd;
+use predicates::prelude::*;
 use predicates::str::contains;
 use tempfile::NamedTempFile;
 
@@ -55,4 +56,180 @@ fn cli_get_missing_prints_nil() {
         .assert()
         .success()
         .stdout(contains("(nil)"));
-}
\ No newline at end of file
+}
+#[test]
+fn cli_verify_reports_corruption_without_modifying_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let log_path = log.to_str().unwrap();
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "set", "a", "1"])
+        .assert()
+        .success();
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "verify"])
+        .assert()
+        .success()
+        .stdout(contains("ok: 0 snapshot entries, 1 log records"));
+
+    // append a record with an unknown op code
+    let mut bytes = std::fs::read(&log).unwrap();
+    let good_len = bytes.len();
+    bytes.extend_from_slice(&[10, 0, 0, 0, 0]);
+    std::fs::write(&log, &bytes).unwrap();
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "verify"])
+        .assert()
+        .failure()
+        .stdout(contains(format!("unknown op code: 10 at offset {good_len}")));
+
+    assert_eq!(std::fs::read(&log).unwrap(), bytes);
+}
+
+#[test]
+fn cli_inspect_log_prints_records_with_offsets_and_seq() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let log_path = log.to_str().unwrap();
+
+    for args in [["set", "a", "1"], ["set", "b", "2"]] {
+        cargo_bin_cmd!("kvs")
+            .args(["--log", log_path])
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "inspect-log"])
+        .assert()
+        .success()
+        .stdout(contains("offset=0 op=SET(3) seq=1"))
+        .stdout(contains("offset=27 op=SET(3) seq=2"));
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "inspect-log", "--json", "--from-offset", "27"])
+        .assert()
+        .success()
+        .stdout(contains("{\"offset\":27,\"op\":\"SET\",\"op_code\":3,\"seq\":2,"))
+        .stdout(contains("\"key\":\"b\",\"val_len\":1,\"val_preview\":\"2\"}"))
+        .stdout(contains("offset\":0").not());
+}
+
+#[test]
+fn cli_ns_flag_picks_the_namespace() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let log_path = log.to_str().unwrap();
+
+    for args in [&["--ns", "users", "set", "a", "1"][..], &["set", "a", "2"]] {
+        cargo_bin_cmd!("kvs")
+            .args(["--log", log_path])
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "users", "get", "a"])
+        .assert()
+        .success()
+        .stdout("1\n");
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "default", "get", "a"])
+        .assert()
+        .success()
+        .stdout("2\n");
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "orders", "scan"])
+        .assert()
+        .success()
+        .stdout(contains("0 keys found"));
+
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "no/slash", "get", "a"])
+        .assert()
+        .failure()
+        .stderr(contains("invalid namespace name"));
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "users", "bulk-load"])
+        .assert()
+        .failure()
+        .stderr(contains("--ns only applies to"));
+}
+
+#[test]
+fn cli_export_and_import_work_one_namespace_at_a_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let log_path = log.to_str().unwrap();
+    for args in [&["--ns", "users", "set", "a", "1"][..], &["set", "b", "2"]] {
+        cargo_bin_cmd!("kvs").args(["--log", log_path]).args(args).assert().success();
+    }
+
+    // rows carry no namespace, so a plain export would quietly leave "users" out
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "export"])
+        .assert()
+        .failure()
+        .stderr(contains("other namespaces (users)"));
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "default", "export"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"b\",\"value\":\"2\",\"encoding\":\"utf8\"}\n");
+    let users = cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "users", "export"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"a\",\"value\":\"1\",\"encoding\":\"utf8\"}\n")
+        .get_output()
+        .stdout
+        .clone();
+
+    let dump = dir.path().join("users.jsonl");
+    std::fs::write(&dump, users).unwrap();
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "archive", "import", dump.to_str().unwrap()])
+        .assert()
+        .success();
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "--ns", "archive", "get", "a"])
+        .assert()
+        .success()
+        .stdout("1\n");
+    cargo_bin_cmd!("kvs")
+        .args(["--log", log_path, "get", "a"])
+        .assert()
+        .success()
+        .stdout("(nil)\n");
+}
+
+#[test]
+fn cli_relative_nested_log_survives_a_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("logs")).unwrap();
+
+    for args in [&["set", "a", "1"][..], &["snapshot"], &["set", "b", "2"], &["snapshot"]] {
+        cargo_bin_cmd!("kvs")
+            .current_dir(dir.path())
+            .args(["--log", "logs/data.log"])
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    let manifest = std::fs::read_to_string(dir.path().join("logs/MANIFEST")).unwrap();
+    assert!(manifest.contains("\"log\":\"data.log\""), "{manifest}");
+    for (key, val) in [("a", "1\n"), ("b", "2\n")] {
+        cargo_bin_cmd!("kvs")
+            .current_dir(dir.path())
+            .args(["--log", "logs/data.log", "get", key])
+            .assert()
+            .success()
+            .stdout(val);
+    }
+}
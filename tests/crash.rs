@@ -0,0 +1,81 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::config::{Durability, StoreOptions};
+use kvs::store::Store;
+use kvs::vfs::{crash_points, CrashPoint, MemFs, RecordingFs, Vfs};
+
+const LOG: &str = "/db/data.log";
+
+fn opts(vfs: impl Vfs + 'static) -> StoreOptions {
+    StoreOptions { vfs: Arc::new(vfs), durability: Durability::FsyncAlways, ..Default::default() }
+}
+
+fn assert_state(s: &Store, expected: &[(&str, Option<&str>)], point: &CrashPoint) {
+    for (key, val) in expected {
+        assert_eq!(s.get(key.as_bytes()), val.map(str::as_bytes), "{point:?}: key {key}");
+    }
+}
+
+// Runs `setup`, then takes a snapshot, and reopens the store on the disk as every point of
+// that snapshot could have left it: every write before it must be there
+fn check_snapshot_crashes(setup: impl Fn(&mut Store), expected: &[(&str, Option<&str>)]) {
+    let fs = RecordingFs::new(MemFs::new());
+    let mut s = Store::open(LOG, opts(fs.clone())).unwrap();
+    setup(&mut s);
+    let start = fs.len();
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    let ops = fs.ops();
+    let points: Vec<_> = crash_points(&ops).into_iter().filter(|point| point.at >= start).collect();
+    assert!(!points.is_empty());
+    for point in points {
+        let disk = point.disk(&ops).unwrap();
+        let mut s = Store::open(LOG, opts(disk.clone()))
+            .unwrap_or_else(|e| panic!("{point:?}: open failed: {e}"));
+        assert_state(&s, expected, &point);
+
+        // the store keeps working: new writes, another snapshot, reopen
+        s.set(b"d", b"4").unwrap();
+        s.create_snapshot().unwrap();
+        drop(s);
+        let s = Store::open(LOG, opts(disk.clone())).unwrap();
+        assert_state(&s, expected, &point);
+        assert_state(&s, &[("d", Some("4"))], &point);
+
+        // and nothing from the crash is left lying around
+        let mut names: Vec<String> = disk.read_dir(Path::new("/db")).unwrap().iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3, "{point:?}: {names:?}");
+        assert_eq!(names[..2], ["MANIFEST", "data.log"], "{point:?}: {names:?}");
+        assert!(names[2].starts_with("snapshot-") && names[2].ends_with(".snap"), "{point:?}: {names:?}");
+    }
+}
+
+#[test]
+fn snapshot_crash_at_each_point_recovers() {
+    check_snapshot_crashes(
+        |s| {
+            s.set(b"a", b"1").unwrap();
+            s.create_snapshot().unwrap();
+            s.set(b"b", b"2").unwrap();
+            s.del(b"a").unwrap();
+            s.set(b"c", b"3").unwrap();
+        },
+        &[("a", None), ("b", Some("2")), ("c", Some("3"))],
+    );
+}
+
+#[test]
+fn first_snapshot_crash_at_each_point_recovers() {
+    check_snapshot_crashes(
+        |s| {
+            s.set(b"a", b"1").unwrap();
+            s.set(b"b", b"2").unwrap();
+        },
+        &[("a", Some("1")), ("b", Some("2"))],
+    );
+}
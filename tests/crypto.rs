@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::backup::{restore_from_archive, write_backup};
+use kvs::config::{Compression, StoreOptions};
+use kvs::crypto::EncryptionKey;
+use kvs::store::Store;
+use kvs::vfs::{FaultFs, MemFs};
+use kvs::verify::{verify_data_dir, verify_data_dir_with_key};
+
+fn key(byte: u8) -> EncryptionKey {
+    EncryptionKey::from_bytes(&[byte; 32])
+}
+
+fn with_key(key: EncryptionKey) -> StoreOptions {
+    StoreOptions { encryption: Some(key), ..Default::default() }
+}
+
+// true if `needle` shows up in any file under `dir`
+fn on_disk(dir: &Path, needle: &[u8]) -> bool {
+    fs::read_dir(dir).unwrap().any(|entry| {
+        let path = entry.unwrap().path();
+        path.is_file() && fs::read(&path).unwrap().windows(needle.len()).any(|w| w == needle)
+    })
+}
+
+#[test]
+fn encrypted_dir_round_trips_without_plaintext_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let big: Vec<u8> = b"card-4111".iter().copied().cycle().take(600 * 1024).collect();
+
+    let mut s = Store::open(&log, StoreOptions { compression: Compression::Lz4, ..with_key(key(1)) }).unwrap();
+    s.set(b"customer:1", b"alice@example.com").unwrap();
+    s.set(b"customer:2", &big).unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"customer:3", b"bob@example.com").unwrap();
+    s.del(b"customer:1").unwrap();
+    drop(s);
+
+    for needle in [&b"customer:"[..], b"alice@example.com", b"bob@example.com", b"card-4111"] {
+        assert!(!on_disk(dir.path(), needle), "{:?} in plaintext", needle.escape_ascii().to_string());
+    }
+
+    let s = Store::open(&log, with_key(key(1))).unwrap();
+    assert_eq!(s.get(b"customer:1"), None);
+    assert_eq!(s.get(b"customer:2"), Some(&big[..]));
+    assert_eq!(s.get(b"customer:3"), Some(&b"bob@example.com"[..]));
+    assert!(verify_data_dir_with_key(&log, Some(&key(1))).unwrap().is_ok());
+}
+
+#[test]
+fn wrong_or_missing_key_fails_clearly() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    Store::open(&log, with_key(key(1))).unwrap().set(b"a", b"1").unwrap();
+
+    let err = Store::open(&log, with_key(key(2))).err().unwrap();
+    assert!(err.to_string().contains("wrong key"), "{err}");
+    let err = Store::open(&log, StoreOptions::default()).err().unwrap();
+    assert!(err.to_string().contains("is encrypted"), "{err}");
+    assert!(!verify_data_dir(&log).unwrap().is_ok());
+
+    // and a plaintext dir with data can't be opened with a key by accident
+    let plain = tempfile::tempdir().unwrap();
+    let plain_log = plain.path().join("data.log");
+    Store::open(&plain_log, StoreOptions::default()).unwrap().set(b"a", b"1").unwrap();
+    let err = Store::open(&plain_log, with_key(key(1))).err().unwrap();
+    assert!(err.to_string().contains("kvs rekey"), "{err}");
+}
+
+#[test]
+fn tampered_record_fails_authentication() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, with_key(key(1))).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+
+    let mut bytes = fs::read(&log).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0x01;
+    fs::write(&log, bytes).unwrap();
+
+    let err = Store::open(&log, with_key(key(1))).err().unwrap();
+    assert!(err.is_corrupt_log() && err.to_string().contains("authentication"), "{err}");
+}
+
+#[test]
+fn failed_rekey_keeps_writing_under_the_key_the_manifest_names() {
+    // every budget that runs out somewhere in the rekey, the second snapshot included
+    for space in (0..20_000).step_by(97) {
+        let fs = FaultFs::new(MemFs::new());
+        let opts = |key: Option<EncryptionKey>| StoreOptions { vfs: Arc::new(fs.clone()), encryption: key, ..Default::default() };
+        let mut s = Store::open("/db/data.log", opts(Some(key(1)))).unwrap();
+        s.set(b"a", b"1").unwrap();
+        fs.set_space(Some(space));
+        if s.rekey(Some(key(2))).is_ok() {
+            continue;
+        }
+
+        fs.heal();
+        s.set(b"b", b"2").unwrap();
+        drop(s);
+        let s = Store::open("/db/data.log", opts(Some(key(1))))
+            .or_else(|_| Store::open("/db/data.log", opts(Some(key(2)))))
+            .unwrap_or_else(|e| panic!("space {space}: {e}"));
+        assert_eq!(s.get(b"a"), Some(&b"1"[..]), "space {space}");
+        assert_eq!(s.get(b"b"), Some(&b"2"[..]), "space {space}");
+    }
+}
+
+#[test]
+fn rekey_switches_keys_and_decrypts() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"secret-1").unwrap();
+    s.rekey(Some(key(1))).unwrap();
+    s.set(b"b", b"secret-2").unwrap();
+    drop(s);
+    assert!(!on_disk(dir.path(), b"secret-"));
+
+    let mut s = Store::open(&log, with_key(key(1))).unwrap();
+    s.rekey(Some(key(2))).unwrap();
+    drop(s);
+    assert!(Store::open(&log, with_key(key(1))).is_err());
+
+    let mut s = Store::open(&log, with_key(key(2))).unwrap();
+    assert_eq!(s.get(b"b"), Some(&b"secret-2"[..]));
+    s.rekey(None).unwrap();
+    drop(s);
+
+    let s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"a"), Some(&b"secret-1"[..]));
+    assert_eq!(s.get(b"b"), Some(&b"secret-2"[..]));
+    assert!(verify_data_dir(&log).unwrap().is_ok());
+}
+
+#[test]
+fn backup_of_encrypted_dir_stays_encrypted() {
+    let src = tempfile::tempdir().unwrap();
+    let out = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+    let archive = out.path().join("backup.kvsbak");
+
+    let mut s = Store::open(&log, with_key(key(1))).unwrap();
+    s.set(b"a", b"secret-1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"secret-2").unwrap();
+    let info = write_backup(s.pin_for_backup().unwrap(), &archive).unwrap();
+    assert!(info.encrypted);
+    assert!(!on_disk(out.path(), b"secret-"));
+
+    let dest = out.path().join("restored");
+    restore_from_archive(&archive, &dest, "data.log").unwrap();
+    assert!(Store::open(dest.join("data.log"), StoreOptions::default()).is_err());
+    let restored = Store::open(dest.join("data.log"), with_key(key(1))).unwrap();
+    assert_eq!(restored.get(b"a"), Some(&b"secret-1"[..]));
+    assert_eq!(restored.get(b"b"), Some(&b"secret-2"[..]));
+}
@@ -0,0 +1,159 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::config::{Durability, StoreOptions};
+use kvs::error::StoreError;
+use kvs::store::{Store, WriteBatch};
+use kvs::vfs::{FaultFs, MemFs, Vfs};
+
+const LOG: &str = "/db/data.log";
+
+fn on(vfs: impl Vfs + 'static, durability: Durability) -> StoreOptions {
+    StoreOptions { vfs: Arc::new(vfs), durability, ..Default::default() }
+}
+
+fn log_len(mem: &MemFs) -> usize {
+    mem.read(Path::new(LOG)).unwrap().len()
+}
+
+#[test]
+fn full_disk_rolls_back_and_goes_read_only_until_space_frees_up() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    s.set(b"a", b"1").unwrap();
+    let good_len = log_len(&mem);
+
+    fs.set_space(Some(8));
+    let err = s.set(b"b", &[b'x'; 64]).err().unwrap();
+    assert!(matches!(&err, StoreError::Io(e) if e.kind() == ErrorKind::StorageFull), "{err}");
+    assert_eq!(log_len(&mem), good_len, "the partial record is truncated away");
+    assert!(s.read_only_cause().unwrap().contains("no space left"));
+
+    // reads keep working, writes get the read-only error
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.scan_prefix(b"").len(), 1);
+    let err = s.del(b"a").err().unwrap();
+    assert!(err.is_read_only() && err.to_string().contains("no space left"), "{err}");
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    let stats = s.stats();
+    assert!(stats.read_only);
+    assert_eq!(stats.failed_writes, 2);
+    assert_eq!(log_len(&mem), good_len);
+
+    // the next write after space frees up gets through and ends read-only mode
+    fs.set_space(None);
+    s.set(b"c", b"3").unwrap();
+    assert_eq!(s.read_only_cause(), None);
+    assert!(!s.stats().read_only);
+    drop(s);
+
+    let s = Store::open(LOG, on(mem, Durability::FsyncAlways)).unwrap();
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), None);
+    assert_eq!(s.get(b"c"), Some(&b"3"[..]));
+}
+
+#[test]
+fn failed_batch_leaves_nothing_behind_in_the_buffer() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::Flush)).unwrap();
+    s.set(b"a", b"1").unwrap();
+
+    fs.set_space(Some(30));
+    let mut batch = WriteBatch::new();
+    for key in [&b"b"[..], b"c", b"d"] {
+        batch.set(key, &[b'x'; 40]);
+    }
+    assert!(s.write(batch).is_err());
+    assert_eq!(s.get(b"b"), None);
+
+    fs.set_space(None);
+    s.set(b"e", b"5").unwrap();
+    drop(s);
+
+    let s = Store::open(LOG, on(mem, Durability::Flush)).unwrap();
+    let keys: Vec<_> = s.scan_prefix(b"").into_iter().map(|(k, _)| k.to_vec()).collect();
+    assert_eq!(keys, [b"a".to_vec(), b"e".to_vec()]);
+    assert_eq!(s.last_seq(), 2);
+}
+
+#[test]
+fn snapshot_that_does_not_fit_cleans_up_after_itself() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    for i in 0..20 {
+        s.set(format!("key{i}").as_bytes(), &[b'v'; 100]).unwrap();
+    }
+
+    fs.set_space(Some(100));
+    assert!(s.create_snapshot().is_err());
+    let files = mem.read_dir(Path::new("/db")).unwrap();
+    assert!(!files.iter().any(|f| f.extension().is_some_and(|e| e == "tmp")), "{files:?}");
+
+    fs.set_space(None);
+    s.create_snapshot().unwrap();
+    s.set(b"after", b"1").unwrap();
+    drop(s);
+
+    let s = Store::open(LOG, on(mem, Durability::FsyncAlways)).unwrap();
+    assert_eq!(s.scan_prefix(b"").len(), 21);
+}
+
+#[test]
+fn failed_auto_snapshot_does_not_fail_the_write() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let opts = StoreOptions { max_log_size: Some(1), ..on(fs.clone(), Durability::Flush) };
+    let mut s = Store::open(LOG, opts).unwrap();
+
+    // room for the write's record, not for the snapshot it triggers
+    fs.set_space(Some(64));
+    s.set(b"k", &[b'v'; 30]).unwrap();
+    let stats = s.stats();
+    assert_eq!((stats.failed_snapshots, stats.failed_writes), (1, 0));
+    assert!(!stats.read_only);
+    assert_eq!(s.get(b"k"), Some(&[b'v'; 30][..]));
+
+    fs.set_space(None);
+    s.set(b"k2", b"v").unwrap();
+    assert_eq!(s.stats().failed_snapshots, 1);
+    drop(s);
+
+    let s = Store::open(LOG, on(mem, Durability::Flush)).unwrap();
+    assert_eq!(s.get(b"k"), Some(&[b'v'; 30][..]));
+    assert_eq!(s.get(b"k2"), Some(&b"v"[..]));
+}
+
+#[test]
+fn failed_eviction_does_not_fail_the_write() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let opts = || StoreOptions { max_memory: Some(250), ..on(fs.clone(), Durability::Flush) };
+    let mut s = Store::open(LOG, opts()).unwrap();
+    s.set(b"k1", &[b'v'; 98]).unwrap();
+    s.set(b"k2", &[b'v'; 98]).unwrap();
+
+    // room for the write's record, not for the delete that evicts k1 to make room for it
+    fs.set_space(Some(130));
+    s.set(b"k3", &[b'v'; 98]).unwrap();
+    let stats = s.stats();
+    assert_eq!((stats.failed_evictions, stats.failed_writes, stats.evictions), (1, 0, 0));
+    assert_eq!(s.get(b"k3"), Some(&[b'v'; 98][..]));
+
+    // the next write evicts what's over budget
+    fs.set_space(None);
+    s.set(b"k4", b"v").unwrap();
+    let stats = s.stats();
+    assert_eq!((stats.failed_evictions, stats.evictions), (1, 1));
+    assert_eq!(s.get(b"k1"), None);
+    drop(s);
+
+    let s = Store::open(LOG, opts()).unwrap();
+    assert_eq!(s.get(b"k1"), None);
+    assert_eq!(s.get(b"k3"), Some(&[b'v'; 98][..]));
+    assert_eq!(s.get(b"k4"), Some(&b"v"[..]));
+}
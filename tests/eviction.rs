@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use kvs::config::{EvictionPolicy, StoreOptions};
+use kvs::store::Store;
+
+// every entry is 10 key bytes + 90 value bytes
+const ENTRY: u64 = 100;
+
+fn key(i: usize) -> Vec<u8> {
+    format!("key:{i:06}").into_bytes()
+}
+
+fn cache(entries: u64, eviction: EvictionPolicy) -> StoreOptions {
+    StoreOptions { max_memory: Some(entries * ENTRY), eviction, ..Default::default() }
+}
+
+fn fill(s: &mut Store, keys: std::ops::Range<usize>) {
+    for i in keys {
+        s.set(&key(i), &[b'v'; 90]).unwrap();
+    }
+}
+
+fn present(s: &Store, keys: std::ops::Range<usize>) -> Vec<usize> {
+    keys.filter(|&i| s.get(&key(i)).is_some()).collect()
+}
+
+fn open(log: &Path, opts: StoreOptions) -> Store {
+    Store::open(log, opts).unwrap()
+}
+
+#[test]
+fn lru_evicts_the_least_recently_used_through_tombstones() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = open(&log, cache(10, EvictionPolicy::Lru));
+
+    fill(&mut s, 0..10);
+    assert_eq!(s.stats().evictions, 0);
+    assert_eq!(s.stats().memory_bytes, 10 * ENTRY);
+    // reading 0 and 1 makes 2 and 3 the least recently used
+    s.get(&key(0)).unwrap();
+    s.get(&key(1)).unwrap();
+    fill(&mut s, 10..12);
+
+    let stats = s.stats();
+    assert_eq!((stats.evictions, stats.evicted_bytes, stats.memory_bytes), (2, 2 * ENTRY, 10 * ENTRY));
+    assert_eq!(present(&s, 0..12), [0, 1, 4, 5, 6, 7, 8, 9, 10, 11]);
+    drop(s);
+
+    // the evictions were logged, so they survive a restart without a budget too
+    let s = open(&log, StoreOptions::default());
+    assert_eq!(present(&s, 0..12), [0, 1, 4, 5, 6, 7, 8, 9, 10, 11]);
+}
+
+#[test]
+fn lfu_keeps_the_most_used() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = open(&dir.path().join("data.log"), cache(10, EvictionPolicy::Lfu));
+
+    fill(&mut s, 0..10);
+    for _ in 0..3 {
+        for i in [2, 5, 7] {
+            s.get(&key(i)).unwrap();
+        }
+    }
+    // each new entry is the least used as soon as the next one comes in
+    fill(&mut s, 10..30);
+    let kept = present(&s, 0..30);
+    assert!([2, 5, 7].iter().all(|i| kept.contains(i)), "{kept:?}");
+    assert_eq!(kept.len(), 10);
+    assert_eq!(s.stats().evictions, 20);
+}
+
+#[test]
+fn oldest_write_evicts_the_oldest_writes_whatever_was_read() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = open(&dir.path().join("data.log"), cache(10, EvictionPolicy::OldestWrite));
+
+    fill(&mut s, 0..10);
+    s.get(&key(0)).unwrap();
+    // rewriting 1 starts its clock over
+    s.set(&key(1), &[b'w'; 90]).unwrap();
+    fill(&mut s, 10..13);
+    assert_eq!(present(&s, 0..13), [1, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+}
+
+#[test]
+fn random_stays_within_the_budget() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = open(&dir.path().join("data.log"), cache(50, EvictionPolicy::Random));
+
+    fill(&mut s, 0..500);
+    let stats = s.stats();
+    assert!(stats.memory_bytes <= 50 * ENTRY, "{stats:?}");
+    assert_eq!(stats.evictions, 450);
+    let kept = present(&s, 0..500);
+    assert_eq!(kept.len(), 50);
+    // not just the newest 50
+    assert!(kept[0] < 450, "{kept:?}");
+}
+
+#[test]
+fn smaller_budget_on_open_evicts_down_to_it() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = open(&log, StoreOptions::default());
+    fill(&mut s, 0..100);
+    s.create_snapshot().unwrap();
+    s.del(&key(0)).unwrap();
+    drop(s);
+
+    let s = open(&log, cache(20, EvictionPolicy::Lru));
+    let stats = s.stats();
+    assert_eq!((stats.evictions, stats.memory_bytes), (79, 20 * ENTRY));
+    drop(s);
+    let s = open(&log, StoreOptions::default());
+    assert_eq!(s.scan_prefix(b"key:").len(), 20);
+}
+
+#[test]
+fn entry_over_the_whole_budget_is_evicted_with_everything_else() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = open(&dir.path().join("data.log"), cache(3, EvictionPolicy::Lru));
+    fill(&mut s, 0..3);
+    s.set(b"big", &[b'x'; 1000]).unwrap();
+    assert_eq!(s.stats().memory_bytes, 0);
+    assert_eq!(s.stats().evictions, 4);
+}
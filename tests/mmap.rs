@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::config::{Compression, RecoveryPolicy, StoreOptions};
+use kvs::crypto::EncryptionKey;
+use kvs::store::Store;
+use kvs::vfs::MemFs;
+
+fn key(i: usize) -> String {
+    format!("user:{i:05}")
+}
+
+fn val(i: usize) -> String {
+    format!(r#"{{"id":{i},"name":"user {i}"}}"#).repeat(i % 7 + 1)
+}
+
+fn contents(store: &Store) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    store.scan_prefix(b"").into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect()
+}
+
+// The same data dir, on a MemFs, which can't map files and so takes the buffered path
+fn in_memory(dir: &Path) -> MemFs {
+    let mem = MemFs::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        mem.write(&Path::new("/db").join(path.file_name().unwrap()), &fs::read(&path).unwrap());
+    }
+    mem
+}
+
+// Writes a many-block snapshot (plus a log tail), then checks the mapped load on disk gives
+// the same store as the buffered one in memory
+fn check_loads_agree(opts: impl Fn() -> StoreOptions) {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, opts()).unwrap();
+    for i in (0..20_000).rev() {
+        s.set(key(i).as_bytes(), val(i).as_bytes()).unwrap();
+    }
+    s.create_snapshot().unwrap();
+    s.del(key(7).as_bytes()).unwrap();
+    s.set(b"tail", b"1").unwrap();
+    let expected = contents(&s);
+    drop(s);
+
+    let mapped = Store::open(&log, opts()).unwrap();
+    assert_eq!(contents(&mapped), expected);
+    assert_eq!(mapped.get(key(7).as_bytes()), None);
+
+    let buffered = StoreOptions { vfs: Arc::new(in_memory(dir.path())), ..opts() };
+    let buffered = Store::open("/db/data.log", buffered).unwrap();
+    assert_eq!(contents(&buffered), expected);
+}
+
+#[test]
+fn mapped_load_matches_buffered_load() {
+    check_loads_agree(StoreOptions::default);
+}
+
+#[test]
+fn mapped_load_handles_compressed_and_encrypted_blocks() {
+    check_loads_agree(|| StoreOptions { compression: Compression::Zstd(3), ..Default::default() });
+    check_loads_agree(|| StoreOptions {
+        compression: Compression::Lz4,
+        encryption: Some(EncryptionKey::from_bytes(&[7; 32])),
+        ..Default::default()
+    });
+}
+
+// snapshots from before the sorted format are a plain run of entries, which are parsed
+// out of the map front to back
+#[test]
+fn mapped_load_reads_unsorted_snapshots() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    let meta = s.create_snapshot().unwrap();
+    drop(s);
+
+    let mut bytes = Vec::new();
+    let pairs = (0..5000).rev().map(|i| (key(i), val(i))).chain([(key(0), "last one wins".to_string())]);
+    for (k, v) in pairs {
+        bytes.extend_from_slice(&(k.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(k.as_bytes());
+        bytes.extend_from_slice(&(v.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(v.as_bytes());
+    }
+    fs::write(&meta.snapshot_path, bytes).unwrap();
+
+    let mapped = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(mapped.scan_prefix(b"user:").len(), 5000);
+    assert_eq!(mapped.get(key(0).as_bytes()), Some(&b"last one wins"[..]));
+
+    let buffered = StoreOptions { vfs: Arc::new(in_memory(dir.path())), ..Default::default() };
+    let buffered = Store::open("/db/data.log", buffered).unwrap();
+    assert_eq!(contents(&buffered), contents(&mapped));
+}
+
+#[test]
+fn damaged_snapshot_fails_the_mapped_load_and_can_still_be_salvaged() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    for i in 0..20_000 {
+        s.set(key(i).as_bytes(), val(i).as_bytes()).unwrap();
+    }
+    let meta = s.create_snapshot().unwrap();
+    drop(s);
+
+    let mut bytes = fs::read(&meta.snapshot_path).unwrap();
+    let mid = bytes.len() / 2;
+    bytes[mid] ^= 0xff;
+    fs::write(&meta.snapshot_path, bytes).unwrap();
+
+    let err = Store::open(&log, StoreOptions::default()).err().unwrap();
+    assert!(err.is_corrupt_log() && err.to_string().contains("checksum mismatch"), "{err}");
+
+    let s = Store::open(&log, StoreOptions { recovery: RecoveryPolicy::Salvage, ..Default::default() }).unwrap();
+    assert_eq!(s.salvage_report().unwrap().skipped_regions.len(), 1);
+    let kept = s.scan_prefix(b"user:").len();
+    assert!(kept < 20_000 && kept > 19_000, "{kept} kept");
+}
+
+// each thread fills its own indexes, which are moved into the store's afterwards; a namespace
+// spanning several threads' blocks has to come out whole
+#[test]
+fn mapped_load_merges_namespaces_split_across_threads() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let namespaces = ["default", "orders", "users"];
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    for i in 0..30_000 {
+        s.namespace(namespaces[i % 3]).unwrap().set(key(i).as_bytes(), val(i).as_bytes()).unwrap();
+    }
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    let by_namespace = |s: &mut Store| -> Vec<BTreeMap<Vec<u8>, Vec<u8>>> {
+        namespaces.iter()
+            .map(|ns| s.namespace(ns).unwrap().scan_prefix(b"").into_iter().map(|(k, v)| (k.to_vec(), v.to_vec())).collect())
+            .collect()
+    };
+    let mapped = by_namespace(&mut Store::open(&log, StoreOptions::default()).unwrap());
+    assert!(mapped.iter().all(|ns| ns.len() == 10_000));
+    assert_eq!(mapped[1][key(1).as_bytes()], val(1).as_bytes());
+
+    let buffered = StoreOptions { vfs: Arc::new(in_memory(dir.path())), ..Default::default() };
+    assert_eq!(by_namespace(&mut Store::open("/db/data.log", buffered).unwrap()), mapped);
+}
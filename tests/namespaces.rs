@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use kvs::config::{Compression, EvictionPolicy, NamespaceOptions, StoreOptions};
+use kvs::crypto::EncryptionKey;
+use kvs::inspect::inspect_file;
+use kvs::restore::{restore_to_point, RestoreTarget};
+use kvs::store::{Store, WriteBatch};
+use kvs::verify::verify_data_dir;
+
+fn keys(s: &mut Store, ns: &str) -> Vec<String> {
+    s.namespace(ns).unwrap().scan_prefix_str(None)
+}
+
+fn get(s: &mut Store, ns: &str, key: &str) -> Option<Vec<u8>> {
+    s.namespace(ns).unwrap().get(key.as_bytes()).map(|v| v.to_vec())
+}
+
+// "KVST" for a snapshot older versions can read, "KVSN" for a namespaced one
+fn snapshot_magic(path: &Path) -> String {
+    String::from_utf8(fs::read(path).unwrap()[4..8].to_vec()).unwrap()
+}
+
+#[test]
+fn namespaces_keep_their_keys_apart() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+
+    s.set(b"user:1", b"default").unwrap();
+    s.namespace("tenant-a").unwrap().set(b"user:1", b"alice").unwrap();
+    s.namespace("tenant-a").unwrap().set(b"user:2", b"bob").unwrap();
+    s.namespace("tenant-b").unwrap().set(b"user:3", b"carol").unwrap();
+
+    assert_eq!(s.get(b"user:1"), Some(&b"default"[..]));
+    assert_eq!(s.get(b"user:2"), None);
+    assert_eq!(get(&mut s, "default", "user:1").as_deref(), Some(&b"default"[..]));
+    assert_eq!(get(&mut s, "tenant-a", "user:1").as_deref(), Some(&b"alice"[..]));
+    assert_eq!(keys(&mut s, "tenant-a"), ["user:1", "user:2"]);
+    assert_eq!(keys(&mut s, "tenant-b"), ["user:3"]);
+    assert_eq!(s.scan_prefix(b"user:").len(), 1);
+
+    // deleting in one namespace leaves the same key in the others
+    assert!(s.namespace("tenant-a").unwrap().del(b"user:1").unwrap());
+    assert!(!s.namespace("tenant-b").unwrap().del(b"user:1").unwrap());
+    assert_eq!(s.get(b"user:1"), Some(&b"default"[..]));
+    assert_eq!(s.namespaces(), ["default", "tenant-a", "tenant-b"]);
+
+    // looking at a namespace doesn't create it
+    assert_eq!(get(&mut s, "nobody", "user:1"), None);
+    assert_eq!(s.namespaces(), ["default", "tenant-a", "tenant-b"]);
+
+    for bad in ["", "a/b", "with space", &"x".repeat(256)] {
+        assert!(s.namespace(bad).is_err(), "{bad:?}");
+    }
+}
+
+#[test]
+fn batches_can_span_namespaces() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+
+    let mut batch = WriteBatch::new();
+    batch.set(b"k", b"default");
+    batch.set_in("a", b"k", b"a");
+    batch.del_in("b", b"k");
+    assert_eq!(s.write(batch).unwrap(), [false, false, false]);
+    assert_eq!(s.stats().commits, 1);
+    assert_eq!(get(&mut s, "a", "k").as_deref(), Some(&b"a"[..]));
+
+    // through a namespace every op goes to it, whatever it was added with
+    let mut batch = WriteBatch::new();
+    batch.set(b"k", b"b");
+    batch.set_in("a", b"other", b"b");
+    assert_eq!(s.namespace("b").unwrap().write(batch).unwrap(), [false, false]);
+    assert_eq!(keys(&mut s, "b"), ["k", "other"]);
+    assert_eq!(keys(&mut s, "a"), ["k"]);
+    let mut batch = WriteBatch::new();
+    batch.set_in("a", b"back", b"home");
+    s.namespace("default").unwrap().write(batch).unwrap();
+    assert_eq!(s.get(b"back"), Some(&b"home"[..]));
+
+    // a bad name fails the whole batch before anything is written
+    let mut batch = WriteBatch::new();
+    batch.set(b"x", b"1");
+    batch.set_in("not valid", b"x", b"1");
+    assert!(s.write(batch).is_err());
+    assert_eq!(s.get(b"x"), None);
+}
+
+#[test]
+fn replay_and_snapshots_cover_every_namespace() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    // a store that never used namespaces keeps the old snapshot format
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"plain", b"1").unwrap();
+    let meta = s.create_snapshot().unwrap();
+    assert_eq!(snapshot_magic(&meta.snapshot_path), "KVST");
+
+    for i in 0..3000 {
+        let ns = ["default", "users", "orders"][i % 3];
+        s.namespace(ns).unwrap().set(format!("key:{i:05}").as_bytes(), &[b'v'; 100]).unwrap();
+    }
+    // replayed from the log alone
+    drop(s);
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(keys(&mut s, "users").len(), 1000);
+
+    let meta = s.create_snapshot().unwrap();
+    assert_eq!(snapshot_magic(&meta.snapshot_path), "KVSN");
+    s.namespace("users").unwrap().del(b"key:00001").unwrap();
+    s.namespace("orders").unwrap().set(b"tail", b"after the snapshot").unwrap();
+    drop(s);
+
+    // the snapshot plus the tail
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(keys(&mut s, "default").len(), 1001);
+    assert_eq!(keys(&mut s, "users").len(), 999);
+    assert_eq!(keys(&mut s, "orders").len(), 1001);
+    assert_eq!(get(&mut s, "orders", "tail").as_deref(), Some(&b"after the snapshot"[..]));
+    assert_eq!(get(&mut s, "users", "key:00002"), None);
+    assert_eq!(s.get(b"plain"), Some(&b"1"[..]));
+    let last_seq = s.last_seq();
+    drop(s);
+
+    let report = verify_data_dir(&log).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.snapshot_entries, 3001);
+
+    let dest = tempfile::tempdir().unwrap();
+    restore_to_point(&log, dest.path(), RestoreTarget::Seq(last_seq)).unwrap();
+    let mut restored = Store::open(dest.path().join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(keys(&mut restored, "users").len(), 999);
+    assert_eq!(get(&mut restored, "orders", "tail").as_deref(), Some(&b"after the snapshot"[..]));
+}
+
+#[test]
+fn inspect_names_the_namespace() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.namespace("users").unwrap().set(b"b", b"2").unwrap();
+    let meta = s.create_snapshot().unwrap();
+    s.namespace("users").unwrap().del(b"b").unwrap();
+    drop(s);
+
+    let lines = |path: &Path| {
+        let mut lines = Vec::new();
+        inspect_file(path, 0, &Default::default(), None, |entry| {
+            lines.push(entry.to_text());
+            Ok(())
+        }).unwrap();
+        lines
+    };
+    let snapshot = lines(&meta.snapshot_path);
+    assert!(snapshot[0].ends_with(r#"op=ENTRY key="a" val_len=1 val="1""#), "{snapshot:?}");
+    assert!(snapshot[1].contains(r#"op=ENTRY ns=users key="b""#), "{snapshot:?}");
+    let log = lines(&log);
+    assert!(log[0].contains("op=DEL(4)") && log[0].contains(r#"ns=users key="b""#), "{log:?}");
+}
+
+#[test]
+fn namespaces_get_their_own_memory_budgets() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let opts = || StoreOptions {
+        namespaces: HashMap::from([(
+            "cache".to_string(),
+            NamespaceOptions { max_memory: Some(1000), eviction: Some(EvictionPolicy::OldestWrite), ..Default::default() },
+        )]),
+        ..Default::default()
+    };
+    let mut s = Store::open(&log, opts()).unwrap();
+
+    // 10 bytes of key and 90 of value apiece
+    for i in 0..30 {
+        s.set(format!("keep:{i:05}").as_bytes(), &[b'v'; 90]).unwrap();
+        s.namespace("cache").unwrap().set(format!("item:{i:05}").as_bytes(), &[b'v'; 90]).unwrap();
+    }
+    assert_eq!(keys(&mut s, "default").len(), 30);
+    let cached = keys(&mut s, "cache");
+    assert_eq!(cached.len(), 10);
+    assert_eq!(cached[0], "item:00020");
+    assert_eq!(s.stats().evictions, 20);
+    assert_eq!(s.stats().memory_bytes, 40 * 100);
+    drop(s);
+
+    // evicted for good, and the budget still holds after a reopen
+    let mut s = Store::open(&log, opts()).unwrap();
+    assert_eq!(keys(&mut s, "cache"), cached);
+    s.namespace("cache").unwrap().set(b"item:99999", &[b'v'; 90]).unwrap();
+    assert_eq!(keys(&mut s, "cache").len(), 10);
+    assert!(get(&mut s, "cache", "item:99999").is_some());
+    assert_eq!(keys(&mut s, "default").len(), 30);
+
+    let bad = StoreOptions {
+        namespaces: HashMap::from([("no spaces".to_string(), NamespaceOptions::default())]),
+        ..Default::default()
+    };
+    assert!(Store::open(&log, bad).is_err());
+}
+
+#[test]
+fn namespace_compression_applies_to_its_own_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let opts = || StoreOptions {
+        namespaces: HashMap::from([(
+            "docs".to_string(),
+            NamespaceOptions { compression: Some(Compression::Zstd(3)), ..Default::default() },
+        )]),
+        ..Default::default()
+    };
+    let mut s = Store::open(&log, opts()).unwrap();
+    let doc = br#"{"title":"compressible","body":"the same words again and again"}"#.repeat(50);
+
+    s.set(b"plain", &doc).unwrap();
+    let stats = s.stats();
+    assert_eq!(stats.stored_value_bytes, stats.value_bytes);
+    s.namespace("docs").unwrap().set(b"doc", &doc).unwrap();
+    let stats = s.stats();
+    assert!(stats.stored_value_bytes < stats.value_bytes - doc.len() as u64 / 2, "{stats:?}");
+    drop(s);
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(get(&mut s, "docs", "doc"), Some(doc));
+}
+
+#[test]
+fn namespaced_records_survive_encryption_and_preallocation() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let opts = || StoreOptions {
+        encryption: Some(EncryptionKey::from_bytes(&[9; 32])),
+        preallocate: Some(64 * 1024),
+        ..Default::default()
+    };
+
+    let mut s = Store::open(&log, opts()).unwrap();
+    s.namespace("secrets").unwrap().set(b"token", b"hunter2").unwrap();
+    // chunked: bigger than a log chunk
+    let big = vec![7u8; 600 * 1024];
+    s.namespace("blobs").unwrap().set(b"big", &big).unwrap();
+    drop(s);
+    assert!(!fs::read(&log).unwrap().windows(7).any(|w| w == b"secrets"));
+
+    let mut s = Store::open(&log, opts()).unwrap();
+    assert_eq!(get(&mut s, "secrets", "token").as_deref(), Some(&b"hunter2"[..]));
+    assert_eq!(get(&mut s, "blobs", "big"), Some(big.clone()));
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    let mut s = Store::open(&log, opts()).unwrap();
+    assert_eq!(get(&mut s, "blobs", "big"), Some(big));
+    assert_eq!(s.get(b"token"), None);
+}
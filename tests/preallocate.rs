@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::config::StoreOptions;
+use kvs::inspect::inspect_file;
+use kvs::store::Store;
+use kvs::vfs::{FaultFs, MemFs};
+use kvs::verify::verify_data_dir;
+
+const SEGMENT: u64 = 64 * 1024;
+
+fn preallocated() -> StoreOptions {
+    StoreOptions { preallocate: Some(SEGMENT), ..Default::default() }
+}
+
+fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).unwrap().len()
+}
+
+#[test]
+fn log_grows_a_segment_at_a_time_and_replays_to_its_logical_end() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    s.del(b"a").unwrap();
+    assert_eq!(file_len(&log), SEGMENT);
+    drop(s);
+
+    // zeros past the last record are the end of the log, not corruption
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    assert_eq!(s.get(b"a"), None);
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+    assert_eq!(file_len(&log), SEGMENT);
+    assert!(verify_data_dir(&log).unwrap().is_ok());
+
+    // appends pick up at the logical end and spill into a second segment
+    for i in 0..100u32 {
+        s.set(format!("key{i}").as_bytes(), &[b'v'; 1000]).unwrap();
+    }
+    assert_eq!(file_len(&log), 2 * SEGMENT);
+    drop(s);
+
+    let s = Store::open(&log, preallocated()).unwrap();
+    assert_eq!(s.scan_prefix(b"key").len(), 100);
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+}
+
+#[test]
+fn snapshot_and_unpreallocated_open_drop_the_zeros() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+    assert_eq!(file_len(&log), SEGMENT);
+
+    let s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert!(file_len(&log) < 100, "{}", file_len(&log));
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+}
+
+#[test]
+fn torn_record_in_preallocated_space_is_truncated() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+
+    // keep the first record and the first half of the second; the rest reads as zeros
+    let mut bytes = fs::read(&log).unwrap();
+    let first_end = bytes.iter().rposition(|&b| b == b'1').unwrap() + 1;
+    let second_end = bytes.iter().rposition(|&b| b == b'2').unwrap() + 1;
+    let torn = first_end + (second_end - first_end) / 2;
+    bytes[torn..second_end].fill(0);
+    fs::write(&log, &bytes).unwrap();
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), None);
+    s.set(b"c", b"3").unwrap();
+    drop(s);
+
+    let s = Store::open(&log, preallocated()).unwrap();
+    assert_eq!(s.get(b"c"), Some(&b"3"[..]));
+}
+
+#[test]
+fn damaged_record_before_the_end_is_still_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+
+    let mut bytes = fs::read(&log).unwrap();
+    let first_val = bytes.iter().position(|&b| b == b'1').unwrap();
+    bytes[first_val] = b'9';
+    fs::write(&log, &bytes).unwrap();
+
+    let err = Store::open(&log, preallocated()).err().unwrap();
+    assert!(err.is_corrupt_log() && err.to_string().contains("checksum"), "{err}");
+}
+
+#[test]
+fn zeros_after_an_unpreallocated_log_are_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    drop(s);
+    let mut bytes = fs::read(&log).unwrap();
+    bytes.extend_from_slice(&[0; 100]);
+    fs::write(&log, bytes).unwrap();
+
+    let err = Store::open(&log, StoreOptions::default()).err().unwrap();
+    assert!(err.is_corrupt_log(), "{err}");
+}
+
+#[test]
+fn failed_write_keeps_the_preallocated_segment() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    let opts = || StoreOptions { vfs: Arc::new(fs.clone()), ..preallocated() };
+    let log = Path::new("/db/data.log");
+
+    let mut s = Store::open(log, opts()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    assert_eq!(mem.read(log).unwrap().len() as u64, SEGMENT);
+
+    // room to take the first segment back, not to add a second one
+    fs.set_space(Some(SEGMENT - 10));
+    assert!(s.set(b"big", &[b'v'; 70 * 1024]).is_err());
+    assert_eq!(mem.read(log).unwrap().len() as u64, SEGMENT);
+
+    fs.heal();
+    s.set(b"b", b"2").unwrap();
+    assert_eq!(mem.read(log).unwrap().len() as u64, SEGMENT);
+    drop(s);
+    let s = Store::open(log, opts()).unwrap();
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+    assert_eq!(s.get(b"big"), None);
+}
+
+#[test]
+fn preallocating_an_existing_log_starts_a_fresh_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    drop(s);
+
+    // the unframed records go into a snapshot rather than having zeros appended after them
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+    assert_eq!(file_len(&log), SEGMENT);
+    let s = Store::open(&log, preallocated()).unwrap();
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+    drop(s);
+    assert!(verify_data_dir(&log).unwrap().is_ok());
+
+    // and back off again, then on again
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"c", b"3").unwrap();
+    drop(s);
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"d", b"4").unwrap();
+    drop(s);
+    let s = Store::open(&log, preallocated()).unwrap();
+    for (key, val) in [(b"a", b"1"), (b"b", b"2"), (b"c", b"3"), (b"d", b"4")] {
+        assert_eq!(s.get(key), Some(&val[..]));
+    }
+}
+
+#[test]
+fn inspect_stops_at_the_zeros_without_calling_them_torn() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, preallocated()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+
+    let summary = inspect_file(&log, 0, &Default::default(), None, |_| Ok(())).unwrap();
+    assert_eq!((summary.entries, summary.torn), (2, None));
+
+    // a record cut short by the zeros still is
+    let mut bytes = fs::read(&log).unwrap();
+    let second_end = bytes.iter().rposition(|&b| b == b'2').unwrap() + 1;
+    bytes[second_end - 3..second_end].fill(0);
+    fs::write(&log, &bytes).unwrap();
+    let summary = inspect_file(&log, 0, &Default::default(), None, |_| Ok(())).unwrap();
+    assert_eq!(summary.entries, 1);
+    assert!(summary.torn.is_some());
+}
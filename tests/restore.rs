@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use kvs::config::StoreOptions;
+use kvs::restore::{restore_to_point, RestoreTarget};
+use kvs::store::Store;
+
+fn retaining() -> StoreOptions {
+    StoreOptions { retain_history: true, ..StoreOptions::default() }
+}
+
+fn open_restored(dir: &Path) -> Store {
+    Store::open(dir.join("data.log"), StoreOptions::default()).unwrap()
+}
+
+#[test]
+fn restore_to_seq_across_snapshots() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+
+    {
+        let mut s = Store::open(&log, retaining()).unwrap();
+        s.set(b"a", b"1").unwrap(); // 1
+        s.set(b"b", b"2").unwrap(); // 2
+        s.create_snapshot().unwrap();
+        s.set(b"a", b"bad").unwrap(); // 3
+        s.del(b"b").unwrap(); // 4
+        s.create_snapshot().unwrap();
+        s.set(b"c", b"3").unwrap(); // 5
+    }
+
+    let report = restore_to_point(&log, &dest.path().join("at2"), RestoreTarget::Seq(2)).unwrap();
+    assert_eq!(report.last_seq, 2);
+    let s = open_restored(&dest.path().join("at2"));
+    assert_eq!(s.get(b"a").unwrap(), b"1");
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+    assert!(s.get(b"c").is_none());
+
+    restore_to_point(&log, &dest.path().join("at3"), RestoreTarget::Seq(3)).unwrap();
+    let mut s = open_restored(&dest.path().join("at3"));
+    assert_eq!(s.get(b"a").unwrap(), b"bad");
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+
+    // the restored store continues the sequence where the restore stopped
+    assert_eq!(s.last_seq(), 3);
+    s.set(b"d", b"4").unwrap();
+    assert_eq!(s.last_seq(), 4);
+
+    restore_to_point(&log, &dest.path().join("at5"), RestoreTarget::Seq(5)).unwrap();
+    let s = open_restored(&dest.path().join("at5"));
+    assert!(s.get(b"b").is_none());
+    assert_eq!(s.get(b"c").unwrap(), b"3");
+
+    assert!(restore_to_point(&log, &dest.path().join("at9"), RestoreTarget::Seq(9)).is_err());
+}
+
+#[test]
+fn restore_to_time() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+
+    let cutoff;
+    {
+        let mut s = Store::open(&log, retaining()).unwrap();
+        s.set(b"k", b"before").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        cutoff = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        s.set(b"k", b"after").unwrap();
+    }
+
+    let report = restore_to_point(&log, dest.path(), RestoreTarget::Time(cutoff)).unwrap();
+    assert_eq!(report.last_seq, 1);
+    assert_eq!(open_restored(dest.path()).get(b"k").unwrap(), b"before");
+}
+
+#[test]
+fn restore_without_retained_history_reports_gap() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+
+    {
+        let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+        s.set(b"a", b"1").unwrap();
+        s.set(b"a", b"2").unwrap();
+        s.create_snapshot().unwrap();
+        s.set(b"a", b"3").unwrap();
+    }
+
+    // seq 1 lived only in the deleted log
+    let err = restore_to_point(&log, dest.path(), RestoreTarget::Seq(1)).unwrap_err();
+    assert!(err.to_string().contains("history gap"));
+
+    restore_to_point(&log, dest.path(), RestoreTarget::Seq(3)).unwrap();
+    assert_eq!(open_restored(dest.path()).get(b"a").unwrap(), b"3");
+}
+
+#[test]
+fn restore_to_time_uses_snapshots_the_log_no_longer_covers() {
+    let src = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    let log = src.path().join("data.log");
+    let now = || std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    let before_write = now() - 1000;
+    {
+        let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+        s.set(b"a", b"1").unwrap();
+        // the write's record goes with the rotated log
+        s.create_snapshot().unwrap();
+    }
+
+    let report = restore_to_point(&log, &dest.path().join("later"), RestoreTarget::Time(now() + 10_000)).unwrap();
+    assert_eq!(report.last_seq, 1);
+    assert_eq!(open_restored(&dest.path().join("later")).get(b"a").unwrap(), b"1");
+
+    // before the snapshot nothing says which writes had happened yet; that's an error, not an empty store
+    let err = restore_to_point(&log, &dest.path().join("earlier"), RestoreTarget::Time(before_write)).unwrap_err();
+    assert!(err.to_string().contains("no write at or before"), "{err}");
+    assert!(!dest.path().join("earlier").exists());
+}
@@ -0,0 +1,286 @@
+use std::sync::mpsc;
+
+use kvs::config::{Durability, ServerOptions, StoreOptions, WriteOptions};
+use kvs::server::{run_server_with, StoreActor, StoreHandle, StoreMessage};
+use kvs::store::{Store, DEFAULT_NAMESPACE};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+
+fn fsync_always() -> StoreOptions {
+    StoreOptions { durability: Durability::FsyncAlways, ..Default::default() }
+}
+
+fn send_set(sender: &mpsc::Sender<StoreMessage>, key: &[u8], value: &[u8]) -> oneshot::Receiver<kvs::error::Result<()>> {
+    let (tx, rx) = oneshot::channel();
+    sender.send(StoreMessage::Set { namespace: DEFAULT_NAMESPACE.into(), key: key.to_vec(), value: value.to_vec(), opts: WriteOptions::default(), respond_to: tx }).unwrap();
+    rx
+}
+
+fn send_del(sender: &mpsc::Sender<StoreMessage>, key: &[u8]) -> oneshot::Receiver<kvs::error::Result<bool>> {
+    let (tx, rx) = oneshot::channel();
+    sender.send(StoreMessage::Del { namespace: DEFAULT_NAMESPACE.into(), key: key.to_vec(), opts: WriteOptions::default(), respond_to: tx }).unwrap();
+    rx
+}
+
+// Runs `store` behind an actor and a server on a free local port; returns the server's
+// address and a handle to the same actor
+fn serve(store: Store, opts: ServerOptions) -> (String, StoreHandle) {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || StoreActor::new(receiver, store).run());
+
+    let addr = {
+        let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let handle = StoreHandle::new(sender);
+    let (server_addr, server_handle) = (addr.clone(), handle.clone());
+    tokio::spawn(async move { run_server_with(&server_addr, server_handle, opts).await });
+    (addr, handle)
+}
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    // retries until the server is listening
+    async fn connect(addr: &str) -> Client {
+        let stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        let (reader, writer) = stream.into_split();
+        Client { reader: BufReader::new(reader), writer }
+    }
+
+    // sends one command line and returns the first line of the reply
+    async fn request(&mut self, command: &str) -> String {
+        self.writer.write_all(command.as_bytes()).await.unwrap();
+        self.read_line().await
+    }
+
+    async fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).await.unwrap();
+        line
+    }
+}
+
+#[test]
+fn actor_group_commits_queued_writes() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut store = Store::open(&log, fsync_always()).unwrap();
+    store.set(b"a", b"old").unwrap();
+    assert_eq!(store.stats().syncs, 1);
+
+    // queue everything before the actor starts so it all lands in the first drain
+    let (sender, receiver) = mpsc::channel();
+    let sets: Vec<_> = (0..10)
+        .map(|i| send_set(&sender, format!("k{i}").as_bytes(), b"v"))
+        .collect();
+    let del_a = send_del(&sender, b"a");
+    let del_missing = send_del(&sender, b"missing");
+    let set_b = send_set(&sender, b"b", b"1");
+    let del_b = send_del(&sender, b"b");
+    let invalid = send_set(&sender, b"", b"v");
+    let (tx, get_k3) = oneshot::channel();
+    sender.send(StoreMessage::Get { namespace: DEFAULT_NAMESPACE.into(), key: b"k3".to_vec(), respond_to: tx }).unwrap();
+    let set_after_get = send_set(&sender, b"k10", b"v");
+    drop(sender);
+
+    let store = StoreActor::new(receiver, store).run();
+
+    for rx in sets {
+        rx.blocking_recv().unwrap().unwrap();
+    }
+    assert!(del_a.blocking_recv().unwrap().unwrap());
+    assert!(!del_missing.blocking_recv().unwrap().unwrap());
+    set_b.blocking_recv().unwrap().unwrap();
+    // the del sees the set queued just before it in the same group
+    assert!(del_b.blocking_recv().unwrap().unwrap());
+    assert!(invalid.blocking_recv().unwrap().is_err());
+    assert_eq!(get_k3.blocking_recv().unwrap().unwrap().as_deref(), Some(&b"v"[..]));
+    set_after_get.blocking_recv().unwrap().unwrap();
+
+    // one fsync for the 14 writes before the get, one for the write after it
+    let stats = store.stats();
+    assert_eq!(stats.writes, 1 + 14 + 1);
+    assert_eq!(stats.syncs, 1 + 2);
+
+    drop(store);
+    let store = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(store.get(b"a"), None);
+    assert_eq!(store.get(b"b"), None);
+    assert_eq!(store.scan_prefix_str(Some("k")).len(), 11);
+}
+
+#[test]
+fn sync_request_upgrades_the_whole_group() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let store = Store::open(&log, StoreOptions::default()).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let plain = send_set(&sender, b"a", b"1");
+    let (tx, synced) = oneshot::channel();
+    sender.send(StoreMessage::Set {
+        namespace: DEFAULT_NAMESPACE.into(),
+        key: b"payment:1".to_vec(),
+        value: b"captured".to_vec(),
+        opts: WriteOptions::sync(),
+        respond_to: tx,
+    }).unwrap();
+    drop(sender);
+
+    let store = StoreActor::new(receiver, store).run();
+    plain.blocking_recv().unwrap().unwrap();
+    synced.blocking_recv().unwrap().unwrap();
+
+    let stats = store.stats();
+    assert_eq!(stats.commits, 1);
+    assert_eq!(stats.syncs, 1);
+}
+
+#[tokio::test]
+async fn stream_commands_carry_raw_values() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    let (addr, _) = serve(store, ServerOptions::default());
+    let mut client = Client::connect(&addr).await;
+
+    // binary, with newlines, and bigger than a chunk
+    let value: Vec<u8> = (0..600 * 1024).map(|i| (i % 256) as u8).collect();
+    client.writer.write_all(format!("SETSTREAM blob {} SYNC\n", value.len()).as_bytes()).await.unwrap();
+    client.writer.write_all(&value).await.unwrap();
+    assert_eq!(client.read_line().await, "OK\n");
+
+    // over the limit: refused, and the body is skipped rather than read as commands
+    client.writer.write_all(format!("SETSTREAM big {}\n", 2 * 1024 * 1024).as_bytes()).await.unwrap();
+    client.writer.write_all(&vec![b'\n'; 2 * 1024 * 1024]).await.unwrap();
+    let line = client.read_line().await;
+    assert!(line.starts_with("ERROR: value too large"), "{line}");
+
+    assert_eq!(client.request("GETSTREAM blob\n").await, format!("VALUE {}\n", value.len()));
+    let mut got = vec![0u8; value.len() + 1];
+    client.reader.read_exact(&mut got).await.unwrap();
+    assert_eq!(got.pop(), Some(b'\n'));
+    assert_eq!(got, value);
+
+    assert_eq!(client.request("GETSTREAM big\n").await, "(nil)\n");
+}
+
+#[tokio::test]
+async fn select_switches_the_connections_namespace() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    let (addr, handle) = serve(store, ServerOptions::default());
+    let mut client = Client::connect(&addr).await;
+
+    assert_eq!(client.request("SET user:1 default\n").await, "OK\n");
+    assert_eq!(client.request("SELECT tenant-a\n").await, "OK\n");
+    assert_eq!(client.request("GET user:1\n").await, "(nil)\n");
+    assert_eq!(client.request("SET user:1 alice\n").await, "OK\n");
+    assert_eq!(client.request("USE tenant-b\n").await, "OK\n");
+    assert_eq!(client.request("DEL user:1\n").await, "0\n");
+    let reply = client.request("SELECT no/slashes\n").await;
+    assert!(reply.starts_with("ERROR:") && reply.contains("invalid namespace name"), "{reply}");
+    // a bad name leaves the connection where it was
+    assert_eq!(client.request("SET user:1 bob\n").await, "OK\n");
+    assert_eq!(client.request("SELECT default\n").await, "OK\n");
+    assert_eq!(client.request("GET user:1\n").await, "default\n");
+
+    // handles switch the same way
+    let get = async |handle: StoreHandle| handle.get(b"user:1".to_vec()).await.unwrap();
+    assert_eq!(get(handle.in_namespace("tenant-a").unwrap()).await.as_deref(), Some(&b"alice"[..]));
+    assert_eq!(get(handle.in_namespace("tenant-b").unwrap()).await.as_deref(), Some(&b"bob"[..]));
+    assert_eq!(get(handle.clone()).await.as_deref(), Some(&b"default"[..]));
+    assert!(handle.in_namespace("").is_err());
+}
+
+#[tokio::test]
+async fn backup_only_writes_inside_the_backup_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let backups = tempfile::tempdir().unwrap();
+    let mut store = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    store.set(b"a", b"1").unwrap();
+    let (addr, _) = serve(store, ServerOptions { backup_dir: Some(backups.path().to_path_buf()) });
+    let mut client = Client::connect(&addr).await;
+
+    let reply = client.request("BACKUP nightly.kvsbak\n").await;
+    assert!(reply.starts_with("OK seq 1 "), "{reply}");
+    assert!(backups.path().join("nightly.kvsbak").exists());
+
+    let outside = dir.path().join("outside.kvsbak");
+    for name in [outside.to_str().unwrap(), "../outside.kvsbak", "sub/../../outside.kvsbak"] {
+        let reply = client.request(&format!("BACKUP {name}\n")).await;
+        assert!(reply.starts_with("ERROR:") && reply.contains("inside the backup dir"), "{reply}");
+    }
+    assert!(!outside.exists());
+}
+
+#[tokio::test]
+async fn backup_is_refused_without_a_backup_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    let (addr, _) = serve(store, ServerOptions::default());
+    let mut client = Client::connect(&addr).await;
+
+    let line = client.request("BACKUP backup.kvsbak\n").await;
+    assert!(line.starts_with("ERROR:") && line.contains("--backup-dir"), "{line}");
+}
+
+#[tokio::test]
+async fn values_ending_in_sync_are_stored_as_written() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    let (addr, handle) = serve(store, ServerOptions::default());
+    let mut client = Client::connect(&addr).await;
+
+    assert_eq!(client.request("SET status stay in SYNC\n").await, "OK\n");
+    assert_eq!(client.request("SET word SYNC\n").await, "OK\n");
+    assert_eq!(client.request("GET status\n").await, "stay in SYNC\n");
+    assert_eq!(client.request("GET word\n").await, "SYNC\n");
+    assert_eq!(handle.stats().await.unwrap().syncs, 0);
+
+    assert_eq!(client.request("SETSYNC payment:1 captured in SYNC\n").await, "OK\n");
+    assert_eq!(client.request("GET payment:1\n").await, "captured in SYNC\n");
+    assert_eq!(handle.stats().await.unwrap().syncs, 1);
+    assert_eq!(client.request("DEL word SYNC\n").await, "1\n");
+    assert_eq!(handle.stats().await.unwrap().syncs, 2);
+}
+
+#[tokio::test]
+async fn concurrent_sets_from_clients_share_commits() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = Store::open(dir.path().join("data.log"), fsync_always()).unwrap();
+    let (addr, _) = serve(store, ServerOptions::default());
+
+    let clients: Vec<_> = (0..16)
+        .map(|n| {
+            let addr = addr.clone();
+            tokio::spawn(async move {
+                let mut client = Client::connect(&addr).await;
+                for i in 0..50 {
+                    assert_eq!(client.request(&format!("SET key:{n}:{i} v\n")).await, "OK\n");
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    // writes that queued up behind another client's fsync went out with the next one
+    let mut client = Client::connect(&addr).await;
+    assert_eq!(client.request("STATS\n").await, format!("writes {}\n", 16 * 50));
+    let commits: u64 = client.read_line().await.trim().strip_prefix("commits ").unwrap().parse().unwrap();
+    assert!(commits < 16 * 50, "{commits} commits");
+    assert_eq!(client.read_line().await, format!("syncs {commits}\n"));
+}
@@ -0,0 +1,110 @@
+use std::fs;
+
+use kvs::config::{Compression, Limits, RecoveryPolicy, StoreOptions};
+use kvs::sst::SstReader;
+use kvs::store::Store;
+use kvs::verify::verify_data_dir;
+
+fn key(i: usize) -> String {
+    format!("user:{i:05}")
+}
+
+fn val(i: usize) -> String {
+    format!(r#"{{"id":{i},"name":"user {i}","email":"user{i}@example.com"}}"#).repeat(4)
+}
+
+#[test]
+fn sorted_snapshot_is_looked_up_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let opts = StoreOptions { compression: Compression::Zstd(3), ..Default::default() };
+    let mut s = Store::open(dir.path().join("data.log"), opts).unwrap();
+    // insertion order doesn't matter, the snapshot is sorted
+    for i in (0..5000).rev() {
+        s.set(key(i).as_bytes(), val(i).as_bytes()).unwrap();
+    }
+    let meta = s.create_snapshot().unwrap();
+    drop(s);
+
+    let mut reader = SstReader::open(&meta.snapshot_path, &Limits::default()).unwrap();
+    assert_eq!(reader.entries(), 5000);
+    assert!(reader.blocks() > 1, "{} blocks", reader.blocks());
+    // compressed blocks: far smaller than the values alone
+    let raw: usize = (0..5000).map(|i| val(i).len()).sum();
+    assert!(fs::metadata(&meta.snapshot_path).unwrap().len() < raw as u64 / 4);
+
+    for i in [0, 1, 2500, 4999] {
+        assert_eq!(reader.get(key(i).as_bytes()).unwrap().unwrap(), val(i).as_bytes());
+    }
+    assert_eq!(reader.get(b"a").unwrap(), None);
+    assert_eq!(reader.get(b"user:02500x").unwrap(), None);
+    assert_eq!(reader.get(b"zzz").unwrap(), None);
+
+    let mut keys = Vec::new();
+    reader.for_each(|k, _| keys.push(k)).unwrap();
+    assert_eq!(keys.len(), 5000);
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+
+    let s = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(s.get(key(42).as_bytes()).unwrap(), val(42).as_bytes());
+}
+
+#[test]
+fn damaged_block_is_found_without_loading_and_salvaged() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    for i in 0..5000 {
+        s.set(key(i).as_bytes(), val(i).as_bytes()).unwrap();
+    }
+    let meta = s.create_snapshot().unwrap();
+    drop(s);
+    assert!(verify_data_dir(&log).unwrap().is_ok());
+
+    // flip a byte somewhere in the middle of the data blocks
+    let mut bytes = fs::read(&meta.snapshot_path).unwrap();
+    let mid = bytes.len() / 2;
+    bytes[mid] ^= 0xff;
+    fs::write(&meta.snapshot_path, bytes).unwrap();
+
+    let report = verify_data_dir(&log).unwrap();
+    assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+    assert!(report.problems[0].msg.contains("checksum mismatch"), "{}", report.problems[0]);
+
+    assert!(Store::open(&log, StoreOptions::default()).is_err());
+    let s = Store::open(&log, StoreOptions { recovery: RecoveryPolicy::Salvage, ..Default::default() }).unwrap();
+    let report = s.salvage_report().unwrap();
+    assert_eq!(report.skipped_regions.len(), 1);
+    // only the damaged block is lost
+    let kept = s.scan_prefix_str(Some("user:")).len();
+    assert!(kept < 5000 && kept > 4500, "{kept} kept");
+    assert_eq!(s.get(key(0).as_bytes()).unwrap(), val(0).as_bytes());
+    assert_eq!(s.get(key(4999).as_bytes()).unwrap(), val(4999).as_bytes());
+}
+
+#[test]
+fn footer_with_an_index_shorter_than_a_block_header_is_corruption() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    let meta = s.create_snapshot().unwrap();
+    drop(s);
+
+    // magic, a 5-byte "index" and a footer whose crc is good
+    let mut bytes = b"\0\0\0\0KVST".to_vec();
+    bytes.extend_from_slice(&[0; 5]);
+    let mut footer = Vec::new();
+    for field in [8u64, 5, 0, 0] {
+        footer.extend_from_slice(&field.to_le_bytes());
+    }
+    footer.extend_from_slice(&crc32fast::hash(&footer).to_le_bytes());
+    footer.extend_from_slice(b"KVST");
+    bytes.extend_from_slice(&footer);
+    assert_eq!(bytes.len(), 53);
+    fs::write(&meta.snapshot_path, &bytes).unwrap();
+
+    let err = SstReader::open(&meta.snapshot_path, &Limits::default()).err().unwrap();
+    assert!(err.is_corrupt_log(), "{err}");
+    let err = Store::open(&log, StoreOptions::default()).err().unwrap();
+    assert!(err.is_corrupt_log(), "{err}");
+}
@@ -1,8 +1,9 @@
 use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use kvs::config::StoreOptions;
+use kvs::config::{Compression, Durability, RecoveryPolicy, StoreOptions, WriteOptions};
 use kvs::store::Store;
 
 fn fresh_log_path(test_name: &str) -> PathBuf {
@@ -147,3 +148,415 @@ fn scan_prefix_returns_sorted_matches() {
     let _ = fs::remove_file(path);
 
 }
+
+#[test]
+fn sequence_numbers_survive_snapshot_and_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+
+    {
+        let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+        s.set(b"a", b"1").unwrap();
+        s.set(b"b", b"2").unwrap();
+        assert_eq!(s.last_seq(), 2);
+
+        let meta = s.create_snapshot().unwrap();
+        assert_eq!(meta.last_seq, 2);
+
+        s.del(b"a").unwrap();
+        assert_eq!(s.last_seq(), 3);
+    }
+
+    let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+    assert_eq!(s.last_seq(), 3);
+    s.set(b"c", b"3").unwrap();
+    assert_eq!(s.last_seq(), 4);
+}
+
+#[test]
+fn salvage_skips_mid_log_corruption_and_keeps_original() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+
+    let second_record_at;
+    {
+        let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+        s.set(b"a", b"1").unwrap();
+        second_record_at = fs::metadata(&path).unwrap().len() as usize;
+        s.set(b"b", b"2").unwrap();
+        s.set(b"c", b"3").unwrap();
+    }
+
+    // clobber the op code of the second record
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[second_record_at] = 0xEE;
+    fs::write(&path, &bytes).unwrap();
+
+    assert!(Store::open(&path, StoreOptions::default()).is_err_and(|e| e.is_corrupt_log()));
+
+    let opts = StoreOptions { recovery: RecoveryPolicy::Salvage, ..StoreOptions::default() };
+    {
+        let s = Store::open(&path, opts).unwrap();
+        assert_eq!(s.get(b"a").unwrap(), b"1");
+        assert!(s.get(b"b").is_none());
+        assert_eq!(s.get(b"c").unwrap(), b"3");
+
+        let report = s.salvage_report().unwrap();
+        assert_eq!(report.recovered_records, 2);
+        assert_eq!(report.lost_records, 1);
+        assert_eq!(report.skipped_regions.len(), 1);
+        assert_eq!(report.preserved.len(), 1);
+        assert_eq!(fs::read(&report.preserved[0]).unwrap(), bytes);
+    }
+
+    // the clean snapshot opens in strict mode again
+    let s = Store::open(&path, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"c").unwrap(), b"3");
+    assert_eq!(s.last_seq(), 3);
+}
+
+#[test]
+fn interval_durability_syncs_in_the_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let opts = StoreOptions {
+        durability: Durability::FsyncInterval(Duration::from_millis(20)),
+        ..Default::default()
+    };
+
+    let mut s = Store::open(&path, opts).unwrap();
+    s.set(b"a", b"1").unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while s.stats().syncs == 0 {
+        assert!(Instant::now() < deadline, "background fsync never ran");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+
+    // nothing new written, nothing more to sync
+    let syncs = s.stats().syncs;
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(s.stats().syncs, syncs);
+
+    // still syncs the new log after a snapshot rotates it
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    while s.stats().syncs == syncs {
+        assert!(Instant::now() < deadline, "background fsync stopped after rotation");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    drop(s);
+
+    let s = Store::open(&path, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+}
+
+#[test]
+fn every_n_or_interval_syncs_on_count() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let opts = StoreOptions {
+        durability: Durability::FsyncEveryNOrInterval(3, Duration::from_secs(3600)),
+        ..Default::default()
+    };
+
+    let mut s = Store::open(&path, opts).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    assert_eq!(s.stats().syncs, 0);
+    s.del(b"a").unwrap();
+    assert_eq!(s.stats().syncs, 1);
+    s.set(b"c", b"3").unwrap();
+    assert_eq!(s.stats().syncs, 1);
+}
+
+#[test]
+fn sync_write_option_upgrades_flush_durability() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+
+    let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    assert_eq!(s.stats().syncs, 0);
+
+    s.set_with(b"payment:1", b"captured", WriteOptions::sync()).unwrap();
+    assert_eq!(s.stats().syncs, 1);
+    assert!(s.del_with(b"a", WriteOptions::sync()).unwrap());
+    assert_eq!(s.stats().syncs, 2);
+
+    s.set(b"b", b"2").unwrap();
+    assert_eq!(s.stats().syncs, 2);
+    assert_eq!(s.get(b"payment:1").unwrap(), b"captured");
+}
+
+#[test]
+fn data_dir_can_be_moved_and_has_colons() {
+    let root = tempfile::tempdir().unwrap();
+    let original = root.path().join("original");
+    fs::create_dir(&original).unwrap();
+
+    let mut s = Store::open(original.join("data.log"), StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    drop(s);
+
+    let manifest = fs::read_to_string(original.join("MANIFEST")).unwrap();
+    assert!(!manifest.contains(original.to_str().unwrap()), "MANIFEST has absolute paths: {manifest}");
+
+    let moved = root.path().join("moved:with:colons");
+    fs::rename(&original, &moved).unwrap();
+
+    let mut s = Store::open(moved.join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"a").unwrap(), b"1");
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    let s = Store::open(moved.join("data.log"), StoreOptions::default()).unwrap();
+    assert_eq!(s.scan_prefix_str(None), ["a", "b"]);
+}
+
+#[test]
+fn legacy_absolute_manifest_is_migrated() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    let last_seq = s.last_seq();
+    drop(s);
+
+    // what an older build wrote, from a dir that has since been moved
+    fs::write(
+        dir.path().join("MANIFEST"),
+        "1:/old/place/snapshot-0001.snap:/old/place/data.log:1\n",
+    ).unwrap();
+
+    let s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"a").unwrap(), b"1");
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+    assert_eq!(s.last_seq(), last_seq);
+    drop(s);
+
+    let manifest = fs::read_to_string(dir.path().join("MANIFEST")).unwrap();
+    assert!(manifest.starts_with('{'), "MANIFEST not migrated: {manifest}");
+    assert!(!manifest.contains("/old/place"));
+}
+
+#[test]
+fn open_replays_and_removes_orphaned_rotated_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    s.del(b"a").unwrap();
+    drop(s);
+
+    // what an older build left behind when it died right after rotating the log
+    fs::rename(&log, dir.path().join("data.log.1700000000")).unwrap();
+    fs::write(&log, b"").unwrap();
+    fs::write(dir.path().join("snapshot-0002.tmp"), b"partial").unwrap();
+    fs::write(dir.path().join("MANIFEST.tmp"), b"{\"vers").unwrap();
+
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"a"), None);
+    assert_eq!(s.get(b"b").unwrap(), b"2");
+    assert_eq!(s.last_seq(), 3);
+    for leftover in ["data.log.1700000000", "snapshot-0002.tmp", "MANIFEST.tmp"] {
+        assert!(!dir.path().join(leftover).exists(), "{leftover} was not removed");
+    }
+
+    s.set(b"c", b"3").unwrap();
+    assert_eq!(s.last_seq(), 4);
+    drop(s);
+
+    let s = Store::open(&log, StoreOptions::default()).unwrap();
+    assert_eq!(s.scan_prefix_str(None), ["b", "c"]);
+}
+
+#[test]
+fn covered_rotated_log_is_archived_with_retain_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let opts = StoreOptions { retain_history: true, ..Default::default() };
+
+    let mut s = Store::open(&log, opts.clone()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    // the snapshot covering it is in the MANIFEST, but the rotated log wasn't archived yet
+    fs::rename(dir.path().join("archive/log-0001.log"), dir.path().join("data.log.1700000000")).unwrap();
+
+    let s = Store::open(&log, opts).unwrap();
+    assert_eq!(s.get(b"a").unwrap(), b"1");
+    assert!(!dir.path().join("data.log.1700000000").exists());
+    assert!(dir.path().join("archive/log-0001.log").exists());
+}
+
+#[test]
+fn size_limits_are_configurable_and_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let doc = vec![b'x'; 2 * 1024 * 1024];
+
+    // the defaults still apply to a new dir
+    let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+    let err = s.set(b"doc", &doc).err().unwrap().to_string();
+    assert!(err.contains("limit is 1048576 bytes"), "{err}");
+    drop(s);
+
+    let big = StoreOptions { max_val_len: Some(16 * 1024 * 1024), ..Default::default() };
+    let mut s = Store::open(&path, big).unwrap();
+    s.set(b"doc", &doc).unwrap();
+    drop(s);
+
+    // replay checks against the dir's limits, not the defaults
+    let mut s = Store::open(&path, StoreOptions::default()).unwrap();
+    assert_eq!(s.get(b"doc").unwrap().len(), doc.len());
+    assert_eq!(s.limits().max_val_len, 16 * 1024 * 1024);
+    s.create_snapshot().unwrap();
+    s.set(b"doc2", &doc).unwrap();
+    drop(s);
+
+    // lowering a limit only affects new writes
+    let small = StoreOptions { max_val_len: Some(1024), ..Default::default() };
+    let mut s = Store::open(&path, small).unwrap();
+    assert_eq!(s.get(b"doc2").unwrap().len(), doc.len());
+    assert!(s.set(b"doc3", &[0u8; 2048]).is_err());
+    drop(s);
+
+    let s = Store::open(&path, StoreOptions::default()).unwrap();
+    assert_eq!(s.scan_prefix_str(Some("doc")), ["doc", "doc2"]);
+}
+
+#[test]
+fn large_values_are_chunked_and_streamed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let opts = || StoreOptions { max_val_len: Some(8 * 1024 * 1024), ..Default::default() };
+    let doc: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|i| (i % 251) as u8).collect();
+
+    let mut s = Store::open(&path, opts()).unwrap();
+    assert_eq!(s.set_from_reader(b"doc", &doc[..], WriteOptions::default()).unwrap(), doc.len() as u64);
+    s.set(b"small", b"1").unwrap();
+    // one commit record plus 13 chunks of 256 KiB (the last one partial)
+    assert_eq!(s.last_seq(), 13 + 1 + 1);
+    drop(s);
+
+    let mut s = Store::open(&path, opts()).unwrap();
+    let mut out = Vec::new();
+    assert_eq!(s.get_to_writer(b"doc", &mut out).unwrap(), Some(doc.len() as u64));
+    assert_eq!(out, doc);
+    assert_eq!(s.get_to_writer(b"missing", &mut out).unwrap(), None);
+
+    // too large for the limit: the chunks already written are cut back off the log
+    let huge = vec![0u8; 8 * 1024 * 1024 + 1];
+    assert!(s.set_from_reader(b"huge", &huge[..], WriteOptions::default()).is_err());
+    assert_eq!(s.last_seq(), 15);
+    // a reader failing part-way does the same; neither is the disk's fault, so writes go on
+    let failing = (&doc[..1024 * 1024]).chain(FailingReader);
+    assert!(s.set_from_reader(b"broken", failing, WriteOptions::default()).is_err());
+    assert_eq!(s.last_seq(), 15);
+    assert_eq!(s.stats().failed_writes, 0);
+    // exactly one chunk's worth is a plain SET
+    assert_eq!(s.set_from_reader(b"chunk", &doc[..256 * 1024], WriteOptions::default()).unwrap(), 256 * 1024);
+    assert_eq!(s.last_seq(), 16);
+
+    // and the value survives a snapshot
+    s.create_snapshot().unwrap();
+    drop(s);
+    let s = Store::open(&path, opts()).unwrap();
+    assert_eq!(s.get(b"doc").unwrap(), &doc[..]);
+    assert_eq!(s.get(b"chunk").unwrap(), &doc[..256 * 1024]);
+    assert_eq!(s.get(b"huge"), None);
+    assert_eq!(s.get(b"broken"), None);
+}
+
+struct FailingReader;
+
+impl std::io::Read for FailingReader {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("connection reset"))
+    }
+}
+
+#[test]
+fn torn_chunked_value_is_dropped_on_replay() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let opts = || StoreOptions { max_val_len: Some(4 * 1024 * 1024), ..Default::default() };
+
+    let mut s = Store::open(&path, opts()).unwrap();
+    s.set(b"doc", &vec![1u8; 1024 * 1024]).unwrap();
+    let committed = fs::metadata(&path).unwrap().len();
+    s.set(b"doc", &vec![2u8; 1024 * 1024]).unwrap();
+    drop(s);
+
+    // crash halfway through the second value's chunks
+    let len = fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap()
+        .set_len(committed + (len - committed) / 2).unwrap();
+
+    let mut s = Store::open(&path, opts()).unwrap();
+    assert_eq!(s.get(b"doc").unwrap(), &vec![1u8; 1024 * 1024][..]);
+
+    // the leftover chunks don't leak into the next write of the key
+    s.set(b"doc", &vec![3u8; 600 * 1024]).unwrap();
+    drop(s);
+    let s = Store::open(&path, opts()).unwrap();
+    assert_eq!(s.get(b"doc").unwrap(), &vec![3u8; 600 * 1024][..]);
+}
+
+#[test]
+fn compressed_and_plain_records_mix() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.log");
+    let with = |compression| StoreOptions { compression, max_val_len: Some(4 * 1024 * 1024), ..Default::default() };
+    let json = |i: usize| format!(r#"{{"id":{i},"name":"user {i}","tags":["a","b","c"],"active":true}}"#).repeat(20);
+
+    let mut s = Store::open(&path, with(Compression::None)).unwrap();
+    s.set(b"plain", json(0).as_bytes()).unwrap();
+    assert_eq!(s.stats().compression_ratio(), 1.0);
+    drop(s);
+
+    let mut s = Store::open(&path, with(Compression::Lz4)).unwrap();
+    s.set(b"lz4", json(1).as_bytes()).unwrap();
+    // too small to gain anything, so stored as is
+    s.set(b"tiny", b"1").unwrap();
+    drop(s);
+
+    let mut s = Store::open(&path, with(Compression::Zstd(3))).unwrap();
+    s.set(b"zstd", json(2).as_bytes()).unwrap();
+    // chunked values compress chunk by chunk
+    let big = json(3).repeat(2000);
+    s.set(b"big", big.as_bytes()).unwrap();
+    let stats = s.stats();
+    assert!(stats.compression_ratio() > 5.0, "{stats:?}");
+    drop(s);
+    assert!(fs::metadata(&path).unwrap().len() < big.len() as u64 / 5);
+
+    // the codec in the options doesn't matter for reading
+    let s = Store::open(&path, with(Compression::None)).unwrap();
+    assert_eq!(s.get(b"plain").unwrap(), json(0).as_bytes());
+    assert_eq!(s.get(b"lz4").unwrap(), json(1).as_bytes());
+    assert_eq!(s.get(b"tiny").unwrap(), b"1");
+    assert_eq!(s.get(b"zstd").unwrap(), json(2).as_bytes());
+    assert_eq!(s.get(b"big").unwrap(), big.as_bytes());
+    drop(s);
+
+    // snapshots too
+    let mut s = Store::open(&path, with(Compression::Lz4)).unwrap();
+    s.create_snapshot().unwrap();
+    assert!(s.stats().stored_value_bytes < s.stats().value_bytes / 5);
+    drop(s);
+    let s = Store::open(&path, with(Compression::None)).unwrap();
+    assert_eq!(s.get(b"big").unwrap(), big.as_bytes());
+    assert_eq!(s.get(b"plain").unwrap(), json(0).as_bytes());
+}
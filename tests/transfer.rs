@@ -0,0 +1,56 @@
+use std::io::Cursor;
+
+use kvs::config::StoreOptions;
+use kvs::store::{Store, DEFAULT_NAMESPACE};
+use kvs::transfer::{export, import, DumpFormat};
+
+fn seeded(dir: &std::path::Path) -> Store {
+    let mut s = Store::open(dir.join("data.log"), StoreOptions::default()).unwrap();
+    s.set(b"user:1", b"Alice, \"the\" admin\nline two").unwrap();
+    s.set(b"user:2", b"Bob").unwrap();
+    s.set(b"bin:\xff", b"\x00\x01\xfe").unwrap();
+    s
+}
+
+#[test]
+fn export_import_roundtrip_in_both_formats() {
+    for format in [DumpFormat::Jsonl, DumpFormat::Csv] {
+        let src_dir = tempfile::tempdir().unwrap();
+        let dst_dir = tempfile::tempdir().unwrap();
+        let mut src = seeded(src_dir.path());
+
+        let mut out = Vec::new();
+        assert_eq!(export(&src.namespace(DEFAULT_NAMESPACE).unwrap(), b"", format, &mut out).unwrap(), 3);
+
+        let mut dst = Store::open(dst_dir.path().join("data.log"), StoreOptions::default()).unwrap();
+        assert_eq!(import(&mut dst.namespace(DEFAULT_NAMESPACE).unwrap(), format, Cursor::new(out), 2).unwrap(), 3);
+
+        for key in [&b"user:1"[..], b"user:2", b"bin:\xff"] {
+            assert_eq!(dst.get(key), src.get(key), "{format:?} {key:?}");
+        }
+    }
+}
+
+#[test]
+fn export_filters_by_prefix_and_marks_binary_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = seeded(dir.path());
+
+    let mut out = Vec::new();
+    export(&s.namespace(DEFAULT_NAMESPACE).unwrap(), b"user:2", DumpFormat::Jsonl, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "{\"key\":\"user:2\",\"value\":\"Bob\",\"encoding\":\"utf8\"}\n");
+
+    let mut out = Vec::new();
+    export(&s.namespace(DEFAULT_NAMESPACE).unwrap(), b"bin:", DumpFormat::Csv, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "key,value,encoding\nYmluOv8=,AAH+,base64\n");
+}
+
+#[test]
+fn import_reports_bad_rows_with_line_numbers() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut s = Store::open(dir.path().join("data.log"), StoreOptions::default()).unwrap();
+
+    let input = "{\"key\":\"a\",\"value\":\"1\"}\n{\"key\":\"b\"}\n";
+    let err = import(&mut s.namespace(DEFAULT_NAMESPACE).unwrap(), DumpFormat::Jsonl, Cursor::new(input), 10).unwrap_err();
+    assert!(err.to_string().contains("line 2"), "{err}");
+}
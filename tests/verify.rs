@@ -0,0 +1,99 @@
+use std::fs;
+
+use kvs::config::StoreOptions;
+use kvs::inspect::inspect_file;
+use kvs::store::Store;
+use kvs::verify::verify_data_dir;
+
+#[test]
+fn verify_reports_torn_tail_dangling_manifest_and_leftovers() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+
+    {
+        let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+        s.set(b"a", b"1").unwrap();
+        s.create_snapshot().unwrap();
+        s.set(b"b", b"2").unwrap();
+    }
+    assert!(verify_data_dir(&log).unwrap().is_ok());
+
+    // torn tail
+    let len = fs::metadata(&log).unwrap().len();
+    let f = fs::OpenOptions::new().write(true).open(&log).unwrap();
+    f.set_len(len - 2).unwrap();
+
+    // leftovers of an interrupted snapshot, and a MANIFEST pointing at a missing snapshot
+    fs::write(dir.path().join("data.log.1700000000"), b"").unwrap();
+    fs::write(dir.path().join("snapshot-0002.tmp"), b"").unwrap();
+    fs::remove_file(dir.path().join("snapshot-0001.snap")).unwrap();
+
+    let report = verify_data_dir(&log).unwrap();
+    let messages: Vec<String> = report.problems.iter().map(|p| p.to_string()).collect();
+    assert_eq!(report.problems.len(), 4, "{messages:#?}");
+    assert!(messages.iter().any(|m| m.contains("does not exist")));
+    assert!(messages.iter().any(|m| m.contains("torn record at offset 0")));
+    assert!(messages.iter().any(|m| m.contains("rotated log")));
+    assert!(messages.iter().any(|m| m.contains("orphaned temporary file")));
+
+    // nothing was repaired
+    assert_eq!(fs::metadata(&log).unwrap().len(), len - 2);
+}
+
+#[test]
+fn verify_reports_every_corrupt_record_and_keeps_going() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    for i in 0..10 {
+        s.set(format!("key{i}").as_bytes(), b"value").unwrap();
+    }
+    drop(s);
+
+    // an unknown op code at the start of the 3rd and the 7th record
+    let mut offsets = Vec::new();
+    inspect_file(&log, 0, &Default::default(), None, |entry| {
+        offsets.push(entry.offset as usize);
+        Ok(())
+    }).unwrap();
+    let mut bytes = fs::read(&log).unwrap();
+    bytes[offsets[2]] = 0xee;
+    bytes[offsets[6]] = 0xee;
+    fs::write(&log, bytes).unwrap();
+
+    let report = verify_data_dir(&log).unwrap();
+    let messages: Vec<String> = report.problems.iter().map(|p| p.to_string()).collect();
+    assert_eq!(report.problems.len(), 2, "{messages:#?}");
+    assert_eq!(report.problems[0].offset, Some(offsets[2] as u64));
+    assert!(messages[0].contains(&format!("next readable record at offset {}", offsets[3])), "{messages:#?}");
+    assert_eq!(report.problems[1].offset, Some(offsets[6] as u64));
+    assert_eq!(report.log_records, 8);
+    assert_eq!(report.last_seq, 10);
+}
+
+#[test]
+fn log_the_snapshot_already_covers_is_a_note_not_a_problem() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("data.log");
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.set(b"b", b"2").unwrap();
+    let before_snapshot = fs::read(&log).unwrap();
+    s.create_snapshot().unwrap();
+    drop(s);
+
+    // as if the snapshot's MANIFEST landed but the log was never rotated
+    fs::write(&log, &before_snapshot).unwrap();
+    let report = verify_data_dir(&log).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.notes.len(), 1, "{:?}", report.notes);
+    assert!(report.notes[0].msg.contains("first 2 records are already covered"), "{}", report.notes[0]);
+
+    // writes after it, from the next open, are fine too
+    let mut s = Store::open(&log, StoreOptions::default()).unwrap();
+    s.set(b"c", b"3").unwrap();
+    drop(s);
+    let report = verify_data_dir(&log).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!((report.notes.len(), report.log_records, report.last_seq), (1, 3, 3));
+}
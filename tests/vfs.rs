@@ -0,0 +1,130 @@
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use kvs::config::{Durability, StoreOptions};
+use kvs::error::StoreError;
+use kvs::store::Store;
+use kvs::vfs::{FaultFs, MemFs, Vfs};
+
+const LOG: &str = "/db/data.log";
+
+fn on(vfs: impl Vfs + 'static, durability: Durability) -> StoreOptions {
+    StoreOptions { vfs: Arc::new(vfs), durability, ..Default::default() }
+}
+
+#[test]
+fn store_runs_in_memory() {
+    let fs = MemFs::new();
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::Flush)).unwrap();
+    s.set(b"a", b"1").unwrap();
+    s.create_snapshot().unwrap();
+    s.set(b"b", b"2").unwrap();
+    s.del(b"a").unwrap();
+    drop(s);
+
+    assert!(fs.read(Path::new("/db/MANIFEST")).is_some());
+    assert!(!Path::new(LOG).exists());
+
+    let s = Store::open(LOG, on(fs, Durability::Flush)).unwrap();
+    assert_eq!(s.get(b"a"), None);
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+}
+
+#[test]
+fn crash_keeps_only_synced_writes() {
+    let fs = MemFs::new();
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    s.set(b"synced", b"1").unwrap();
+    drop(s);
+
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::Flush)).unwrap();
+    s.set(b"flushed", b"2").unwrap();
+    fs.crash();
+    drop(s);
+
+    let s = Store::open(LOG, on(fs, Durability::Flush)).unwrap();
+    assert_eq!(s.get(b"synced"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"flushed"), None);
+}
+
+#[test]
+fn crash_keeps_only_synced_dir_entries() {
+    let fs = MemFs::new();
+    let dir = Path::new("/db");
+    let (old, new, tmp) = (dir.join("old"), dir.join("new"), dir.join("tmp"));
+    fs.write(&old, b"old");
+    fs.write(&new, b"new");
+    for path in [&old, &tmp] {
+        let mut file = fs.create(path).unwrap();
+        file.write_all(b"data").unwrap();
+        file.sync_all().unwrap();
+    }
+    // the renamed-over and removed files come back, the created one is gone: none of it reached the dir
+    fs.rename(&tmp, &new).unwrap();
+    fs.remove_file(&old).unwrap();
+    fs.crash();
+    assert_eq!(fs.read(&old).as_deref(), Some(&b"data"[..]));
+    assert_eq!(fs.read(&new).as_deref(), Some(&b"new"[..]));
+    assert_eq!(fs.read(&tmp), None);
+
+    let mut file = fs.create(&tmp).unwrap();
+    file.write_all(b"data").unwrap();
+    file.sync_all().unwrap();
+    fs.rename(&tmp, &new).unwrap();
+    fs.sync_dir(dir).unwrap();
+    fs.crash();
+    assert_eq!(fs.read(&new).as_deref(), Some(&b"data"[..]));
+    assert_eq!(fs.read(&tmp), None);
+}
+
+#[test]
+fn lost_fsyncs_lose_data_on_crash() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+    fs.lose_fsyncs(true);
+
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    s.set(b"a", b"1").unwrap();
+    assert!(fs.lost_fsyncs() > 0);
+    mem.crash();
+    drop(s);
+
+    let s = Store::open(LOG, on(mem, Durability::FsyncAlways)).unwrap();
+    assert_eq!(s.get(b"a"), None);
+}
+
+#[test]
+fn torn_write_is_truncated_on_reopen() {
+    let mem = MemFs::new();
+    let fs = FaultFs::new(mem.clone());
+
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    s.set(b"a", b"1").unwrap();
+    let good_len = mem.read(Path::new(LOG)).unwrap().len();
+
+    fs.tear_after(5);
+    assert!(s.set(b"b", b"2").is_err());
+    assert!(fs.torn());
+    drop(s);
+    assert_eq!(mem.read(Path::new(LOG)).unwrap().len(), good_len + 5);
+
+    fs.heal();
+    let mut s = Store::open(LOG, on(fs, Durability::FsyncAlways)).unwrap();
+    assert_eq!(mem.read(Path::new(LOG)).unwrap().len(), good_len);
+    assert_eq!(s.get(b"a"), Some(&b"1"[..]));
+    assert_eq!(s.get(b"b"), None);
+    s.set(b"b", b"2").unwrap();
+    assert_eq!(s.get(b"b"), Some(&b"2"[..]));
+}
+
+#[test]
+fn full_disk_fails_writes_with_storage_full() {
+    let fs = FaultFs::new(MemFs::new());
+    let mut s = Store::open(LOG, on(fs.clone(), Durability::FsyncAlways)).unwrap();
+    s.set(b"a", b"1").unwrap();
+
+    fs.set_space(Some(8));
+    let err = s.set(b"b", &[b'x'; 64]).err().unwrap();
+    assert!(matches!(&err, StoreError::Io(e) if e.kind() == ErrorKind::StorageFull), "{err}");
+}